@@ -1,33 +1,86 @@
 extern crate libc;
 extern crate rinput;
 
-use std::fs;
+use std::collections::HashMap;
 use std::io::stdin;
+use std::sync::mpsc::channel;
+use std::time::Instant;
 
 use clap::Clap;
 use colored::*;
 
-use rinput::{Editor, Input};
+use rinput::{Editor, EditorOptions, Input};
 use rinput::rustbox::rustbox::{InitOptions, InputMode, OutputMode, RustBox};
 
-use crate::rmd::executor::execute_command;
+use crate::rmd::browser;
+use crate::rmd::cache::BuildCache;
+use crate::rmd::completions::{self, Shell};
+use crate::rmd::config::Config;
+use crate::rmd::document;
+use crate::rmd::executor::{RunContext, Transcript};
+use crate::rmd::export::{self, Format};
+use crate::rmd::invoke;
+use crate::rmd::log;
+use crate::rmd::outline;
+use crate::rmd::runbook;
+use crate::rmd::runner;
+use crate::rmd::secrets;
+use crate::rmd::view;
+use crate::rmd::watch;
+use crate::rmd::workdir;
 
 mod rmd;
 
 #[derive(Clap)]
 struct Opts {
-    config: String,
+    /// A configuration file, applied over ~/.config/rinput/config and the project's .rinput.toml
+    #[clap(long, global = true)]
+    config: Option<String>,
+    /// The runbook to use instead of the closest rinput.md or maskfile.md
+    #[clap(long, global = true)]
+    file: Option<String>,
+    /// Tell what each phase and block does, -vv for the details
+    #[clap(short, long, parse(from_occurrences), global = true)]
+    verbose: u64,
+    /// Print errors only
+    #[clap(short, long, global = true)]
+    quiet: bool,
+    /// text, or json for one event per line
+    #[clap(long, default_value = "text", global = true)]
+    log_format: String,
     #[clap(subcommand)]
     subcmd: SubCommand,
 }
 
 #[derive(Clap)]
 enum SubCommand {
-    UI(EditorCmd),
+    UI(UiCmd),
 
     Box(EditorCmd),
 
-    Run(EditorCmd),
+    Run(RunCmd),
+
+    List(ListCmd),
+
+    Show(ShowCmd),
+
+    View(ViewCmd),
+
+    Export(ExportCmd),
+
+    Cache(CacheCmd),
+
+    Completions(CompletionsCmd),
+
+    // `rinput deploy prod --dry-run` runs the runbook's `deploy` command, `rinput -- run` the
+    // runbook's `run` where the builtin would take it
+    #[clap(external_subcommand)]
+    External(Vec<String>),
+}
+
+#[derive(Clap)]
+struct UiCmd {
+    path: Option<String>,
 }
 
 #[derive(Clap)]
@@ -35,19 +88,174 @@ struct EditorCmd {
     path: String,
 }
 
+#[derive(Clap)]
+struct RunCmd {
+    path: Option<String>,
+    /// Keep the scratch directory holding the document's file blocks after the run
+    #[clap(long)]
+    keep_workdir: bool,
+    /// Re-run the document whenever it or a `cwd=` directory changes
+    #[clap(long)]
+    watch: bool,
+    /// Run only the n-th executable block, as numbered by `rinput view`
+    #[clap(long)]
+    block: Option<usize>,
+}
+
+#[derive(Clap)]
+struct ListCmd {
+    path: Option<String>,
+    /// Print the command tree as JSON
+    #[clap(long)]
+    json: bool,
+}
+
+#[derive(Clap)]
+struct ShowCmd {
+    /// The document, or the command when the runbook is found on its own
+    path: String,
+    /// The command's heading path, e.g. `deploy staging` or `deploy/staging`
+    command: Option<String>,
+}
+
+#[derive(Clap)]
+struct ViewCmd {
+    path: Option<String>,
+}
+
+#[derive(Clap)]
+struct ExportCmd {
+    path: Option<String>,
+    /// sh, make, justfile or ipynb
+    #[clap(long, default_value = "sh")]
+    format: String,
+    /// Run the blocks first and keep what they print in the notebook's outputs
+    #[clap(long)]
+    run: bool,
+}
+
+#[derive(Clap)]
+struct CompletionsCmd {
+    /// bash, zsh or fish
+    shell: String,
+}
+
+#[derive(Clap)]
+struct CacheCmd {
+    #[clap(subcommand)]
+    action: CacheAction,
+}
+
+#[derive(Clap)]
+enum CacheAction {
+    Ls,
+
+    Clean,
+}
+
+// the subcommands offered next to a document's own commands when completing
+const BUILTINS: [(&str, &str); 9] = [
+    ("run", "Run a markdown document"),
+    ("list", "List a document's commands"),
+    ("show", "Show a command's source"),
+    ("view", "Render a markdown document"),
+    ("export", "Export a document as a shell script, Makefile or justfile"),
+    ("cache", "Manage cached builds"),
+    ("completions", "Print a shell completion script"),
+    ("ui", "Browse and run a document's commands"),
+    ("box", "Open the editor on a file or stdin"),
+];
+
 fn main() {
-    let opts: Opts = Opts::parse();
+    // the hook behind dynamic completions, answered before the usual arguments are required
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("__complete") {
+        // only the separator the completion scripts put first, a `--` after it is the user's
+        let separator = args.get(2).is_some_and(|word| word == "--");
+        let words = args.iter().skip(if separator { 3 } else { 2 }).cloned().collect::<Vec<String>>();
+        complete_words(&words);
+        return;
+    }
+
+    let opts: Opts = Opts::parse_from(escape_runbook_command(args));
+    if let Err(err) = log::init(log::Level::from_flags(opts.verbose, opts.quiet), &opts.log_format) {
+        eprintln!("{} {}", "ERROR:".red(), err);
+        std::process::exit(1)
+    }
+    let config = match Config::load(opts.config.as_deref()) {
+        Ok(config) => config,
+        Err(err) => {
+            log::error("config", &err.to_string());
+            std::process::exit(1)
+        }
+    };
     match opts.subcmd {
-        SubCommand::UI(_t) => {
-            start_ui();
+        SubCommand::UI(t) => {
+            let path = runbook_path(t.path, opts.file, &config);
+            start_ui(&path, &config);
         }
         SubCommand::Box(t) => {
-            start_box(t);
+            start_box(t, config.editor);
         }
         SubCommand::Run(t) => {
-            run_markdown(t);
+            let path = runbook_path(t.path.clone(), opts.file, &config);
+            if t.watch && t.block.is_some() {
+                log::error("run", "--block can't be combined with --watch");
+                std::process::exit(1)
+            }
+            if t.watch {
+                watch_markdown(&path, t, &config);
+            } else {
+                run_markdown(&path, t, &config);
+            }
+        }
+        SubCommand::List(t) => {
+            let path = runbook_path(t.path.clone(), opts.file, &config);
+            list_commands(&path, t);
+        }
+        SubCommand::Show(t) => {
+            show_command(t, opts.file, &config);
+        }
+        SubCommand::View(t) => {
+            view_markdown(&runbook_path(t.path, opts.file, &config));
+        }
+        SubCommand::Export(t) => {
+            let path = runbook_path(t.path.clone(), opts.file, &config);
+            export_markdown(&path, t, &config);
+        }
+        SubCommand::Cache(t) => {
+            manage_cache(t);
+        }
+        SubCommand::Completions(t) => {
+            print_completions(t, opts.file);
+        }
+        SubCommand::External(words) => {
+            let words = if words.first().map(String::as_str) == Some(RUNBOOK_ESCAPE) { &words[1..] } else { &words[..] };
+            run_command(&runbook_path(None, opts.file, &config), words, &config);
+        }
+    }
+}
+
+// what the `--` of `rinput --file ops.md -- run` becomes, so clap hands the words after it over as
+// an external subcommand instead of taking `run` for the builtin
+const RUNBOOK_ESCAPE: &str = "__runbook";
+
+fn escape_runbook_command(mut args: Vec<String>) -> Vec<String> {
+    let mut index = 1;
+    while let Some(arg) = args.get(index) {
+        match arg.as_str() {
+            "--" => {
+                args[index] = String::from(RUNBOOK_ESCAPE);
+                break;
+            }
+            "--config" | "--file" | "--log-format" => index += 2,
+            "--verbose" | "--quiet" => index += 1,
+            arg if ["--config=", "--file=", "--log-format="].iter().any(|flag| arg.starts_with(flag)) => index += 1,
+            arg if arg.len() > 1 && arg.starts_with('-') && arg[1..].chars().all(|c| c == 'v' || c == 'q') => index += 1,
+            _ => break,
         }
     }
+    args
 }
 
 fn is_atty(fileno: libc::c_int) -> bool {
@@ -56,29 +264,297 @@ fn is_atty(fileno: libc::c_int) -> bool {
     unsafe { libc::isatty(fileno) != 0 }
 }
 
-fn run_markdown(args: EditorCmd) {
-    let filename = args.path;
-    let contents = fs::read_to_string(filename)
-        .expect("Something went wrong reading the file");
+// the document named on the command line, then --file, then the closest runbook
+fn runbook_path(path: Option<String>, file: Option<String>, config: &Config) -> String {
+    if let Some(path) = path.or(file) {
+        return path;
+    }
+    let cwd = std::env::current_dir().unwrap_or_default();
+    let names = config.runbook_names();
+    match runbook::discover(&cwd, &names) {
+        Some(found) => found.display().to_string(),
+        None => {
+            log::error("parse", &format!("no {} in {} or its parents, pass a document or --file", names.join(" or "), cwd.display()));
+            std::process::exit(1)
+        }
+    }
+}
+
+// the document as markdown, whatever format it is in
+fn read_document(path: &str) -> String {
+    match document::read(path) {
+        Ok(text) => text,
+        Err(err) => {
+            log::error("parse", &format!("cannot read {}: {}", path, err));
+            std::process::exit(1)
+        }
+    }
+}
+
+// parses the document, giving up when a runbook it includes cannot be read
+fn parse_document(parser: &mut rmd::Rmd) -> Vec<rmd::Command> {
+    let started = Instant::now();
+    let commands = parser.parse();
+    log::timed("parse", &format!("{} executable block(s), {} file block(s)", commands.len(), parser.fixtures().len()), started.elapsed());
+    for cmd in &commands {
+        log::debug("parse", &format!("{} block at {}:{} under `{}`", cmd.script.executor, cmd.script.file, cmd.script.line, cmd.path.join(" / ")));
+    }
+    if !parser.errors().is_empty() {
+        for err in parser.errors() {
+            log::error("parse", err);
+        }
+        std::process::exit(1)
+    }
+    commands
+}
+
+fn run_markdown(path: &str, args: RunCmd, config: &Config) {
+    let contents = read_document(path);
+
+    let mut parser = rmd::Rmd::with_path(contents, path.to_string());
+    let mut vec = parse_document(&mut parser);
+    warn_shadowed(&vec, path);
+    if let Some(number) = args.block {
+        if number == 0 || number > vec.len() {
+            log::error("run", &format!("no block {} in {}, it has {} executable block(s)", number, path, vec.len()));
+            std::process::exit(1)
+        }
+        vec = vec![vec.swap_remove(number - 1)];
+    }
+    run_blocks(&parser, vec, args.keep_workdir, config)
+}
 
-    let mut parser = rmd::Rmd::new(contents);
-    let vec = parser.parse();
+// `rinput run` runs the whole document, however much its `run` heading looks like the one meant
+fn warn_shadowed(commands: &[rmd::Command], path: &str) {
+    let builtins: Vec<&str> = BUILTINS.iter().map(|(name, _)| *name).chain(["help"]).collect();
+    for word in invoke::shadowed(commands, &builtins) {
+        let msg = format!("the `{}` command of {} is shadowed by rinput's own, run it as `rinput -- {}`", word, path, word);
+        log::status("parse", &msg, format!("{} {}", "warning:".yellow(), msg));
+    }
+}
 
-    for cmd in vec.into_iter() {
-        match execute_command(cmd) {
-            Ok(status) => match status.code() {
-                Some(code) => std::process::exit(code),
-                None => return,
-            },
-            Err(err) => {
-                eprintln!("{} {}", "ERROR:".red(), err);
-                std::process::exit(1)
+// runs the words after `rinput` as one of the runbook's commands
+fn run_command(path: &str, words: &[String], config: &Config) {
+    let contents = read_document(path);
+
+    let mut parser = rmd::Rmd::with_path(contents, path.to_string());
+    let commands = parse_document(&mut parser);
+    match invoke::resolve(&commands, words) {
+        Ok(blocks) => {
+            // the `--verbose` every command gets
+            if blocks.iter().flat_map(|block| &block.option_flags).any(|flag| flag.name == "verbose" && !flag.val.is_empty()) {
+                log::raise(log::Level::Verbose);
+            }
+            run_blocks(&parser, blocks, false, config)
+        }
+        Err(err) => {
+            log::error("run", &format!("{} in {}", err, path));
+            std::process::exit(1)
+        }
+    }
+}
+
+fn create_workdir(parser: &rmd::Rmd) -> Option<tempfile::TempDir> {
+    if parser.fixtures().is_empty() {
+        return None;
+    }
+    match workdir::create(parser.fixtures()) {
+        Ok(dir) => Some(dir),
+        Err(err) => {
+            log::error("run", &err.to_string());
+            std::process::exit(1)
+        }
+    }
+}
+
+// asked for before the run starts, so a prompt does not end up between block output
+fn resolve_secrets(commands: &[rmd::Command]) -> HashMap<String, String> {
+    match secrets::resolve(commands, &HashMap::new()) {
+        Ok(secrets) => secrets,
+        Err(err) => {
+            log::error("run", &err.to_string());
+            std::process::exit(1)
+        }
+    }
+}
+
+fn run_blocks(parser: &rmd::Rmd, vec: Vec<rmd::Command>, keep_workdir: bool, config: &Config) {
+    let workdir = create_workdir(parser);
+    let mut ctx = RunContext {
+        workdir: workdir.as_ref().map(|dir| dir.path().to_path_buf()),
+        interpreters: config.interpreters.clone(),
+        secrets: resolve_secrets(&vec),
+        ..RunContext::default()
+    };
+
+    let exit_code = match runner::run_commands(vec, &mut ctx) {
+        Ok(report) => {
+            if log::enabled(log::Level::Normal) {
+                report.print_summary();
             }
+            report.exit_code
+        }
+        Err(err) => {
+            log::error("run", &err.to_string());
+            1
+        }
+    };
+
+    // `exit` skips destructors, so the workdir is removed (or kept) before it
+    if let Some(workdir) = workdir {
+        if keep_workdir {
+            let kept = workdir.into_path().display().to_string();
+            log::status("run", &format!("workdir kept at {}", kept), format!("{} {}", "workdir kept at".green(), kept));
+        }
+    }
+    std::process::exit(exit_code)
+}
+
+fn watch_markdown(path: &str, args: RunCmd, config: &Config) {
+    if let Err(err) = watch::watch(path, args.keep_workdir, &config.interpreters) {
+        log::error("watch", &err.to_string());
+        std::process::exit(1)
+    }
+}
+
+fn parse_markdown(path: &str) -> Vec<rmd::Command> {
+    parse_document(&mut rmd::Rmd::with_path(read_document(path), path.to_string()))
+}
+
+fn list_commands(path: &str, args: ListCmd) {
+    let commands = parse_markdown(path);
+    warn_shadowed(&commands, path);
+    let tree = outline::command_tree(&commands);
+    if args.json {
+        println!("{}", outline::tree_to_json(&tree));
+    } else {
+        print!("{}", outline::render_tree(&tree));
+    }
+}
+
+fn view_markdown(path: &str) {
+    let contents = read_document(path);
+    let commands = parse_document(&mut rmd::Rmd::with_path(contents.clone(), path.to_string()));
+    print!("{}", view::render(&contents, path, &commands));
+}
+
+fn export_markdown(path: &str, args: ExportCmd, config: &Config) {
+    let format = match Format::from_name(&args.format) {
+        Ok(format) => format,
+        Err(err) => {
+            log::error("export", &err.to_string());
+            std::process::exit(1)
+        }
+    };
+    if args.run && format != Format::Ipynb {
+        log::error("export", "--run only applies to --format ipynb");
+        std::process::exit(1)
+    }
+    let contents = read_document(path);
+    let mut parser = rmd::Rmd::with_path(contents.clone(), path.to_string());
+    let commands = parse_document(&mut parser);
+    let transcript = if args.run { run_for_transcript(&parser, commands.clone(), config) } else { Transcript::new() };
+    match export::export(format, &contents, &commands, parser.fixtures(), &transcript, &config.interpreters, path) {
+        Ok(script) => print!("{}", script),
+        Err(err) => {
+            log::error("export", &err.to_string());
+            std::process::exit(1)
         }
     }
 }
 
-fn start_box(args: EditorCmd) {
+// runs the blocks for what they print, which goes to the transcript rather than the terminal
+fn run_for_transcript(parser: &rmd::Rmd, vec: Vec<rmd::Command>, config: &Config) -> Transcript {
+    let workdir = create_workdir(parser);
+    let (sink, _) = channel();
+    let mut ctx = RunContext {
+        workdir: workdir.as_ref().map(|dir| dir.path().to_path_buf()),
+        interpreters: config.interpreters.clone(),
+        output: Some(sink),
+        transcript: Some(Transcript::new()),
+        secrets: resolve_secrets(&vec),
+        ..RunContext::default()
+    };
+    match runner::run_commands(vec, &mut ctx) {
+        Ok(report) if log::enabled(log::Level::Normal) => report.print_summary(),
+        Ok(_) => (),
+        Err(err) => log::error("run", &err.to_string()),
+    }
+    ctx.transcript.unwrap_or_default()
+}
+
+fn show_command(args: ShowCmd, file: Option<String>, config: &Config) {
+    // `show deploy` names only the command and leaves the document to --file or discovery
+    let (path, command) = match args.command {
+        Some(command) => (args.path, command),
+        None => (runbook_path(None, file, config), args.path),
+    };
+    let commands = parse_markdown(&path);
+    let found = outline::find(&commands, &command);
+    if found.is_empty() {
+        log::error("run", &format!("no command `{}` in {}", command, path));
+        std::process::exit(1)
+    }
+    for (index, cmd) in found.into_iter().enumerate() {
+        if index > 0 {
+            println!();
+        }
+        print!("{}", outline::render_command(cmd));
+    }
+}
+
+// with --file, the document's commands go into the script instead of rinput being asked on every completion
+fn print_completions(args: CompletionsCmd, file: Option<String>) {
+    let shell = match Shell::from_name(&args.shell) {
+        Ok(shell) => shell,
+        Err(err) => {
+            log::error("completions", &err.to_string());
+            std::process::exit(1)
+        }
+    };
+    match file {
+        Some(path) => print!("{}", completions::static_script(shell, &parse_markdown(&path), &BUILTINS, &path)),
+        None => print!("{}", completions::dynamic_script(shell)),
+    }
+}
+
+fn complete_words(words: &[String]) {
+    // `rinput --file ops.md ...` completes for that document, otherwise for the closest runbook
+    let (file, words) = match words {
+        [flag, file, rest @ ..] if flag == "--file" => (Some(std::path::PathBuf::from(file)), rest),
+        _ => (None, words),
+    };
+    // a broken configuration is reported when rinput runs, not in the middle of a completion
+    let config = Config::load(None).unwrap_or_default();
+    let commands = file
+        .or_else(|| std::env::current_dir().ok().and_then(|dir| runbook::discover(&dir, &config.runbook_names())))
+        .and_then(|path| document::read(&path.display().to_string()).ok().map(|text| rmd::Rmd::with_path(text, path.display().to_string()).parse()))
+        .unwrap_or_default();
+    for (word, desc) in completions::complete(&commands, &BUILTINS, words) {
+        println!("{}\t{}", word, desc);
+    }
+}
+
+fn manage_cache(args: CacheCmd) {
+    let cache = BuildCache::new();
+    let result = match args.action {
+        CacheAction::Ls => cache.entries().map(|entries| {
+            for entry in entries {
+                println!("{} {} {:>10} {}", entry.lang.green(), entry.key, entry.size, entry.binary.display());
+            }
+        }),
+        CacheAction::Clean => cache.clean().map(|count| {
+            println!("Removed {} cached build(s) from {}", count, cache.dir().display());
+        }),
+    };
+
+    if let Err(err) = result {
+        log::error("cache", &err.to_string());
+        std::process::exit(1)
+    }
+}
+
+fn start_box(args: EditorCmd, options: EditorOptions) {
     let stdin_is_atty = is_atty(libc::STDIN_FILENO);
     let stderr_is_atty = is_atty(libc::STDERR_FILENO);
 
@@ -99,8 +575,47 @@ fn start_box(args: EditorCmd) {
         Result::Err(e) => panic!("{}", e),
     };
 
-    let mut editor = Editor::new(source, rustbox);
+    let mut editor = Editor::with_options(source, rustbox, options);
     editor.start();
 }
 
-fn start_ui() {}
+fn start_ui(path: &str, config: &Config) {
+    if let Err(err) = browser::browse(path, &config.interpreters) {
+        log::error("ui", &err.to_string());
+        std::process::exit(1)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use clap::Clap;
+
+    use crate::{escape_runbook_command, Opts, SubCommand};
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn should_reach_runbook_commands_shadowed_by_builtins() {
+        let opts = Opts::try_parse_from(escape_runbook_command(args("rinput -v --file ops.md -- run --fast"))).unwrap();
+        match opts.subcmd {
+            SubCommand::External(words) => assert_eq!(args("__runbook run --fast"), words),
+            _ => panic!("expected the runbook's command"),
+        }
+        assert_eq!(Some(String::from("ops.md")), opts.file);
+
+        let opts = Opts::try_parse_from(escape_runbook_command(args("rinput run -- ops.md"))).unwrap();
+        assert!(matches!(opts.subcmd, SubCommand::Run(_)));
+    }
+
+    #[test]
+    fn should_take_logging_flags_after_the_subcommand() {
+        let opts = Opts::try_parse_from(args("rinput run ops.md -vv --log-format json")).unwrap();
+        assert_eq!(2, opts.verbose);
+        assert_eq!("json", opts.log_format);
+
+        let opts = Opts::try_parse_from(args("rinput export -q")).unwrap();
+        assert!(opts.quiet);
+    }
+}
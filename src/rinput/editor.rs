@@ -11,8 +11,9 @@ use crate::input::Input;
 use crate::keyboard::Key;
 use crate::buffer::Buffer;
 use crate::command::{Command, BuilderArgs, BuilderEvent, Action, Instruction, Operation};
-use crate::view::View;
+use crate::view::{Colors, View};
 use crate::modes::{Mode, StandardMode, ModeType, InsertMode, NormalMode};
+use crate::keymap::CommandInfo;
 
 
 type EditorCommand = fn(Option<BuilderArgs>) -> Command;
@@ -40,11 +41,58 @@ lazy_static! {
     };
 }
 
+/// The commands a configured key can be bound to: the ones that need no arguments
+pub const BINDABLE_COMMANDS: [&str; 6] = [
+    "editor::quit",
+    "editor::save_buffer",
+    "editor::noop",
+    "editor::undo",
+    "editor::redo",
+    "buffer::insert_tab",
+];
+
+/// Settings the editor starts with
+#[derive(Clone, Debug, PartialEq)]
+pub struct EditorOptions {
+    /// Columns a tab advances to, and the spaces the tab key inserts
+    pub tab_width: usize,
+    /// Start in the vi-like normal mode instead of the standard, non-modal one
+    pub normal_mode: bool,
+    /// Bindings on top of each mode's defaults, as a key name (`ctrl-s`, `f5`) and a command name
+    pub keymap: Vec<(String, String)>,
+    /// The colours the view draws with
+    pub colors: Colors,
+}
+
+impl Default for EditorOptions {
+    fn default() -> Self {
+        EditorOptions {
+            tab_width: 4,
+            normal_mode: false,
+            keymap: Vec::new(),
+            colors: Colors::default(),
+        }
+    }
+}
+
+/// Check a key binding before the editor starts, so a typo is reported rather than ignored
+pub fn validate_binding(key: &str, command: &str) -> Result<(), String> {
+    if Key::from_name(key).is_none() {
+        return Err(format!("unknown key `{}`, expected e.g. ctrl-s, f5, pagedown or a single character", key));
+    }
+    if !BINDABLE_COMMANDS.contains(&command) {
+        return Err(format!("cannot bind `{}`, expected one of: {}", command, BINDABLE_COMMANDS.join(", ")));
+    }
+    Ok(())
+}
+
 pub struct Editor {
     buffers: Vec<Arc<Mutex<Buffer>>>,
     view: View,
     rb: RustBox,
     mode: Box<dyn Mode>,
+    bindings: Vec<(Key, CommandInfo)>,
+    tab_width: usize,
 
     running: bool,
 
@@ -53,7 +101,13 @@ pub struct Editor {
 }
 
 impl Editor {
+    /// Create an editor with the default settings
     pub fn new(source: Input, rb: RustBox) -> Editor {
+        Editor::with_options(source, rb, EditorOptions::default())
+    }
+
+    /// Create an editor with the given settings
+    pub fn with_options(source: Input, rb: RustBox, options: EditorOptions) -> Editor {
         let height = rb.height();
         let width = rb.width();
 
@@ -74,18 +128,35 @@ impl Editor {
         };
 
         buffers.push(Arc::new(Mutex::new(buffer)));
-        let view = View::new(buffers[0].clone(), width, height);
+        let mut view = View::new(buffers[0].clone(), width, height);
+        view.set_style(options.tab_width, options.colors);
 
-        let mode = Box::new(StandardMode::new());
+        // bindings that fail validate_binding are skipped
+        let bindings = options.keymap.iter()
+            .filter(|(key, command)| validate_binding(key, command).is_ok())
+            .filter_map(|(key, command)| Key::from_name(key).map(|key| (key, CommandInfo { command_name: command.clone(), args: None })))
+            .collect();
+        let mode: Box<dyn Mode> = if options.normal_mode { Box::new(NormalMode::new()) } else { Box::new(StandardMode::new()) };
 
-        Editor {
+        let mut editor = Editor {
             rb,
             buffers,
             view,
             running: true,
             mode,
+            bindings,
+            tab_width: options.tab_width,
             command_queue: recv,
             command_sender: snd,
+        };
+        editor.apply_bindings();
+        editor
+    }
+
+    /// Add the configured bindings to the current mode
+    fn apply_bindings(&mut self) {
+        for (key, command) in &self.bindings {
+            self.mode.bind_key(*key, command.clone());
         }
     }
 
@@ -127,7 +198,10 @@ impl Editor {
 
             match ALL_COMMANDS.get(&*c.command_name) {
                 Some(cmd) => {
-                    let cmd = cmd(c.args);
+                    let mut cmd = cmd(c.args);
+                    if c.command_name == "buffer::insert_tab" {
+                        cmd.number = self.tab_width as i32;
+                    }
                     let _ = self.command_sender.send(cmd);
                 }
                 None => {
@@ -175,6 +249,7 @@ impl Editor {
                     ModeType::Insert => { self.mode = Box::new(InsertMode::new()) }
                     ModeType::Normal => { self.mode = Box::new(NormalMode::new()) }
                 }
+                self.apply_bindings();
             }
             Action::Instruction(Instruction::ShowMessage(msg)) => {
                 self.view.show_message(msg)
@@ -185,10 +260,9 @@ impl Editor {
 
     fn handle_operation(&mut self, command: Command) {
         match command.action {
+            // handle_command already repeats the operation `number` times
             Action::Operation(Operation::Insert(c)) => {
-                for _ in 0..command.number {
-                    self.view.insert_char(c)
-                }
+                self.view.insert_char(c)
             }
             Action::Operation(Operation::DeleteObject) => {
                 if let Some(obj) = command.object {
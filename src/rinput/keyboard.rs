@@ -3,36 +3,60 @@ use std::time::Duration;
 
 use crate::rustbox::rustbox::{RustBox, Event};
 
+/// A key or chord as the editor sees it, decoded from termbox's key codes
 #[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
 pub enum Key {
+    /// Tab
     Tab,
+    /// Enter
     Enter,
+    /// Escape
     Esc,
+    /// Backspace
     Backspace,
+    /// Right arrow
     Right,
+    /// Left arrow
     Left,
+    /// Up arrow
     Up,
+    /// Down arrow
     Down,
+    /// Delete
     Delete,
+    /// Insert
     Insert,
 
+    /// Home
     Home,
+    /// End
     End,
+    /// Page up
     PageUp,
+    /// Page down
     PageDown,
 
+    /// Ctrl and the left arrow
     CtrlLeft,
+    /// Ctrl and the right arrow
     CtrlRight,
+    /// Alt and the left arrow
     AltLeft,
+    /// Alt and the right arrow
     AltRight,
 
+    /// A printable character
     Char(char),
+    /// Ctrl and a character
     Ctrl(char),
+    /// A function key, `F(1)` to `F(12)`
     F(u32),
+    /// A key code with no name here
     Unknown(u16),
 }
 
 impl Key {
+    /// The key termbox reports as `code` when it is not a plain character
     pub fn from_special_code(code: u16) -> Option<Key> {
         match code {
             1 => Some(Key::Ctrl('a')),
@@ -94,6 +118,51 @@ impl Key {
         }
     }
 
+    /// Parse a key as written in a keymap: `ctrl-s`, `f5`, `pagedown`, `x`
+    pub fn from_name(name: &str) -> Option<Key> {
+        let lower = name.to_lowercase();
+        if let Some(rest) = lower.strip_prefix("ctrl-") {
+            let mut chars = rest.chars();
+            return match (chars.next(), chars.next()) {
+                (Some(c), None) => Some(Key::Ctrl(c)),
+                _ if rest == "left" => Some(Key::CtrlLeft),
+                _ if rest == "right" => Some(Key::CtrlRight),
+                _ => None,
+            };
+        }
+        if let Some(number) = lower.strip_prefix('f').and_then(|rest| rest.parse::<u32>().ok()) {
+            return if (1..=12).contains(&number) { Some(Key::F(number)) } else { None };
+        }
+
+        match lower.as_str() {
+            "tab" => Some(Key::Tab),
+            "enter" => Some(Key::Enter),
+            "esc" => Some(Key::Esc),
+            "backspace" => Some(Key::Backspace),
+            "left" => Some(Key::Left),
+            "right" => Some(Key::Right),
+            "up" => Some(Key::Up),
+            "down" => Some(Key::Down),
+            "delete" => Some(Key::Delete),
+            "insert" => Some(Key::Insert),
+            "home" => Some(Key::Home),
+            "end" => Some(Key::End),
+            "pageup" => Some(Key::PageUp),
+            "pagedown" => Some(Key::PageDown),
+            "alt-left" => Some(Key::AltLeft),
+            "alt-right" => Some(Key::AltRight),
+            "space" => Some(Key::Char(' ')),
+            _ => {
+                let mut chars = name.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => Some(Key::Char(c)),
+                    _ => None,
+                }
+            }
+        }
+    }
+
+    /// The key that starts with `start`, reading the rest of an escape sequence when there is one
     pub fn from_chord(rb: &mut RustBox, start: u16) -> Option<Key> {
         let chord = Key::get_chord(rb, start);
 
@@ -108,6 +177,7 @@ impl Key {
         }
     }
 
+    /// `start` and whatever is waiting after it, as a string
     pub fn get_chord(rb: &mut RustBox, start: u16) -> String {
         // Copy any data waiting to a string
         // There may be a cleaner way to do this?
@@ -119,6 +189,7 @@ impl Key {
         chord
     }
 
+    /// The key an input event stands for
     pub fn from_event(rb: &mut RustBox, event: Event) -> Option<Key> {
         match event {
             Event::KeyEventRaw(_, k, ch) => {
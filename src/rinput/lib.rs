@@ -14,8 +14,10 @@ extern crate gag;
 extern crate num_traits;
 extern crate termbox_sys as termbox;
 
-pub use editor::Editor;
+pub use editor::{Editor, EditorOptions, BINDABLE_COMMANDS, validate_binding};
+pub use view::Colors;
 pub use input::Input;
+pub use keyboard::Key;
 pub use modes::{StandardMode};
 
 mod input;
@@ -117,4 +117,8 @@ impl Mode for InsertMode {
             BuilderEvent::Incomplete
         }
     }
+
+    fn bind_key(&mut self, key: Key, command: CommandInfo) {
+        self.keymap.bind_key(key, command);
+    }
 }
@@ -1,5 +1,6 @@
 use crate::keyboard::Key;
 use crate::command::BuilderEvent;
+use crate::keymap::CommandInfo;
 
 pub use self::standard::StandardMode;
 pub use self::normal::NormalMode;
@@ -22,4 +23,7 @@ pub enum ModeType {
 pub trait Mode {
     /// Given a Key, return a Command wrapped in a BuilderEvent for the Editor to interpret
     fn handle_key_event(&mut self, key: Key) -> BuilderEvent;
+
+    /// Bind a key to a command, on top of (or instead of) the mode's default bindings
+    fn bind_key(&mut self, key: Key, command: CommandInfo);
 }
@@ -164,6 +164,10 @@ impl Mode for NormalMode {
             }
         }
     }
+
+    fn bind_key(&mut self, key: Key, command: CommandInfo) {
+        self.keymap.bind_key(key, command);
+    }
 }
 
 impl Default for NormalMode {
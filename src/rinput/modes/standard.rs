@@ -225,6 +225,10 @@ impl Mode for StandardMode {
             self.check_key(key)
         }
     }
+
+    fn bind_key(&mut self, key: Key, command: CommandInfo) {
+        self.keymap.bind_key(key, command);
+    }
 }
 
 impl Default for StandardMode {
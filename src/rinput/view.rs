@@ -17,6 +17,27 @@ use crate::overlay::{CommandPrompt, Overlay, OverlayType};
 use crate::textobject::{Anchor, Kind, Offset, TextObject};
 use crate::utils;
 
+/// The colours the view draws with
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Colors {
+    /// Text in the buffer
+    pub text: Color,
+    /// Behind the text
+    pub background: Color,
+    /// The status bar of a buffer without unsaved changes
+    pub status: Color,
+}
+
+impl Default for Colors {
+    fn default() -> Self {
+        Colors {
+            text: Color::White,
+            background: Color::Black,
+            status: Color::Byte(19),
+        }
+    }
+}
+
 pub struct View {
     pub buffer: Arc<Mutex<Buffer>>,
     pub last_buffer: Option<Arc<Mutex<Buffer>>>,
@@ -40,6 +61,10 @@ pub struct View {
     /// Message to be displayed in the status bar along with the time it
     /// was displayed.
     message: Option<(String, SystemTime)>,
+
+    /// Columns a tab character advances to
+    tab_width: usize,
+    colors: Colors,
 }
 
 impl View {
@@ -65,9 +90,17 @@ impl View {
             left_col: 0,
             threshold: 5,
             message: None,
+            tab_width: 4,
+            colors: Colors::default(),
         }
     }
 
+    /// Set the tab width and colours the view draws with
+    pub fn set_style(&mut self, tab_width: usize, colors: Colors) {
+        self.tab_width = tab_width;
+        self.colors = colors;
+    }
+
 
     /// Get the height of the View.
     ///
@@ -107,7 +140,7 @@ impl View {
             let mut lines = buffer.lines_from(self.top_line).unwrap().take(height);
             for y_position in 0..height {
                 let line = lines.next().unwrap_or_else(Vec::new);
-                draw_line(rb, &line, y_position, self.left_col);
+                draw_line(rb, &line, y_position, self.left_col, self.tab_width, self.colors);
             }
         }
 
@@ -138,7 +171,7 @@ impl View {
             let ch: char = if index < status_text_len {
                 status_text[index] as char
             } else { ' ' };
-            let mut color = Color::Byte(19);
+            let mut color = self.colors.status;
             if buffer.dirty {
                 color = Color::Red
             }
@@ -153,7 +186,7 @@ impl View {
         }
         if let Some((ref message, _time)) = self.message {
             for (offset, ch) in message.chars().enumerate() {
-                rb.print_char(offset, height + 1, RustBoxStyle::empty(), Color::White, Color::Black, ch);
+                rb.print_char(offset, height + 1, RustBoxStyle::empty(), self.colors.text, self.colors.background, ch);
             }
         }
     }
@@ -173,7 +206,7 @@ impl View {
     pub fn clear(&mut self, rb: &mut RustBox) {
         for row in 0..self.height {
             for col in 0..self.width {
-                rb.print_char(col, row, RustBoxStyle::empty(), Color::White, Color::Black, ' ');
+                rb.print_char(col, row, RustBoxStyle::empty(), self.colors.text, self.colors.background, ' ');
             }
         }
     }
@@ -182,7 +215,7 @@ impl View {
     pub fn insert_char(&mut self, ch: char) {
         self.buffer.lock().unwrap().insert_char(self.cursor, ch as u8);
         // NOTE: the last param to char_width here may not be correct
-        if let Some(ch_width) = utils::char_width(ch, false, 4, 1) {
+        if let Some(ch_width) = utils::char_width(ch, false, self.tab_width, 1) {
             let obj = TextObject {
                 kind: Kind::Char,
                 offset: Offset::Forward(ch_width, Mark::Cursor(0)),
@@ -344,7 +377,7 @@ impl View {
     }
 }
 
-pub fn draw_line(rb: &mut RustBox, line: &[u8], idx: usize, left: usize) {
+pub fn draw_line(rb: &mut RustBox, line: &[u8], idx: usize, left: usize, tab_width: usize, colors: Colors) {
     let width = rb.width() - 1;
     let mut x = 0;
 
@@ -352,15 +385,15 @@ pub fn draw_line(rb: &mut RustBox, line: &[u8], idx: usize, left: usize) {
         let ch = *ch as char;
         match ch {
             '\t' => {
-                let w = 4 - x % 4;
+                let w = tab_width - x % tab_width;
                 for _ in 0..w {
-                    rb.print_char(x, idx, RustBoxStyle::empty(), Color::White, Color::Black, ' ');
+                    rb.print_char(x, idx, RustBoxStyle::empty(), colors.text, colors.background, ' ');
                     x += 1;
                 }
             }
             '\n' => {}
             _ => {
-                rb.print_char(x, idx, RustBoxStyle::empty(), Color::White, Color::Black, ch);
+                rb.print_char(x, idx, RustBoxStyle::empty(), colors.text, colors.background, ch);
                 x += UnicodeWidthChar::width(ch).unwrap_or(1);
             }
         }
@@ -371,11 +404,11 @@ pub fn draw_line(rb: &mut RustBox, line: &[u8], idx: usize, left: usize) {
 
     // Replace any cells after end of line with ' '
     while x < width {
-        rb.print_char(x, idx, RustBoxStyle::empty(), Color::White, Color::Black, ' ');
+        rb.print_char(x, idx, RustBoxStyle::empty(), colors.text, colors.background, ' ');
         x += 1;
     }
 
     // If the line is too long to fit on the screen, show an indicator
     let indicator = if line.len() > width + left { '→' } else { ' ' };
-    rb.print_char(width, idx, RustBoxStyle::empty(), Color::White, Color::Black, indicator);
+    rb.print_char(width, idx, RustBoxStyle::empty(), colors.text, colors.background, indicator);
 }
@@ -0,0 +1,509 @@
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind, Result};
+use std::sync::mpsc::{channel, Sender};
+use std::thread;
+use std::time::Duration;
+
+use rinput::Key;
+use rinput::rustbox::rustbox::{Color, Event, InitOptions, InputMode, OutputMode, RustBox, Style};
+
+use crate::rmd::command::{Command, Fixture};
+use crate::rmd::document;
+use crate::rmd::executor::RunContext;
+use crate::rmd::invoke;
+use crate::rmd::outline::command_tree;
+use crate::rmd::parser::Rmd;
+use crate::rmd::runner::{self, Outcome};
+use crate::rmd::secrets;
+use crate::rmd::workdir;
+
+// how much of the run's output the bottom pane keeps
+const MAX_OUTPUT_LINES: usize = 2000;
+const REFRESH: Duration = Duration::from_millis(100);
+
+// one line of the command tree on the left; blocks under the same heading share a row
+#[derive(Debug, Clone, PartialEq)]
+struct Row {
+    depth: usize,
+    label: String,
+    path: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum UiKey {
+    Up,
+    Down,
+    Enter,
+    Esc,
+    Backspace,
+    Char(char),
+}
+
+#[derive(Debug)]
+enum Action {
+    Nothing,
+    Quit,
+    // the selected command's blocks, with its arguments filled in
+    Run(Vec<Command>),
+}
+
+// asks for the selected command's arguments one at a time before it runs
+#[derive(Debug)]
+struct Prompt {
+    names: Vec<String>,
+    values: Vec<String>,
+    input: String,
+}
+
+// What the browser shows, apart from drawing it, so it can be driven without a terminal.
+struct Browser {
+    commands: Vec<Command>,
+    rows: Vec<Row>,
+    selected: usize,
+    prompt: Option<Prompt>,
+    output: Vec<String>,
+    // the output after the last newline, shown but not yet a line of its own
+    partial: String,
+    running: bool,
+    status: String,
+}
+
+impl Browser {
+    fn new(commands: Vec<Command>) -> Browser {
+        let mut rows = vec![];
+        add_rows(&command_tree(&commands), 0, &mut rows);
+        Browser {
+            commands,
+            rows,
+            selected: 0,
+            prompt: None,
+            output: vec![],
+            partial: String::new(),
+            running: false,
+            status: String::from(HELP),
+        }
+    }
+
+    fn selected_blocks(&self) -> Vec<&Command> {
+        match self.rows.get(self.selected) {
+            Some(row) => self.commands.iter().filter(|cmd| cmd.path == row.path).collect(),
+            None => vec![],
+        }
+    }
+
+    fn handle(&mut self, key: UiKey) -> Action {
+        if self.prompt.is_some() {
+            return self.handle_prompt(key);
+        }
+        match key {
+            UiKey::Char('q') | UiKey::Esc => Action::Quit,
+            UiKey::Up | UiKey::Char('k') => {
+                self.selected = self.selected.saturating_sub(1);
+                Action::Nothing
+            }
+            UiKey::Down | UiKey::Char('j') => {
+                self.selected = (self.selected + 1).min(self.rows.len().saturating_sub(1));
+                Action::Nothing
+            }
+            UiKey::Char('c') => {
+                self.output.clear();
+                self.partial.clear();
+                Action::Nothing
+            }
+            UiKey::Enter | UiKey::Char('r') => self.start_run(),
+            _ => Action::Nothing,
+        }
+    }
+
+    fn start_run(&mut self) -> Action {
+        if self.running {
+            self.status = String::from("a command is still running");
+            return Action::Nothing;
+        }
+        let names: Vec<String> = match self.selected_blocks().first() {
+            Some(first) => first.required_args.iter().map(|arg| arg.name.clone()).collect(),
+            None => {
+                self.status = String::from("this heading only groups other commands");
+                return Action::Nothing;
+            }
+        };
+        if names.is_empty() {
+            return self.run(vec![]);
+        }
+        self.status = format!("<{}>: ", names[0]);
+        self.prompt = Some(Prompt { names, values: vec![], input: String::new() });
+        Action::Nothing
+    }
+
+    fn handle_prompt(&mut self, key: UiKey) -> Action {
+        let prompt = self.prompt.as_mut().unwrap();
+        match key {
+            UiKey::Esc => {
+                self.prompt = None;
+                self.status = String::from(HELP);
+                return Action::Nothing;
+            }
+            UiKey::Char(c) => prompt.input.push(c),
+            UiKey::Backspace => {
+                prompt.input.pop();
+            }
+            UiKey::Enter => {
+                prompt.values.push(prompt.input.split_off(0));
+                if prompt.values.len() == prompt.names.len() {
+                    let values = prompt.values.clone();
+                    self.prompt = None;
+                    return self.run(values);
+                }
+            }
+            _ => {}
+        }
+        let prompt = self.prompt.as_ref().unwrap();
+        self.status = format!("<{}>: {}", prompt.names[prompt.values.len()], prompt.input);
+        Action::Nothing
+    }
+
+    fn run(&mut self, values: Vec<String>) -> Action {
+        let mut blocks: Vec<Command> = self.selected_blocks().into_iter().cloned().collect();
+        for block in &mut blocks {
+            for (arg, value) in block.required_args.iter_mut().zip(&values) {
+                arg.val = value.clone();
+            }
+        }
+        // the blocks their `on-failure` names run as they would under `rinput run`
+        let blocks = invoke::with_handlers(&self.commands, blocks);
+        let row = &self.rows[self.selected];
+        let line = format!("$ {} {}", row.path.join(" / "), values.join(" ")).trim_end().to_string();
+        self.status = format!("running {}...", row.label);
+        self.push_line(line);
+        self.running = true;
+        Action::Run(blocks)
+    }
+
+    fn push_output(&mut self, bytes: &[u8]) {
+        let text = String::from_utf8_lossy(bytes).replace('\r', "");
+        let mut pieces = text.split('\n');
+        if let Some(first) = pieces.next() {
+            self.partial.push_str(first);
+        }
+        for piece in pieces {
+            let line = std::mem::replace(&mut self.partial, piece.to_string());
+            self.push_line(line);
+        }
+    }
+
+    fn push_line(&mut self, line: String) {
+        self.output.push(line);
+        if self.output.len() > MAX_OUTPUT_LINES {
+            self.output.remove(0);
+        }
+    }
+
+    fn finish(&mut self, summary: String) {
+        if !self.partial.is_empty() {
+            let line = self.partial.split_off(0);
+            self.push_line(line);
+        }
+        self.push_line(summary.clone());
+        self.running = false;
+        self.status = format!("{}  |  {}", summary, HELP);
+    }
+
+    // the right pane: where the selected command comes from, what it does and its source
+    fn details(&self) -> Vec<String> {
+        let mut lines = vec![];
+        let row = match self.rows.get(self.selected) {
+            Some(row) => row,
+            None => return lines,
+        };
+        lines.push(row.path.join(" / "));
+        let blocks = self.selected_blocks();
+        let first = match blocks.first() {
+            Some(first) => *first,
+            None => {
+                lines.push(String::new());
+                lines.push(String::from("groups:"));
+                let children = self.rows.iter().filter(|other| other.path.len() == row.path.len() + 1 && other.path.starts_with(&row.path));
+                lines.extend(children.map(|child| format!("  {}", child.label)));
+                return lines;
+            }
+        };
+
+        if !first.desc.is_empty() {
+            lines.push(String::new());
+            lines.extend(first.desc.lines().map(String::from));
+        }
+        if !first.required_args.is_empty() || !first.option_flags.is_empty() {
+            lines.push(String::new());
+        }
+        for arg in &first.required_args {
+            lines.push(format!("  <{}>  required", arg.name));
+        }
+        for flag in &first.option_flags {
+            let short = if flag.short.is_empty() { String::from("   ") } else { format!("-{},", flag.short) };
+            lines.push(format!("  {} --{}  {}", short, flag.long, flag.desc));
+        }
+        for block in blocks {
+            lines.push(String::new());
+            lines.push(format!("```{}  {}:{}", block.script.executor, block.script.file, block.script.line));
+            lines.extend(block.script.source.lines().map(String::from));
+            lines.push(String::from("```"));
+        }
+        lines
+    }
+}
+
+const HELP: &str = "j/k move  enter run  c clear  q quit";
+
+fn add_rows(nodes: &[Command], depth: usize, rows: &mut Vec<Row>) {
+    for node in nodes {
+        if rows.last().is_some_and(|row: &Row| row.path == node.path) {
+            continue;
+        }
+        let mut label = if node.name.is_empty() { format!("{} block", node.script.executor) } else { node.name.clone() };
+        for arg in &node.required_args {
+            label.push_str(&format!(" <{}>", arg.name));
+        }
+        rows.push(Row { depth, label, path: node.path.clone() });
+        add_rows(&node.subcommands, depth + 1, rows);
+    }
+}
+
+// Opens the document in a full-screen browser: the command tree on the left, the selected
+// command on the right and what its run prints in a pane below.
+pub fn browse(path: &str, interpreters: &HashMap<String, String>) -> Result<()> {
+    let text = document::read(path)?;
+    let mut parser = Rmd::with_path(text, path.to_string());
+    let commands = parser.parse();
+    if let Some(err) = parser.errors().first() {
+        return Err(Error::new(ErrorKind::InvalidData, err.clone()));
+    }
+    let fixtures = parser.fixtures().to_vec();
+    // there is no asking for a secret once the screen is ours
+    let secrets = secrets::resolve(&commands, &HashMap::new())?;
+    let mut browser = Browser::new(commands);
+
+    // stderr is held back while the screen is ours, and printed once it is not
+    let rb = RustBox::init(InitOptions { buffer_stderr: true, input_mode: InputMode::Esc, output_mode: OutputMode::EightBit })
+        .map_err(|err| Error::other(format!("cannot open the terminal: {}", err)))?;
+    let (output_sender, output) = channel::<Vec<u8>>();
+    let (done_sender, done) = channel();
+
+    loop {
+        while let Ok(bytes) = output.try_recv() {
+            browser.push_output(&bytes);
+        }
+        if let Ok(summary) = done.try_recv() {
+            while let Ok(bytes) = output.try_recv() {
+                browser.push_output(&bytes);
+            }
+            browser.finish(summary);
+        }
+        draw(&rb, &browser);
+
+        let key = match rb.peek_event(REFRESH, true) {
+            Ok(Event::KeyEventRaw(_, code, ch)) => key_of(code, ch),
+            _ => None,
+        };
+        let action = match key {
+            Some(key) => browser.handle(key),
+            None => Action::Nothing,
+        };
+        match action {
+            Action::Quit => return Ok(()),
+            Action::Run(blocks) => {
+                start(blocks, &fixtures, interpreters, &secrets, output_sender.clone(), done_sender.clone())
+            }
+            Action::Nothing => {}
+        }
+    }
+}
+
+// runs the blocks on a thread of their own, so the screen keeps up with their output
+fn start(blocks: Vec<Command>, fixtures: &[Fixture], interpreters: &HashMap<String, String>,
+         secrets: &HashMap<String, String>, output: Sender<Vec<u8>>, done: Sender<String>) {
+    let fixtures = fixtures.to_vec();
+    let interpreters = interpreters.clone();
+    let secrets = secrets.clone();
+    thread::spawn(move || {
+        let workdir = if fixtures.is_empty() { Ok(None) } else { workdir::create(&fixtures).map(Some) };
+        let summary = match workdir {
+            Ok(workdir) => {
+                let mut ctx = RunContext {
+                    workdir: workdir.as_ref().map(|dir| dir.path().to_path_buf()),
+                    interpreters,
+                    output: Some(output),
+                    secrets,
+                    ..RunContext::default()
+                };
+                match runner::run_commands(blocks, &mut ctx) {
+                    Ok(report) => summarize(&report.blocks.iter().map(|block| block.outcome.clone()).collect::<Vec<_>>()),
+                    Err(err) => format!("error: {}", err),
+                }
+            }
+            Err(err) => format!("error: {}", err),
+        };
+        let _ = done.send(summary);
+    });
+}
+
+fn summarize(outcomes: &[Outcome]) -> String {
+    match outcomes.iter().find_map(|outcome| match outcome {
+        Outcome::Failed { reason, .. } => Some(reason),
+        _ => None,
+    }) {
+        Some(reason) => format!("failed: {}", reason),
+        None => format!("passed ({} block(s))", outcomes.len()),
+    }
+}
+
+fn key_of(code: u16, ch: u32) -> Option<UiKey> {
+    if code == 0 {
+        return std::char::from_u32(ch).map(UiKey::Char);
+    }
+    match Key::from_special_code(code)? {
+        Key::Enter => Some(UiKey::Enter),
+        Key::Esc => Some(UiKey::Esc),
+        Key::Backspace | Key::Ctrl('h') => Some(UiKey::Backspace),
+        Key::Char(c) => Some(UiKey::Char(c)),
+        Key::Up => Some(UiKey::Up),
+        Key::Down => Some(UiKey::Down),
+        _ => None,
+    }
+}
+
+fn draw(rb: &RustBox, browser: &Browser) {
+    let (width, height) = (rb.width(), rb.height());
+    if width < 20 || height < 8 {
+        return;
+    }
+    rb.clear();
+    let output_height = (height / 3).max(4);
+    let top_height = height - output_height - 2;
+    let left_width = (width / 3).min(40);
+
+    // the command tree, scrolled to keep the selection in view
+    let first = browser.selected.saturating_sub(top_height.saturating_sub(1));
+    for (y, (index, row)) in browser.rows.iter().enumerate().skip(first).take(top_height).enumerate() {
+        let text = format!("{}{}", "  ".repeat(row.depth), row.label);
+        let (fg, bg) = if index == browser.selected { (Color::Black, Color::Cyan) } else { (Color::White, Color::Default) };
+        print_clipped(rb, 0, y, left_width - 1, &text, fg, bg);
+    }
+    for y in 0..top_height {
+        rb.print_char(left_width - 1, y, Style::empty(), Color::White, Color::Default, '│');
+    }
+    for (y, line) in browser.details().iter().take(top_height).enumerate() {
+        print_clipped(rb, left_width + 1, y, width - left_width - 1, line, Color::White, Color::Default);
+    }
+
+    let rule = "─".repeat(width);
+    rb.print(0, top_height, Style::empty(), Color::White, Color::Default, &rule);
+    let mut lines: Vec<&str> = browser.output.iter().map(String::as_str).collect();
+    if !browser.partial.is_empty() {
+        lines.push(&browser.partial);
+    }
+    let skip = lines.len().saturating_sub(output_height);
+    for (y, line) in lines.iter().skip(skip).enumerate() {
+        print_clipped(rb, 0, top_height + 1 + y, width, line, Color::White, Color::Default);
+    }
+
+    print_clipped(rb, 0, height - 1, width, &browser.status, Color::Black, Color::White);
+    rb.present();
+}
+
+fn print_clipped(rb: &RustBox, x: usize, y: usize, width: usize, text: &str, fg: Color, bg: Color) {
+    let clipped: String = text.replace('\t', "    ").chars().take(width).collect();
+    let padded = format!("{:<width$}", clipped, width = width);
+    rb.print(x, y, Style::empty(), fg, bg, &padded);
+}
+
+#[cfg(test)]
+mod test {
+    use crate::rmd::browser::{Action, Browser, UiKey};
+    use crate::rmd::Rmd;
+
+    const DOC: &str = "# Ops
+
+## deploy (env)
+
+> Deploys the current build
+
+```sh on-failure=undo
+echo deploying to $env
+```
+
+### rollback
+
+```sh
+echo rolling back
+```
+
+## undo
+
+```sh id=undo
+echo undoing
+```
+";
+
+    fn browser() -> Browser {
+        Browser::new(Rmd::new(String::from(DOC)).parse())
+    }
+
+    #[test]
+    fn should_list_commands_and_show_the_selected_one() {
+        let mut browser = browser();
+        let labels: Vec<(usize, &str)> = browser.rows.iter().map(|row| (row.depth, row.label.as_str())).collect();
+        assert_eq!(vec![(0, "Ops"), (1, "deploy <env>"), (2, "rollback"), (1, "undo")], labels);
+        assert_eq!(vec!["Ops", "", "groups:", "  deploy <env>", "  undo"], browser.details());
+
+        browser.handle(UiKey::Down);
+        let details = browser.details();
+        assert_eq!("Ops / deploy", details[0]);
+        assert!(details.contains(&String::from("Deploys the current build")));
+        assert!(details.contains(&String::from("echo deploying to $env")));
+
+        browser.handle(UiKey::Down);
+        browser.handle(UiKey::Down);
+        browser.handle(UiKey::Down);
+        assert_eq!(3, browser.selected);
+        assert!(matches!(browser.handle(UiKey::Char('q')), Action::Quit));
+    }
+
+    #[test]
+    fn should_ask_for_arguments_before_running() {
+        let mut browser = browser();
+        assert!(matches!(browser.handle(UiKey::Enter), Action::Nothing));
+        assert!(!browser.running);
+
+        browser.handle(UiKey::Down);
+        assert!(matches!(browser.handle(UiKey::Enter), Action::Nothing));
+        for c in "prodd".chars() {
+            browser.handle(UiKey::Char(c));
+        }
+        browser.handle(UiKey::Backspace);
+        assert_eq!("<env>: prod", browser.status);
+
+        match browser.handle(UiKey::Enter) {
+            Action::Run(blocks) => {
+                assert_eq!("prod", blocks[0].required_args[0].val);
+                assert_eq!(Some("undo"), blocks[1].script.attr("id"));
+            }
+            action => panic!("expected a run, got {:?}", action),
+        }
+        assert!(browser.running);
+        assert!(matches!(browser.handle(UiKey::Char('r')), Action::Nothing));
+        assert_eq!("a command is still running", browser.status);
+    }
+
+    #[test]
+    fn should_collect_output_into_lines() {
+        let mut browser = browser();
+        browser.push_output(b"one\ntw");
+        browser.push_output(b"o\r\nthree");
+        assert_eq!(vec!["one", "two"], browser.output);
+        assert_eq!("three", browser.partial);
+
+        browser.finish(String::from("passed (1 block(s))"));
+        assert_eq!(vec!["one", "two", "three", "passed (1 block(s))"], browser.output);
+        assert!(!browser.running);
+    }
+}
@@ -0,0 +1,151 @@
+use std::{env, fs};
+use std::io::Result;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use crate::rmd::lang::content_hash;
+
+pub fn data_dir() -> PathBuf {
+    if let Some(dir) = env::var_os("RINPUT_DATA_DIR") {
+        return PathBuf::from(dir);
+    }
+    if let Some(dir) = env::var_os("XDG_DATA_HOME") {
+        return PathBuf::from(dir).join("rinput");
+    }
+    match env::var_os("HOME") {
+        Some(home) => PathBuf::from(home).join(".local").join("share").join("rinput"),
+        None => env::temp_dir().join("com.phodal.rinput"),
+    }
+}
+
+pub fn toolchain_version(tool: &str) -> String {
+    match process::Command::new(tool).arg("--version").output() {
+        Ok(output) if output.status.success() => String::from_utf8_lossy(&output.stdout).trim().to_string(),
+        _ => String::from("unknown"),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CacheEntry {
+    pub lang: String,
+    pub key: String,
+    pub binary: PathBuf,
+    pub size: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct BuildCache {
+    dir: PathBuf,
+}
+
+impl BuildCache {
+    pub fn new() -> BuildCache {
+        BuildCache::at(data_dir().join("cache"))
+    }
+
+    pub fn at(dir: PathBuf) -> BuildCache {
+        BuildCache { dir }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    // every input that can change the produced binary has to be part of the key
+    pub fn key(parts: &[&str]) -> String {
+        content_hash(&parts.join("\u{0}"))
+    }
+
+    pub fn lookup(&self, lang: &str, key: &str) -> Option<PathBuf> {
+        let entry = self.dir.join(lang).join(key);
+        fs::read_dir(&entry).ok()?
+            .filter_map(|file| file.ok())
+            .map(|file| file.path())
+            .find(|path| path.is_file())
+    }
+
+    pub fn store(&self, lang: &str, key: &str, binary: &Path) -> Result<PathBuf> {
+        let entry = self.dir.join(lang).join(key);
+        fs::create_dir_all(&entry)?;
+
+        let target = entry.join(binary.file_name().unwrap_or_default());
+        fs::copy(binary, &target)?;
+        Ok(target)
+    }
+
+    pub fn entries(&self) -> Result<Vec<CacheEntry>> {
+        let mut entries = vec![];
+        if !self.dir.exists() {
+            return Ok(entries);
+        }
+
+        for lang in fs::read_dir(&self.dir)? {
+            let lang = lang?;
+            if !lang.path().is_dir() {
+                continue;
+            }
+            for key in fs::read_dir(lang.path())? {
+                let key = key?;
+                for binary in fs::read_dir(key.path())? {
+                    let binary = binary?;
+                    entries.push(CacheEntry {
+                        lang: lang.file_name().to_string_lossy().to_string(),
+                        key: key.file_name().to_string_lossy().to_string(),
+                        binary: binary.path(),
+                        size: binary.metadata()?.len(),
+                    });
+                }
+            }
+        }
+
+        entries.sort_by(|a, b| (&a.lang, &a.key).cmp(&(&b.lang, &b.key)));
+        Ok(entries)
+    }
+
+    pub fn clean(&self) -> Result<usize> {
+        let count = self.entries()?.len();
+        if self.dir.exists() {
+            fs::remove_dir_all(&self.dir)?;
+        }
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use tempfile::tempdir;
+
+    use crate::rmd::cache::BuildCache;
+
+    #[test]
+    fn should_store_and_lookup_binary() {
+        let root = tempdir().unwrap();
+        let cache = BuildCache::at(root.path().join("cache"));
+        let binary = root.path().join("demo");
+        fs::write(&binary, "binary").unwrap();
+
+        let key = BuildCache::key(&["fn main() {}", "rustc 1.0"]);
+        assert_eq!(None, cache.lookup("rust", &key));
+
+        let stored = cache.store("rust", &key, &binary).unwrap();
+        assert_eq!(Some(stored), cache.lookup("rust", &key));
+
+        let entries = cache.entries().unwrap();
+        assert_eq!(1, entries.len());
+        assert_eq!("rust", entries[0].lang);
+        assert_eq!(6, entries[0].size);
+
+        assert_eq!(1, cache.clean().unwrap());
+        assert_eq!(0, cache.entries().unwrap().len());
+    }
+
+    #[test]
+    fn should_key_by_every_input() {
+        let debug = BuildCache::key(&["fn main() {}", "colored=1.8", "rustc 1.0", "debug"]);
+        let release = BuildCache::key(&["fn main() {}", "colored=1.8", "rustc 1.0", "release"]);
+
+        assert_ne!(debug, release);
+    }
+}
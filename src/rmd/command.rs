@@ -2,6 +2,8 @@
 pub struct Command {
     pub cmd_level: u8,
     pub name: String,
+    // the names of the headings the command sits under, ending with its own
+    pub path: Vec<String>,
     pub desc: String,
     pub script: Script,
     pub subcommands: Vec<Command>,
@@ -14,6 +16,7 @@ impl Command {
         Self {
             cmd_level,
             name: "".to_string(),
+            path: vec![],
             desc: "".to_string(),
             script: Script::new(),
             subcommands: vec![],
@@ -24,7 +27,7 @@ impl Command {
 
     pub fn build(mut self) -> Self {
         // Auto add common flags like verbose for commands that have a script source
-        if !self.script.source.is_empty() {
+        if !self.script.source.is_empty() && !self.option_flags.iter().any(|flag| flag.name == "verbose") {
             self.option_flags.push(OptionFlag {
                 name: "verbose".to_string(),
                 desc: "Sets the level of verbosity".to_string(),
@@ -40,12 +43,21 @@ impl Command {
     }
 }
 
+use std::collections::HashMap;
+
 #[derive(Debug, Clone)]
 pub struct Script {
     // The executor to run the source with
     pub executor: String, // shell, node, ruby, python, etc...
     // The script source to execute
     pub source: String,
+    // Where the source lives: the document path and the line of its first source line
+    pub file: String,
+    pub line: usize,
+    // `key=value` attributes from the info string, on top of the document's front matter
+    pub attrs: HashMap<String, String>,
+    // variables the `<!-- rinput: env FOO=bar -->` directives before the block set for it
+    pub env: Vec<(String, String)>,
 }
 
 impl Script {
@@ -53,14 +65,40 @@ impl Script {
         Self {
             executor: "".to_string(),
             source: "".to_string(),
+            file: "".to_string(),
+            line: 0,
+            attrs: HashMap::new(),
+            env: vec![],
         }
     }
 
+    pub fn attr(&self, key: &str) -> Option<&str> {
+        self.attrs.get(key).map(|value| value.as_str())
+    }
+
     pub fn has_script(&self) -> bool {
         self.source != String::from("") && self.executor != String::from("")
     }
 }
 
+// A ```file path=...``` block, written into the run's working directory before any block runs
+#[derive(Debug, Clone)]
+pub struct Fixture {
+    pub path: String,
+    pub content: String,
+    pub line: usize,
+}
+
+impl Fixture {
+    pub fn new(path: String, line: usize) -> Self {
+        Self {
+            path,
+            content: "".to_string(),
+            line,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct RequiredArg {
     pub name: String,
@@ -0,0 +1,536 @@
+use std::io::{Error, ErrorKind, Result};
+
+use crate::rmd::command::{Command, OptionFlag};
+use crate::rmd::invoke::shadowed;
+use crate::rmd::outline::{command_tree, command_word, commands_root};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+impl Shell {
+    pub fn from_name(name: &str) -> Result<Shell> {
+        match name {
+            "bash" => Ok(Shell::Bash),
+            "zsh" => Ok(Shell::Zsh),
+            "fish" => Ok(Shell::Fish),
+            _ => Err(Error::new(ErrorKind::InvalidInput, format!("no completions for `{}`, expected bash, zsh or fish", name))),
+        }
+    }
+}
+
+// What can follow one command path: its subcommands, its flags and how many arguments it takes.
+#[derive(Debug, Default)]
+struct Node {
+    // the command words leading here, space separated; empty for the top level
+    key: String,
+    children: Vec<(String, String)>,
+    flags: Vec<OptionFlag>,
+    args: Vec<String>,
+}
+
+impl Node {
+    fn flag_words(&self) -> Vec<(String, &OptionFlag)> {
+        let mut words = vec![];
+        for flag in &self.flags {
+            if !flag.long.is_empty() {
+                words.push((format!("--{}", flag.long), flag));
+            }
+            if !flag.short.is_empty() {
+                words.push((format!("-{}", flag.short), flag));
+            }
+        }
+        words
+    }
+
+    fn value_flags(&self) -> Vec<String> {
+        self.flag_words().into_iter().filter(|(_, flag)| flag.takes_value).map(|(word, _)| word).collect()
+    }
+}
+
+// One node per command path. Blocks sharing a heading share a node, with their flags merged.
+// A top-level command a builtin shadows is left out, it takes `rinput -- <command>` to reach.
+fn table(commands: &[Command], builtins: &[(&str, &str)]) -> Vec<Node> {
+    let children = builtins.iter().map(|(name, desc)| (name.to_string(), desc.to_string())).collect();
+    let mut nodes = vec![Node { children, ..Node::default() }];
+    let names: Vec<&str> = builtins.iter().map(|(name, _)| *name).collect();
+    let hidden = shadowed(commands, &names);
+    let tree = command_tree(commands);
+    let roots: Vec<Command> = commands_root(&tree).iter()
+        .filter(|cmd| !hidden.contains(&command_word(&cmd.name)))
+        .cloned()
+        .collect();
+    add_nodes(&mut nodes, "", &roots);
+    nodes
+}
+
+fn add_nodes(nodes: &mut Vec<Node>, parent: &str, commands: &[Command]) {
+    for cmd in commands {
+        let word = command_word(&cmd.name);
+        if word.is_empty() {
+            continue;
+        }
+        let key = if parent.is_empty() { word.clone() } else { format!("{} {}", parent, word) };
+
+        let parent_node = nodes.iter_mut().find(|node| node.key == parent).unwrap();
+        if !parent_node.children.iter().any(|(child, _)| *child == word) {
+            parent_node.children.push((word, first_line(&cmd.desc)));
+        }
+        let index = match nodes.iter().position(|node| node.key == key) {
+            Some(index) => index,
+            None => {
+                nodes.push(Node { key: key.clone(), ..Node::default() });
+                nodes.len() - 1
+            }
+        };
+        let node = &mut nodes[index];
+        for flag in &cmd.option_flags {
+            if !node.flags.iter().any(|known| known.name == flag.name) {
+                node.flags.push(flag.clone());
+            }
+        }
+        if node.args.is_empty() {
+            node.args = cmd.required_args.iter().map(|arg| arg.name.clone()).collect();
+        }
+
+        add_nodes(nodes, &key, &cmd.subcommands);
+    }
+}
+
+fn first_line(desc: &str) -> String {
+    desc.lines().next().unwrap_or("").trim().to_string()
+}
+
+// The candidates for the word after `words`, the ones typed after `rinput`, as (word, description).
+// Nothing comes back where an argument or a flag's value goes, so the shell completes files there.
+pub fn complete(commands: &[Command], builtins: &[(&str, &str)], words: &[String]) -> Vec<(String, String)> {
+    // after `--` only the runbook's commands are left
+    if let Some((first, rest)) = words.split_first() {
+        if first == "--" {
+            return complete(commands, &[], rest);
+        }
+    }
+    let nodes = table(commands, builtins);
+    let mut node = &nodes[0];
+    let mut positional = 0;
+    let mut value_next = false;
+    for word in words {
+        if value_next {
+            value_next = false;
+        } else if word.starts_with('-') {
+            value_next = node.value_flags().contains(word);
+        } else if node.children.iter().any(|(child, _)| child == word) {
+            let key = if node.key.is_empty() { word.clone() } else { format!("{} {}", node.key, word) };
+            node = nodes.iter().find(|node| node.key == key).unwrap_or(node);
+            positional = 0;
+        } else {
+            positional += 1;
+        }
+    }
+
+    if value_next || positional < node.args.len() {
+        return vec![];
+    }
+    let mut candidates = node.children.clone();
+    for (word, flag) in node.flag_words() {
+        candidates.push((word, flag.desc.clone()));
+    }
+    candidates
+}
+
+// A completion script with the document's commands written into it.
+pub fn static_script(shell: Shell, commands: &[Command], builtins: &[(&str, &str)], source: &str) -> String {
+    let nodes = table(commands, builtins);
+    match shell {
+        Shell::Bash => bash_script(&nodes, source),
+        Shell::Zsh => zsh_script(&nodes, source),
+        Shell::Fish => fish_script(&nodes, source),
+    }
+}
+
+// A completion script that asks `rinput __complete` each time, for whichever document is at hand.
+pub fn dynamic_script(shell: Shell) -> String {
+    match shell {
+        Shell::Bash => String::from(r#"# bash completion for rinput
+_rinput() {
+    local cur="${COMP_WORDS[COMP_CWORD]}"
+    COMPREPLY=($(compgen -W "$(rinput __complete -- "${COMP_WORDS[@]:1:COMP_CWORD-1}" 2>/dev/null | cut -f1)" -- "$cur"))
+}
+complete -o default -F _rinput rinput
+"#),
+        Shell::Zsh => String::from(r#"#compdef rinput
+# zsh completion for rinput
+
+_rinput() {
+    local -a candidates
+    candidates=(${(f)"$(rinput __complete -- "${(@)words[2,CURRENT-1]}" 2>/dev/null)"})
+    if (( ${#candidates} == 0 )); then
+        _files
+        return
+    fi
+    candidates=(${candidates//$'\t'/:})
+    _describe 'rinput' candidates
+}
+
+_rinput "$@"
+"#),
+        Shell::Fish => String::from(r#"# fish completion for rinput
+complete -c rinput -a '(rinput __complete -- (commandline -opc)[2..-1] 2>/dev/null)'
+"#),
+    }
+}
+
+fn bash_script(nodes: &[Node], source: &str) -> String {
+    let mut children = String::new();
+    let mut flags = String::new();
+    let mut value_flags = String::new();
+    let mut args = String::new();
+    for node in nodes {
+        let key = sh_quote(&node.key);
+        if !node.children.is_empty() {
+            let words: Vec<&str> = node.children.iter().map(|(word, _)| word.as_str()).collect();
+            children.push_str(&format!("        {}) echo {} ;;\n", key, sh_quote(&words.join(" "))));
+        }
+        let words: Vec<String> = node.flag_words().into_iter().map(|(word, _)| word).collect();
+        if !words.is_empty() {
+            flags.push_str(&format!("        {}) echo {} ;;\n", key, sh_quote(&words.join(" "))));
+        }
+        if !node.value_flags().is_empty() {
+            value_flags.push_str(&format!("        {}) echo {} ;;\n", key, sh_quote(&node.value_flags().join(" "))));
+        }
+        if !node.args.is_empty() {
+            args.push_str(&format!("        {}) echo {} ;;\n", key, node.args.len()));
+        }
+    }
+
+    format!(r#"# bash completion for rinput, generated from {source}
+_rinput_children() {{
+    case "$1" in
+{children}    esac
+}}
+_rinput_flags() {{
+    case "$1" in
+{flags}    esac
+}}
+_rinput_value_flags() {{
+    case "$1" in
+{value_flags}    esac
+}}
+_rinput_args() {{
+    case "$1" in
+{args}        *) echo 0 ;;
+    esac
+}}
+_rinput() {{
+    local cur="${{COMP_WORDS[COMP_CWORD]}}" node="" positional=0 skip=0 word i
+    for ((i = 1; i < COMP_CWORD; i++)); do
+        word="${{COMP_WORDS[i]}}"
+        if ((skip)); then
+            skip=0
+        elif [[ $word == -* ]]; then
+            [[ " $(_rinput_value_flags "$node") " == *" $word "* ]] && skip=1
+        elif [[ " $(_rinput_children "$node") " == *" $word "* ]]; then
+            node="${{node:+$node }}$word"
+            positional=0
+        else
+            positional=$((positional + 1))
+        fi
+    done
+    # a flag's value or an argument: left to the default completion
+    if ((skip)) || {{ [[ $cur != -* ]] && ((positional < $(_rinput_args "$node"))); }}; then
+        COMPREPLY=()
+        return
+    fi
+    COMPREPLY=($(compgen -W "$(_rinput_children "$node") $(_rinput_flags "$node")" -- "$cur"))
+}}
+complete -o default -F _rinput rinput
+"#, source = source, children = children, flags = flags, value_flags = value_flags, args = args)
+}
+
+fn zsh_script(nodes: &[Node], source: &str) -> String {
+    let described = |word: &str, desc: &str| {
+        if desc.is_empty() { sh_quote(word) } else { sh_quote(&format!("{}:{}", word, desc)) }
+    };
+    let mut children = String::new();
+    let mut flags = String::new();
+    let mut value_flags = String::new();
+    let mut args = String::new();
+    for node in nodes {
+        let key = sh_quote(&node.key);
+        if !node.children.is_empty() {
+            let words: Vec<String> = node.children.iter().map(|(word, desc)| described(word, desc)).collect();
+            children.push_str(&format!("        {}) reply=({}) ;;\n", key, words.join(" ")));
+        }
+        let words: Vec<String> = node.flag_words().into_iter().map(|(word, flag)| described(&word, &flag.desc)).collect();
+        if !words.is_empty() {
+            flags.push_str(&format!("        {}) reply=({}) ;;\n", key, words.join(" ")));
+        }
+        if !node.value_flags().is_empty() {
+            value_flags.push_str(&format!("        {}) reply=({}) ;;\n", key, node.value_flags().join(" ")));
+        }
+        if !node.args.is_empty() {
+            let names: Vec<String> = node.args.iter().map(|name| sh_quote(name)).collect();
+            args.push_str(&format!("        {}) reply=({}) ;;\n", key, names.join(" ")));
+        }
+    }
+
+    format!(r#"#compdef rinput
+# zsh completion for rinput, generated from {source}
+
+_rinput_children() {{
+    case "$1" in
+{children}        *) reply=() ;;
+    esac
+}}
+_rinput_flags() {{
+    case "$1" in
+{flags}        *) reply=() ;;
+    esac
+}}
+_rinput_value_flags() {{
+    case "$1" in
+{value_flags}        *) reply=() ;;
+    esac
+}}
+_rinput_args() {{
+    case "$1" in
+{args}        *) reply=() ;;
+    esac
+}}
+
+_rinput() {{
+    local node="" word
+    local -a reply children flags
+    local -i positional=0 skip=0 i
+    for ((i = 2; i < CURRENT; i++)); do
+        word=${{words[i]}}
+        if ((skip)); then
+            skip=0
+        elif [[ $word == -* ]]; then
+            _rinput_value_flags "$node"
+            (( ${{reply[(Ie)$word]}} )) && skip=1
+        else
+            _rinput_children "$node"
+            if (( ${{reply[(Ie)$word]}} || ${{reply[(I)${{(b)word}}:*]}} )); then
+                node="${{node:+$node }}$word"
+                positional=0
+            else
+                positional+=1
+            fi
+        fi
+    done
+
+    if ((skip)); then
+        _files
+        return
+    fi
+    _rinput_args "$node"
+    if (( positional < ${{#reply}} )) && [[ $PREFIX != -* ]]; then
+        _message "<${{reply[positional + 1]}}>"
+        return
+    fi
+    _rinput_children "$node"
+    children=("${{reply[@]}}")
+    _rinput_flags "$node"
+    flags=("${{reply[@]}}")
+    _describe 'rinput' children -- flags
+}}
+
+_rinput "$@"
+"#, source = source, children = children, flags = flags, value_flags = value_flags, args = args)
+}
+
+fn fish_script(nodes: &[Node], source: &str) -> String {
+    let mut children = String::new();
+    let mut value_flags = String::new();
+    let mut completions = String::new();
+    for node in nodes {
+        let key = fish_quote(&node.key);
+        if !node.children.is_empty() {
+            let words: Vec<&str> = node.children.iter().map(|(word, _)| word.as_str()).collect();
+            children.push_str(&format!("        case {}\n            printf '%s\\n' {}\n", key, words.join(" ")));
+        }
+        if !node.value_flags().is_empty() {
+            value_flags.push_str(&format!("        case {}\n            printf '%s\\n' -- {}\n", key, node.value_flags().join(" ")));
+        }
+
+        for (word, desc) in &node.children {
+            completions.push_str(&format!("complete -c rinput -n \"__rinput_at {} {}\" -a {}", key, node.args.len(), word));
+            if !desc.is_empty() {
+                completions.push_str(&format!(" -d {}", fish_quote(desc)));
+            }
+            completions.push('\n');
+        }
+        for flag in &node.flags {
+            completions.push_str(&format!("complete -c rinput -n \"__rinput_at {} 0\"", key));
+            if !flag.short.is_empty() {
+                completions.push_str(&format!(" -s {}", flag.short));
+            }
+            if !flag.long.is_empty() {
+                completions.push_str(&format!(" -l {}", flag.long));
+            }
+            if flag.takes_value {
+                completions.push_str(" -r");
+            }
+            if !flag.desc.is_empty() {
+                completions.push_str(&format!(" -d {}", fish_quote(&flag.desc)));
+            }
+            completions.push('\n');
+        }
+    }
+
+    format!(r#"# fish completion for rinput, generated from {source}
+function __rinput_children
+    switch "$argv[1]"
+{children}    end
+end
+
+function __rinput_value_flags
+    switch "$argv[1]"
+{value_flags}    end
+end
+
+# whether the command line has reached the command path $argv[1], past at least $argv[2] arguments
+function __rinput_at
+    set -l node ''
+    set -l positional 0
+    set -l skip 0
+    for word in (commandline -opc)[2..-1]
+        if test $skip -eq 1
+            set skip 0
+        else if string match -q -- '-*' $word
+            contains -- $word (__rinput_value_flags $node); and set skip 1
+        else if contains -- $word (__rinput_children $node)
+            set node (string trim -- "$node $word")
+            set positional 0
+        else
+            set positional (math $positional + 1)
+        end
+    end
+    test "$node" = "$argv[1]"; and test $skip -eq 0; and test $positional -ge $argv[2]
+end
+
+complete -c rinput -f
+{completions}"#, source = source, children = children, value_flags = value_flags, completions = completions)
+}
+
+fn sh_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+fn fish_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\\', r"\\").replace('\'', r"\'"))
+}
+
+#[cfg(test)]
+mod test {
+    use std::process::Command as Process;
+
+    use crate::rmd::completions::{complete, static_script, Shell};
+    use crate::rmd::Rmd;
+
+    const DOC: &str = "# Ops
+
+## deploy (env)
+
+> Deploys the current build
+
+**OPTIONS**
+* dry-run
+    * flags: -n --dry-run
+    * desc: Only print what would change
+* level
+    * flags: -l --level
+    * type: string
+
+```sh
+echo deploying to $env
+```
+
+### rollback
+
+```sh
+echo rolling back
+```
+
+## Show Status
+
+```sh
+echo ok
+```
+";
+
+    fn words(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    fn names(candidates: Vec<(String, String)>) -> Vec<String> {
+        candidates.into_iter().map(|(word, _)| word).collect()
+    }
+
+    #[test]
+    fn should_complete_commands_args_and_flags() {
+        let commands = Rmd::new(String::from(DOC)).parse();
+        let builtins = [("run", "Run a document")];
+
+        let top = complete(&commands, &builtins, &[]);
+        assert_eq!(vec!["run", "deploy", "show-status"], names(top.clone()));
+        assert_eq!("Deploys the current build", top[1].1);
+
+        assert!(complete(&commands, &builtins, &words("deploy")).is_empty());
+        let deploy = names(complete(&commands, &builtins, &words("deploy prod")));
+        assert_eq!(vec!["rollback", "--dry-run", "-n", "--level", "-l", "--verbose", "-v"], deploy);
+
+        assert!(complete(&commands, &builtins, &words("deploy prod --level")).is_empty());
+        assert_eq!(deploy, names(complete(&commands, &builtins, &words("deploy prod --level 2 -n"))));
+        assert_eq!(vec!["--verbose", "-v"], names(complete(&commands, &builtins, &words("deploy prod rollback"))));
+    }
+
+    #[test]
+    fn should_leave_shadowed_commands_to_the_escape() {
+        let commands = Rmd::new(String::from(DOC)).parse();
+        let builtins = [("run", "Run a document"), ("show-status", "Show the cache")];
+
+        let top = complete(&commands, &builtins, &[]);
+        assert_eq!(vec!["run", "show-status", "deploy"], names(top.clone()));
+        assert_eq!("Show the cache", top[1].1);
+        assert_eq!(vec!["deploy", "show-status"], names(complete(&commands, &builtins, &words("--"))));
+        assert_eq!(vec!["--verbose", "-v"], names(complete(&commands, &builtins, &words("-- deploy prod rollback"))));
+    }
+
+    #[test]
+    fn should_generate_working_bash_completion() {
+        let commands = Rmd::new(String::from(DOC)).parse();
+        let script = static_script(Shell::Bash, &commands, &[], "ops.md");
+        let probe = |line: &str| {
+            let test = format!("{}\nCOMP_WORDS=({}); COMP_CWORD=${{#COMP_WORDS[@]}}; COMP_WORDS+=(''); _rinput; echo \"${{COMPREPLY[*]}}\"",
+                               script, line);
+            let output = Process::new("bash").arg("-c").arg(test).output().unwrap();
+            String::from_utf8(output.stdout).unwrap().trim().to_string()
+        };
+
+        assert_eq!("deploy show-status", probe("rinput"));
+        assert_eq!("", probe("rinput deploy"));
+        assert_eq!("rollback --dry-run -n --level -l --verbose -v", probe("rinput deploy prod"));
+        assert_eq!("", probe("rinput deploy prod -l"));
+        assert_eq!("--verbose -v", probe("rinput deploy prod rollback"));
+    }
+
+    #[test]
+    fn should_describe_commands_for_zsh_and_fish() {
+        let commands = Rmd::new(String::from(DOC)).parse();
+
+        let zsh = static_script(Shell::Zsh, &commands, &[], "ops.md");
+        assert!(zsh.starts_with("#compdef rinput\n"));
+        assert!(zsh.contains("'') reply=('deploy:Deploys the current build' 'show-status') ;;"));
+        assert!(zsh.contains("'deploy') reply=('env') ;;"));
+
+        let fish = static_script(Shell::Fish, &commands, &[], "ops.md");
+        assert!(fish.contains("complete -c rinput -n \"__rinput_at '' 0\" -a deploy -d 'Deploys the current build'"));
+        assert!(fish.contains("complete -c rinput -n \"__rinput_at 'deploy' 1\" -a rollback"));
+        assert!(fish.contains("-s l -l level -r"));
+    }
+}
@@ -0,0 +1,385 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+
+use rinput::{validate_binding, EditorOptions};
+use rinput::rustbox::rustbox::Color;
+use yaml_rust::{Yaml, YamlLoader};
+
+use crate::rmd::executor::UNCONFIGURABLE;
+use crate::rmd::runbook::RUNBOOK_NAMES;
+
+// the first of these in ~/.config/rinput is the user's configuration
+const USER_NAMES: [&str; 4] = ["config", "config.toml", "config.yaml", "config.yml"];
+// the closest of these from the working directory upwards is the project's
+const PROJECT_NAMES: [&str; 3] = [".rinput.toml", ".rinput.yaml", ".rinput.yml"];
+
+// Everything rinput can be configured with. A configuration file sets it with keys such as
+// `editor.tab_width`, written as TOML tables or nested YAML maps.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Config {
+    // tried before rinput.md and maskfile.md when looking for a runbook
+    pub runbook_names: Vec<String>,
+    // the program to run a language with, by language: `python = "python3"`
+    pub interpreters: HashMap<String, String>,
+    pub editor: EditorOptions,
+    // the files the settings came from, lowest precedence first
+    pub sources: Vec<PathBuf>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    String(String),
+    Integer(i64),
+    Bool(bool),
+    List(Vec<String>),
+}
+
+// one `section.key = value` setting and the line it is on
+#[derive(Debug, Clone, PartialEq)]
+struct Entry {
+    key: String,
+    value: Value,
+    line: usize,
+}
+
+type Parsed<T> = std::result::Result<T, String>;
+
+impl Config {
+    // The user's configuration, then the project's, then `explicit` (`--config`); later ones win.
+    pub fn load(explicit: Option<&str>) -> Result<Config> {
+        let mut files = vec![];
+        if let Some(dir) = user_dir() {
+            files.extend(first_file(&dir, &USER_NAMES));
+        }
+        if let Ok(cwd) = env::current_dir() {
+            files.extend(cwd.ancestors().find_map(|dir| first_file(dir, &PROJECT_NAMES)));
+        }
+        if let Some(path) = explicit {
+            if !Path::new(path).is_file() {
+                return Err(Error::new(ErrorKind::NotFound, format!("--config {} is not a file", path)));
+            }
+            files.push(PathBuf::from(path));
+        }
+
+        let mut config = Config::default();
+        for file in files {
+            let text = fs::read_to_string(&file)?;
+            config.merge(&file, &text)?;
+        }
+        Ok(config)
+    }
+
+    // the names a runbook is looked for under, in order of preference
+    pub fn runbook_names(&self) -> Vec<&str> {
+        self.runbook_names.iter().map(String::as_str).chain(RUNBOOK_NAMES.iter().copied()).collect()
+    }
+
+    fn merge(&mut self, path: &Path, text: &str) -> Result<()> {
+        let yaml = path.extension().is_some_and(|ext| ext == "yaml" || ext == "yml");
+        let entries = if yaml { parse_yaml(text) } else { parse_toml(text) }
+            .map_err(|msg| unreadable(path, msg))?;
+        for entry in entries {
+            self.set(&entry).map_err(|msg| invalid(path, entry.line, msg))?;
+        }
+        self.sources.push(path.to_path_buf());
+        Ok(())
+    }
+
+    fn set(&mut self, entry: &Entry) -> Parsed<()> {
+        match entry.key.as_str() {
+            "runbook.names" => self.runbook_names = entry.list()?,
+            "editor.mode" => {
+                self.editor.normal_mode = match entry.string()?.as_str() {
+                    "standard" => false,
+                    "normal" => true,
+                    mode => return Err(format!("editor.mode is standard or normal, got `{}`", mode)),
+                }
+            }
+            "editor.tab_width" => {
+                self.editor.tab_width = match entry.integer()? {
+                    width @ 1..=16 => width as usize,
+                    width => return Err(format!("editor.tab_width is between 1 and 16, got {}", width)),
+                }
+            }
+            "colors.text" => self.editor.colors.text = entry.color()?,
+            "colors.background" => self.editor.colors.background = entry.color()?,
+            "colors.status" => self.editor.colors.status = entry.color()?,
+            key => {
+                if let Some(lang) = key.strip_prefix("executors.") {
+                    if let Some((_, instead)) = UNCONFIGURABLE.iter().find(|(name, _)| *name == lang) {
+                        return Err(format!("`{}` is never used, {}", key, instead));
+                    }
+                    self.interpreters.insert(lang.to_string(), entry.string()?);
+                } else if let Some(name) = key.strip_prefix("editor.keymap.") {
+                    let command = entry.string()?;
+                    validate_binding(name, &command)?;
+                    self.editor.keymap.retain(|(bound, _)| bound != name);
+                    self.editor.keymap.push((name.to_string(), command));
+                } else {
+                    return Err(format!("unknown key `{}`", key));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Entry {
+    fn string(&self) -> Parsed<String> {
+        match &self.value {
+            Value::String(value) => Ok(value.clone()),
+            _ => Err(format!("`{}` expects a string", self.key)),
+        }
+    }
+
+    fn integer(&self) -> Parsed<i64> {
+        match &self.value {
+            Value::Integer(value) => Ok(*value),
+            _ => Err(format!("`{}` expects a number", self.key)),
+        }
+    }
+
+    fn list(&self) -> Parsed<Vec<String>> {
+        match &self.value {
+            Value::List(values) => Ok(values.clone()),
+            Value::String(value) => Ok(vec![value.clone()]),
+            _ => Err(format!("`{}` expects a list of strings", self.key)),
+        }
+    }
+
+    // a colour name, `default`, or a number from the 256-colour palette
+    fn color(&self) -> Parsed<Color> {
+        let color = match &self.value {
+            Value::Integer(value @ 0..=255) => Color::Byte(*value as u16),
+            Value::String(name) => match name.as_str() {
+                "black" => Color::Black,
+                "red" => Color::Red,
+                "green" => Color::Green,
+                "yellow" => Color::Yellow,
+                "blue" => Color::Blue,
+                "magenta" => Color::Magenta,
+                "cyan" => Color::Cyan,
+                "white" => Color::White,
+                "default" => Color::Default,
+                _ => return Err(format!("`{}` is not a colour: `{}`", self.key, name)),
+            },
+            _ => return Err(format!("`{}` expects a colour name or a number from 0 to 255", self.key)),
+        };
+        Ok(color)
+    }
+}
+
+fn user_dir() -> Option<PathBuf> {
+    env::var_os("XDG_CONFIG_HOME").map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+        .map(|dir| dir.join("rinput"))
+}
+
+fn first_file(dir: &Path, names: &[&str]) -> Option<PathBuf> {
+    names.iter().map(|name| dir.join(name)).find(|path| path.is_file())
+}
+
+fn invalid(path: &Path, line: usize, msg: String) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("{}:{}: {}", path.display(), line, msg))
+}
+
+// what the parsers found wrong with the file as a whole, which says where itself
+fn unreadable(path: &Path, msg: String) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("{}: {}", path.display(), msg))
+}
+
+fn parse_toml(text: &str) -> Parsed<Vec<Entry>> {
+    let table: toml::Value = text.parse().map_err(|err: toml::de::Error| err.to_string())?;
+    let mut entries = vec![];
+    toml_entries(&table, "", text, &mut entries)?;
+    entries.sort_by_key(|entry| entry.line);
+    Ok(entries)
+}
+
+fn toml_entries(value: &toml::Value, key: &str, text: &str, entries: &mut Vec<Entry>) -> Parsed<()> {
+    let value = match value {
+        toml::Value::Table(table) => {
+            for (name, value) in table {
+                toml_entries(value, &join(key, name), text, entries)?;
+            }
+            return Ok(());
+        }
+        toml::Value::String(value) => Value::String(value.clone()),
+        toml::Value::Integer(value) => Value::Integer(*value),
+        toml::Value::Boolean(value) => Value::Bool(*value),
+        toml::Value::Array(items) => {
+            let items = items.iter().map(|item| item.as_str().map(String::from)).collect::<Option<Vec<String>>>();
+            Value::List(items.ok_or_else(|| format!("line {}: `{}` expects a list of strings", line_of(text, key), key))?)
+        }
+        _ => return Err(format!("line {}: unsupported value for `{}`", line_of(text, key), key)),
+    };
+    entries.push(Entry { key: key.to_string(), value, line: line_of(text, key) });
+    Ok(())
+}
+
+fn parse_yaml(text: &str) -> Parsed<Vec<Entry>> {
+    let docs = YamlLoader::load_from_str(text).map_err(|err| err.to_string())?;
+    let mut entries = vec![];
+    match docs.first() {
+        Some(map @ Yaml::Hash(_)) => yaml_entries(map, "", text, &mut entries)?,
+        Some(Yaml::Null) | None => {}
+        Some(_) => return Err(String::from("expected a map of settings")),
+    }
+    Ok(entries)
+}
+
+fn yaml_entries(value: &Yaml, key: &str, text: &str, entries: &mut Vec<Entry>) -> Parsed<()> {
+    let scalar = |value: &Yaml| match value {
+        Yaml::String(value) => Some(value.clone()),
+        Yaml::Integer(value) => Some(value.to_string()),
+        Yaml::Boolean(value) => Some(value.to_string()),
+        _ => None,
+    };
+    let value = match value {
+        Yaml::Hash(map) => {
+            for (name, value) in map {
+                let name = scalar(name).ok_or_else(|| format!("line {}: keys below `{}` must be strings", line_of(text, key), key))?;
+                yaml_entries(value, &join(key, &name), text, entries)?;
+            }
+            return Ok(());
+        }
+        // `key:` with nothing below it
+        Yaml::Null => return Ok(()),
+        Yaml::String(value) => Value::String(value.clone()),
+        Yaml::Integer(value) => Value::Integer(*value),
+        Yaml::Boolean(value) => Value::Bool(*value),
+        Yaml::Array(items) => {
+            let items = items.iter().map(scalar).collect::<Option<Vec<String>>>();
+            Value::List(items.ok_or_else(|| format!("line {}: `{}` expects a list of strings", line_of(text, key), key))?)
+        }
+        _ => return Err(format!("line {}: unsupported value for `{}`", line_of(text, key), key)),
+    };
+    entries.push(Entry { key: key.to_string(), value, line: line_of(text, key) });
+    Ok(())
+}
+
+fn join(key: &str, name: &str) -> String {
+    if key.is_empty() { name.to_string() } else { format!("{}.{}", key, name) }
+}
+
+// Neither parser keeps where a value was, so this finds the line that names each part of `key`
+// in turn: `[editor]` and then the `tab_width` below it.
+fn line_of(text: &str, key: &str) -> usize {
+    let lines: Vec<&str> = text.lines().collect();
+    let mut line = 0;
+    for part in key.split('.') {
+        if let Some(found) = (line..lines.len()).find(|&index| names(lines[index], part)) {
+            line = found;
+        }
+    }
+    line + 1
+}
+
+fn names(line: &str, part: &str) -> bool {
+    let is_key = |c: char| c.is_alphanumeric() || c == '_' || c == '-';
+    let content = line.split('#').next().unwrap_or("");
+    content.match_indices(part).any(|(index, _)| {
+        !content[..index].chars().next_back().is_some_and(is_key)
+            && !content[index + part.len()..].chars().next().is_some_and(is_key)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use rinput::rustbox::rustbox::Color;
+
+    use crate::rmd::config::Config;
+
+    const TOML: &str = r#"
+# the project's runbooks live under docs
+[runbook]
+names = ["ops.md", "docs/runbook.md"]
+
+[executors]
+python = "python3"
+sh = 'dash'
+
+[editor]
+mode = "normal"
+tab_width = 2
+keymap."ctrl-w" = "editor::save_buffer"
+
+[colors]
+status = 24
+text = "green"  # easier on the eyes
+"#;
+
+    const YAML: &str = "
+runbook:
+  names:
+    - ops.md
+    - docs/runbook.md
+executors:
+  python: python3
+  sh: 'dash'
+editor:
+  mode: normal
+  tab_width: 2
+  keymap:
+    ctrl-w: editor::save_buffer
+colors:
+  status: 24
+  text: green # easier on the eyes
+";
+
+    fn config(name: &str, text: &str) -> Config {
+        let mut config = Config::default();
+        config.merge(Path::new(name), text).unwrap();
+        config.sources.clear();
+        config
+    }
+
+    fn error(name: &str, text: &str) -> String {
+        Config::default().merge(Path::new(name), text).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn should_read_toml_and_yaml_alike() {
+        let config = config(".rinput.toml", TOML);
+        assert_eq!(vec!["ops.md", "docs/runbook.md", "rinput.md", "maskfile.md"], config.runbook_names());
+        assert_eq!(Some(&String::from("python3")), config.interpreters.get("python"));
+        assert_eq!(Some(&String::from("dash")), config.interpreters.get("sh"));
+        assert!(config.editor.normal_mode);
+        assert_eq!(2, config.editor.tab_width);
+        assert_eq!(vec![(String::from("ctrl-w"), String::from("editor::save_buffer"))], config.editor.keymap);
+        assert_eq!(Color::Byte(24), config.editor.colors.status);
+        assert_eq!(Color::Green, config.editor.colors.text);
+
+        assert_eq!(config, self::config(".rinput.yaml", YAML));
+    }
+
+    #[test]
+    fn should_let_later_files_win() {
+        let mut config = Config::default();
+        config.merge(Path::new("config"), "[editor]\ntab_width = 8\nmode = \"normal\"\n").unwrap();
+        config.merge(Path::new(".rinput.yml"), "editor:\n  tab_width: 2\n").unwrap();
+
+        assert_eq!(2, config.editor.tab_width);
+        assert!(config.editor.normal_mode);
+        assert_eq!(vec![Path::new("config"), Path::new(".rinput.yml")], config.sources);
+    }
+
+    #[test]
+    fn should_reject_unknown_keys_and_bad_values() {
+        assert_eq!("config:3: unknown key `editor.tabwidth`", error("config", "[editor]\nmode = \"standard\"\ntabwidth = 2\n"));
+        assert_eq!("c.yaml:2: unknown key `editor.tabwidth`", error("c.yaml", "editor:\n  tabwidth: 2\n"));
+        assert_eq!("config:1: `editor.tab_width` expects a number", error("config", "editor.tab_width = \"2\"\n"));
+        assert_eq!("config:1: editor.mode is standard or normal, got `emacs`", error("config", "editor.mode = \"emacs\"\n"));
+        assert_eq!("config: invalid TOML value, did you mean to use a quoted string? at line 1 column 15", error("config", "editor.mode = yes\n"));
+        assert!(error("c.yaml", "editor:\n  mode: [normal\n").starts_with("c.yaml: "));
+        assert_eq!("c.yaml:3: `executors.js` is never used, set `executors.node`", error("c.yaml", "executors:\n  python: python3\n  js: bun\n"));
+        assert_eq!("config:2: `executors.rust` is never used, rust blocks are built with cargo", error("config", "[executors]\nrust = \"rustc\"\n"));
+        assert!(error("config", "[editor.keymap]\nctrl-w = \"buffer::move_cursor\"\n").starts_with("config:2: cannot bind `buffer::move_cursor`"));
+        assert!(error("config", "[editor.keymap]\nhyper-w = \"editor::quit\"\n").starts_with("config:2: unknown key `hyper-w`"));
+    }
+}
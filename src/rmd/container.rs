@@ -0,0 +1,153 @@
+use std::env;
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+use std::process;
+
+use tempfile::TempDir;
+
+use crate::rmd::command::Script;
+use crate::rmd::lang::find_executable;
+
+// where the block's script is mounted inside the container
+const MOUNT_POINT: &str = "/rinput";
+// where the run's working directory is mounted, writable
+const WORKDIR_MOUNT_POINT: &str = "/work";
+
+pub struct ContainerExec {
+    runtime: PathBuf,
+    image: String,
+}
+
+impl ContainerExec {
+    // `RINPUT_CONTAINER_RUNTIME` wins, so a stand-in script can take the place of a real runtime
+    pub fn new(image: String) -> Result<ContainerExec> {
+        let runtime = env::var_os("RINPUT_CONTAINER_RUNTIME")
+            .map(PathBuf::from)
+            .or_else(|| find_executable("podman"))
+            .or_else(|| find_executable("docker"));
+
+        match runtime {
+            Some(runtime) => Ok(ContainerExec::with_runtime(runtime, image)),
+            None => {
+                let msg = format!("image={} needs podman or docker, and neither is on the PATH", image);
+                Err(Error::new(ErrorKind::NotFound, msg))
+            }
+        }
+    }
+
+    pub fn with_runtime(runtime: PathBuf, image: String) -> ContainerExec {
+        ContainerExec { runtime, image }
+    }
+
+    // The returned directory holds the mounted script and has to outlive the child. Secrets are
+    // passed by name, so their values stay off the runtime's command line.
+    pub fn command(&self, script: &Script, envs: &[(String, String)], secrets: &[(String, String)],
+                   runtime_args: &[String], workdir: Option<&Path>) -> Result<(process::Command, TempDir)> {
+        let (file_name, interpreter) = interpreter_for(&script.executor);
+        let dir = tempfile::Builder::new().prefix("rinput-container").tempdir()?;
+        fs::write(dir.path().join(file_name), &script.source)?;
+
+        let mut child = process::Command::new(&self.runtime);
+        child.arg("run").arg("--rm").arg("-i")
+            .arg("--volume").arg(format!("{}:{}:ro", dir.path().display(), MOUNT_POINT));
+        match workdir {
+            Some(workdir) => child
+                .arg("--volume").arg(format!("{}:{}", workdir.display(), WORKDIR_MOUNT_POINT))
+                .arg("--workdir").arg(WORKDIR_MOUNT_POINT),
+            None => child.arg("--workdir").arg(MOUNT_POINT),
+        };
+        for (key, value) in envs {
+            child.arg("--env").arg(format!("{}={}", key, value));
+        }
+        for (key, value) in secrets {
+            child.arg("--env").arg(key).env(key, value);
+        }
+        child.args(runtime_args).arg(&self.image);
+
+        let script_path = format!("{}/{}", MOUNT_POINT, file_name);
+        match interpreter {
+            Some(interpreter) => child.args(interpreter).arg(script_path),
+            None => child.arg("sh").arg("-c").arg(format!("rustc -o /tmp/main {} && /tmp/main", script_path)),
+        };
+
+        Ok((child, dir))
+    }
+}
+
+// the file name the script is mounted as and the command that runs it; `None` means the
+// script has to be compiled first
+fn interpreter_for(executor: &str) -> (&'static str, Option<Vec<String>>) {
+    let program = |words: &[&str]| Some(words.iter().map(|word| word.to_string()).collect());
+    match executor {
+        "js" | "javascript" => ("script.js", program(&["node"])),
+        "mjs" => ("script.mjs", program(&["node"])),
+        // the image needs node; npx fetches ts-node when it doesn't have it
+        "ts" | "typescript" => ("script.ts", program(&["npx", "ts-node"])),
+        "py" | "python" => ("script.py", program(&["python"])),
+        "rb" | "ruby" => ("script.rb", program(&["ruby"])),
+        "php" => ("script.php", program(&["php"])),
+        "rust" => ("main.rs", None),
+        "sh" | "shell" => ("script.sh", program(&["sh"])),
+        other => ("script", program(&[other])),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+
+    use tempfile::tempdir;
+
+    use crate::rmd::command::Script;
+    use crate::rmd::container::ContainerExec;
+
+    #[test]
+    fn should_run_script_through_runtime() {
+        let bin = tempdir().unwrap();
+        let runtime = bin.path().join("docker");
+        fs::write(&runtime, "#!/bin/sh\nfor arg in \"$@\"; do echo \"$arg\"; done\n").unwrap();
+        fs::set_permissions(&runtime, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let mut script = Script::new();
+        script.executor = String::from("python");
+        script.source = String::from("print('hello')\n");
+
+        let exec = ContainerExec::with_runtime(runtime, String::from("python:3.11"));
+        let envs = vec![(String::from("TARGET"), String::from("prod"))];
+        let (mut child, dir) = exec.command(&script, &envs, &[], &[String::from("--pids-limit=32")], None).unwrap();
+        let output = child.output().unwrap();
+        let args: Vec<String> = String::from_utf8_lossy(&output.stdout).lines().map(String::from).collect();
+
+        assert_eq!("print('hello')\n", fs::read_to_string(dir.path().join("script.py")).unwrap());
+        assert_eq!(vec![
+            "run", "--rm", "-i",
+            "--volume", &format!("{}:/rinput:ro", dir.path().display()),
+            "--workdir", "/rinput",
+            "--env", "TARGET=prod",
+            "--pids-limit=32",
+            "python:3.11", "python", "/rinput/script.py",
+        ], args);
+    }
+
+    #[test]
+    fn should_run_typescript_with_ts_node() {
+        let bin = tempdir().unwrap();
+        let runtime = bin.path().join("docker");
+        fs::write(&runtime, "#!/bin/sh\nfor arg in \"$@\"; do echo \"$arg\"; done\n").unwrap();
+        fs::set_permissions(&runtime, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let mut script = Script::new();
+        script.executor = String::from("ts");
+        script.source = String::from("const n: number = 1;\n");
+
+        let exec = ContainerExec::with_runtime(runtime, String::from("node:20"));
+        let (mut child, dir) = exec.command(&script, &[], &[], &[], None).unwrap();
+        let output = child.output().unwrap();
+        let args: Vec<String> = String::from_utf8_lossy(&output.stdout).lines().map(String::from).collect();
+
+        assert_eq!("const n: number = 1;\n", fs::read_to_string(dir.path().join("script.ts")).unwrap());
+        assert_eq!(vec!["node:20", "npx", "ts-node", "/rinput/script.ts"], args[args.len() - 4..].to_vec());
+    }
+}
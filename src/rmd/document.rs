@@ -0,0 +1,17 @@
+use std::fs;
+use std::io::Result;
+use std::path::Path;
+
+use crate::rmd::{notebook, org, rst};
+
+// Reads a runbook as markdown, converting it first when it is a Jupyter notebook, an org-mode
+// file or reStructuredText.
+pub fn read(path: &str) -> Result<String> {
+    let text = fs::read_to_string(path)?;
+    match Path::new(path).extension().and_then(|ext| ext.to_str()) {
+        Some("ipynb") => notebook::to_markdown(&text),
+        Some("org") => Ok(org::to_markdown(&text)),
+        Some("rst") => Ok(rst::to_markdown(&text)),
+        _ => Ok(text),
+    }
+}
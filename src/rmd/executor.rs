@@ -1,18 +1,56 @@
 use std::{env, fs, process};
 use std::fmt::Debug;
 use std::fs::{canonicalize, File, Metadata};
+use std::collections::HashMap;
+use std::io;
 use std::io::{Error, ErrorKind, Read, Seek, SeekFrom, Write};
 use std::io::Result;
+use std::os::unix::process::CommandExt;
 use std::path::{Path, PathBuf};
-use std::process::ExitStatus;
+use std::process::{Output, Stdio};
+use std::sync::Arc;
+use std::sync::mpsc::Sender;
+use std::sync::Once;
+use std::sync::atomic::{AtomicI32, AtomicUsize, Ordering};
+use std::thread;
+use std::thread::JoinHandle;
 
 use tempfile::{NamedTempFile, TempDir, tempdir_in};
 
 use crate::main;
 use crate::rmd::command::Command;
-use crate::rmd::lang::{LangExecutor, PythonExec, RustExec};
+use crate::rmd::container::ContainerExec;
+use crate::rmd::interpolate::{BlockOutputs, exported_env, interpolate};
+use crate::rmd::lang::{LangExecutor, NodeExec, PythonExec, RustExec};
+use crate::rmd::limits::Limits;
+use crate::rmd::sandbox;
+use crate::rmd::sandbox::SandboxMode;
+use crate::rmd::secrets;
+use crate::rmd::secrets::MaskingWriter;
+use crate::rmd::workdir;
 
-pub fn execute_command(cmd: Command) -> Result<ExitStatus> {
+// what each block printed, by the file and line its source starts at
+pub type Transcript = HashMap<(String, usize), BlockOutputs>;
+
+// State shared by the blocks of one run of a document.
+#[derive(Debug, Default)]
+pub struct RunContext {
+    // the scratch directory holding the document's file fixtures; blocks without a `cwd=` run inside it
+    pub workdir: Option<PathBuf>,
+    // what the blocks with an `id=` that already ran left for later ones, by id
+    pub blocks: HashMap<String, BlockOutputs>,
+    // the program to run a language with instead of the usual one, by language (`python`, `sh`)
+    pub interpreters: HashMap<String, String>,
+    // where block output goes instead of the terminal, such as a pane of `rinput ui`
+    pub output: Option<Sender<Vec<u8>>>,
+    // when set, what every block printed
+    pub transcript: Option<Transcript>,
+    // the values of the secrets the document's blocks declare, by name; only the blocks that
+    // declare one see it, and it is masked in what any block prints
+    pub secrets: HashMap<String, String>,
+}
+
+pub fn execute_command(mut cmd: Command, ctx: &mut RunContext) -> Result<Output> {
     if cmd.script.source == String::from("") {
         let msg = "Command has no script.";
         return Err(Error::new(ErrorKind::Other, msg));
@@ -23,45 +61,449 @@ pub fn execute_command(cmd: Command) -> Result<ExitStatus> {
         return Err(Error::new(ErrorKind::Other, msg));
     }
 
-    let mut child = prepare_command(&cmd);
-    child.spawn()?.wait()
+    cmd.script.source = interpolate(&cmd.script.source, &cmd.script.executor, &ctx.blocks)?;
+    let sandbox = SandboxMode::from_attr(cmd.script.attr("sandbox"))?;
+    let limits = Limits::from_script(&cmd.script)?;
+    let mut envs = command_env(&cmd);
+    envs.extend(exported_env(&ctx.blocks));
+    let mut secret_envs = vec![];
+    for name in secrets::declared(&cmd.script) {
+        let value = ctx.secrets.get(&name).cloned().or_else(|| env::var(&name).ok())
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, format!("secret {} is not set", name)))?;
+        secret_envs.push((name, value));
+    }
+    let mut masked: Vec<String> = ctx.secrets.values().cloned().collect();
+    masked.extend(secret_envs.iter().map(|(_, value)| value.clone()));
+    let cwd = match workdir::cwd_of(&cmd.script) {
+        Some(cwd) if !cwd.is_dir() => {
+            let msg = format!("cwd={} is not a directory", cwd.display());
+            return Err(Error::new(ErrorKind::NotFound, msg));
+        }
+        Some(cwd) => Some(cwd),
+        None => ctx.workdir.clone(),
+    };
+    let (mut child, mounted) = match cmd.script.attr("image") {
+        Some(image) => {
+            if sandbox != SandboxMode::Off {
+                let msg = "image= already runs the block in a container and cannot be combined with sandbox=";
+                return Err(Error::new(ErrorKind::InvalidInput, msg));
+            }
+            let container = ContainerExec::new(image.to_string())?;
+            let (child, dir) = container.command(&cmd.script, &envs, &secret_envs, &limits.runtime_args(), cwd.as_deref())?;
+            (child, Some(dir))
+        }
+        None => {
+            let mut child = prepare_command(&cmd, &ctx.interpreters, ctx.output.as_ref())?;
+            child.envs(envs);
+            // a secret from rinput's own environment would reach every block otherwise
+            for name in ctx.secrets.keys() {
+                child.env_remove(name);
+            }
+            child.envs(secret_envs.iter().cloned());
+            if let Some(cwd) = &cwd {
+                child.current_dir(cwd);
+            }
+            limits.apply(&mut child);
+            (child, None)
+        }
+    };
+    let scratch = sandbox::isolate(&mut child, sandbox)?;
+
+    let id = cmd.script.attr("id");
+    // inside the sandbox only its scratch dir is writable, so `$RINPUT_OUTPUT` lives there
+    let output_dir = match scratch {
+        Some(scratch) => Some(scratch),
+        None if id.is_some() => Some(tempfile::Builder::new().prefix("rinput-output").tempdir()?),
+        None => None,
+    };
+    let output_file = output_dir.as_ref().map(|dir| dir.path().join("output"));
+    // a container cannot see the file, so container blocks only pass their streams on
+    if let (Some(output_file), None) = (&output_file, &mounted) {
+        child.env("RINPUT_OUTPUT", output_file);
+    }
+
+    // blocks with an id are captured so later blocks can refer to what they printed, every block
+    // is when the run keeps a transcript, and all of them when there are secrets to mask
+    let capture = id.is_some() || ctx.transcript.is_some() || !masked.is_empty();
+    let output = run_child(child, limits.max_output.or_else(|| Some(usize::MAX).filter(|_| capture)), ctx.output.as_ref(), &masked)?;
+    // masked before later blocks can see them, as those may not have declared the secrets
+    let stdout = secrets::mask(&String::from_utf8_lossy(&output.stdout), &masked);
+    let stderr = secrets::mask(&String::from_utf8_lossy(&output.stderr), &masked);
+    if let Some(id) = id {
+        let exported = output_file.and_then(|file| fs::read_to_string(file).ok()).unwrap_or_default();
+        ctx.blocks.insert(id.to_string(), BlockOutputs::new(stdout.clone(), stderr.clone(), &secrets::mask(&exported, &masked)));
+    }
+    if let Some(transcript) = ctx.transcript.as_mut() {
+        transcript.insert((cmd.script.file.clone(), cmd.script.line), BlockOutputs { stdout, stderr, ..BlockOutputs::default() });
+    }
+
+    Ok(output)
+}
+
+// The process groups of the capped blocks running now. They are outside the terminal's foreground
+// group, so an interrupt only reaches them through rinput.
+static CAPPED_GROUPS: [AtomicI32; 32] = [const { AtomicI32::new(0) }; 32];
+static FORWARDING: Once = Once::new();
+
+extern "C" fn on_terminate(signal: libc::c_int) {
+    forward_to_groups(signal);
+    // then die of the signal as we would have without the handler
+    unsafe {
+        libc::signal(signal, libc::SIG_DFL);
+        libc::raise(signal);
+    }
+}
+
+fn forward_to_groups(signal: libc::c_int) {
+    for group in CAPPED_GROUPS.iter() {
+        let pid = group.load(Ordering::SeqCst);
+        if pid != 0 {
+            unsafe {
+                libc::kill(-pid, signal);
+            }
+        }
+    }
+}
+
+// a capped block's group for as long as it runs
+struct CappedGroup(Option<usize>);
+
+impl CappedGroup {
+    fn register(pid: u32) -> CappedGroup {
+        FORWARDING.call_once(|| unsafe {
+            libc::signal(libc::SIGINT, on_terminate as extern "C" fn(libc::c_int) as libc::sighandler_t);
+            libc::signal(libc::SIGTERM, on_terminate as extern "C" fn(libc::c_int) as libc::sighandler_t);
+        });
+        let slot = CAPPED_GROUPS.iter()
+            .position(|group| group.compare_exchange(0, pid as i32, Ordering::SeqCst, Ordering::SeqCst).is_ok());
+        CappedGroup(slot)
+    }
+}
+
+impl Drop for CappedGroup {
+    fn drop(&mut self) {
+        if let Some(slot) = self.0 {
+            CAPPED_GROUPS[slot].store(0, Ordering::SeqCst);
+        }
+    }
 }
 
-fn prepare_command(cmd: &Command) -> process::Command {
+// Output is only captured when asked for, so other blocks keep a terminal. Captured output is
+// still streamed as it arrives, to `sink` if there is one, with the values in `mask` hidden; past
+// `max_output` a marker is written and the block killed.
+fn run_child(mut child: process::Command, max_output: Option<usize>, sink: Option<&Sender<Vec<u8>>>,
+             mask: &[String]) -> Result<Output> {
+    let max_output = match (max_output, sink) {
+        (Some(max_output), _) => max_output,
+        (None, Some(_)) => usize::MAX,
+        (None, None) => {
+            let status = child.spawn()?.wait()?;
+            return Ok(Output { status, stdout: vec![], stderr: vec![] });
+        }
+    };
+    let writer = |stream: Box<dyn Write + Send>| -> Box<dyn Write + Send> {
+        let stream: Box<dyn Write + Send> = match sink {
+            Some(sink) => Box::new(ChannelWriter(sink.clone())),
+            None => stream,
+        };
+        if mask.is_empty() {
+            return stream;
+        }
+        Box::new(MaskingWriter::new(stream, mask))
+    };
+    // without a terminal of its own the block must not wait on one
+    if sink.is_some() {
+        child.stdin(Stdio::null());
+    }
+    // a capped block gets a process group of its own, so reaching the cap stops everything it
+    // started; outside the terminal's foreground group it cannot read the terminal either, and
+    // an interrupt is passed on to it by `on_terminate`
+    if max_output != usize::MAX {
+        child.stdin(Stdio::null());
+        unsafe {
+            child.pre_exec(|| {
+                if libc::setpgid(0, 0) == -1 {
+                    return Err(Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+    }
+
+    let mut child = child.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+    let _group = if max_output != usize::MAX { Some(CappedGroup::register(child.id())) } else { None };
+    let written = Arc::new(AtomicUsize::new(0));
+    let stdout = tee(child.stdout.take().unwrap(), writer(Box::new(io::stdout())), written.clone(), max_output, child.id());
+    let stderr = tee(child.stderr.take().unwrap(), writer(Box::new(io::stderr())), written, max_output, child.id());
+
+    // the readers finish before the child is reaped, so its pid cannot be reused while they may kill it
+    let stdout = stdout.join().unwrap_or_default();
+    let stderr = stderr.join().unwrap_or_default();
+    let status = child.wait()?;
+    Ok(Output { status, stdout, stderr })
+}
+
+fn tee<R: Read + Send + 'static>(mut reader: R, mut writer: Box<dyn Write + Send>, written: Arc<AtomicUsize>,
+                                 max_output: usize, pid: u32) -> JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut captured = vec![];
+        let mut buf = [0; 8192];
+        loop {
+            let count = match reader.read(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(count) => count,
+            };
+
+            // both streams share the budget; only the reader that crosses it writes the marker
+            let before = written.fetch_add(count, Ordering::SeqCst);
+            if before > max_output {
+                break;
+            }
+            let keep = count.min(max_output - before);
+            captured.extend_from_slice(&buf[..keep]);
+            let _ = writer.write_all(&buf[..keep]);
+
+            if keep < count {
+                let marker = format!("\n[rinput: output truncated after {} bytes, stopping the block]\n", max_output);
+                captured.extend_from_slice(marker.as_bytes());
+                let _ = writer.write_all(marker.as_bytes());
+                // the whole group, as a `yes | cat` would keep the pipes open otherwise
+                unsafe {
+                    libc::kill(-(pid as libc::pid_t), libc::SIGKILL);
+                }
+                let _ = writer.flush();
+                break;
+            }
+            let _ = writer.flush();
+        }
+        captured
+    })
+}
+
+struct ChannelWriter(Sender<Vec<u8>>);
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let _ = self.0.send(buf.to_vec());
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+// required args and option flags reach the script as environment variables
+fn command_env(cmd: &Command) -> Vec<(String, String)> {
+    let mut envs = vec![];
+    for arg in &cmd.required_args {
+        envs.push((arg.name.clone(), arg.val.clone()));
+    }
+    for flag in &cmd.option_flags {
+        if !flag.val.is_empty() {
+            envs.push((flag.name.clone(), flag.val.clone()));
+        }
+    }
+    envs.extend(cmd.script.env.iter().cloned());
+    envs
+}
+
+// The languages `executors.<lang>` can't set a program for, and what to set instead: these blocks
+// run with the program of another name, or are compiled.
+pub const UNCONFIGURABLE: [(&str, &str); 8] = [
+    ("js", "set `executors.node`"),
+    ("javascript", "set `executors.node`"),
+    ("mjs", "set `executors.node`"),
+    ("ts", "set `executors.node`"),
+    ("typescript", "set `executors.node`"),
+    ("py", "set `executors.python`"),
+    ("rb", "set `executors.ruby`"),
+    ("rust", "rust blocks are built with cargo"),
+];
+
+fn prepare_command(cmd: &Command, interpreters: &HashMap<String, String>, output: Option<&Sender<Vec<u8>>>) -> Result<process::Command> {
     let executor = cmd.script.executor.clone();
     let source = cmd.script.source.clone();
+    let program = |lang: &str| interpreters.get(lang).cloned().unwrap_or_else(|| lang.to_string());
 
     match executor.as_ref() {
-        "js" | "javascript" => {
-            let mut child;
-            child = process::Command::new("node");
-            child.arg("-e").arg(source);
-            child
+        "js" | "javascript" | "mjs" => {
+            NodeExec::new(source).with_program(interpreters.get("node")).with_output(output.cloned()).execute()
+        }
+        "ts" | "typescript" => {
+            NodeExec::typescript(source).with_program(interpreters.get("node")).with_output(output.cloned()).execute()
         }
         "py" | "python" => {
-            let mut py_exec = PythonExec::new(source.clone());
-            let child = py_exec.execute();
-            child
+            PythonExec::new(source).with_program(interpreters.get("python")).execute()
         }
         "rb" | "ruby" => {
-            let mut child = process::Command::new("ruby");
+            let mut child = process::Command::new(program("ruby"));
             child.arg("-e").arg(source);
-            child
+            Ok(child)
         }
         "php" => {
-            let mut child = process::Command::new("php");
+            let mut child = process::Command::new(program("php"));
             child.arg("-r").arg(source);
-            child
+            Ok(child)
         }
         "rust" => {
-            let mut rustexec = RustExec::new(source.clone());
-            let child = rustexec.execute();
-            child
+            RustExec::new(source)
+                .with_origin(cmd.script.file.clone(), cmd.script.line)
+                .with_output(output.cloned())
+                .execute()
         }
         _ => {
-            let mut child = process::Command::new(executor);
+            let mut child = process::Command::new(program(&executor));
             child.arg("-c").arg(source);
-            child
+            Ok(child)
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::os::unix::process::ExitStatusExt;
+    use std::process;
+    use std::sync::mpsc::channel;
+
+    use crate::rmd::command::Command;
+    use crate::rmd::executor::{execute_command, run_child, RunContext, Transcript};
+
+    fn sh_block(id: Option<&str>, source: &str) -> Command {
+        let mut cmd = Command::new(1);
+        cmd.script.executor = String::from("sh");
+        cmd.script.source = String::from(source);
+        if let Some(id) = id {
+            cmd.script.attrs.insert(String::from("id"), String::from(id));
+        }
+        cmd
+    }
+
+    #[test]
+    fn should_truncate_runaway_output() {
+        let mut child = process::Command::new("sh");
+        child.arg("-c").arg("while true; do echo spam; done");
+        let output = run_child(child, Some(100), None, &[]).unwrap();
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(stdout.starts_with("spam\nspam\n"));
+        assert!(stdout.ends_with("[rinput: output truncated after 100 bytes, stopping the block]\n"));
+        assert_eq!(None, output.status.code());
+    }
+
+    #[test]
+    fn should_stop_every_process_of_a_runaway_block() {
+        let mut child = process::Command::new("sh");
+        child.arg("-c").arg("yes | cat");
+        let output = run_child(child, Some(100), None, &[]).unwrap();
+
+        assert!(String::from_utf8_lossy(&output.stdout).ends_with("[rinput: output truncated after 100 bytes, stopping the block]\n"));
+        assert!(!output.status.success());
+    }
+
+    // runs in a process of its own, started by `should_pass_interrupts_on_to_capped_blocks`
+    #[test]
+    #[ignore]
+    fn interrupted_while_running_a_capped_sleep() {
+        if std::env::var_os("RINPUT_INTERRUPT_TEST").is_none() {
+            return;
+        }
+        std::thread::spawn(|| {
+            std::thread::sleep(std::time::Duration::from_millis(500));
+            unsafe {
+                libc::kill(libc::getpid(), libc::SIGINT);
+            }
+        });
+        let mut child = process::Command::new("sh");
+        child.arg("-c").arg("echo $$; exec sleep 30");
+        let _ = run_child(child, Some(1024), None, &[]);
+    }
+
+    #[test]
+    fn should_pass_interrupts_on_to_capped_blocks() {
+        let output = process::Command::new(std::env::current_exe().unwrap())
+            .args(["--ignored", "--exact", "--nocapture", "rmd::executor::test::interrupted_while_running_a_capped_sleep"])
+            .env("RINPUT_INTERRUPT_TEST", "1")
+            .output().unwrap();
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let sleep: i32 = stdout.split("... ").nth(1).and_then(|rest| rest.split_whitespace().next()?.parse().ok()).expect("the sleep's pid");
+
+        assert_eq!(Some(libc::SIGINT), output.status.signal());
+        // gone, or a zombie nobody has reaped yet
+        std::thread::sleep(std::time::Duration::from_millis(200));
+        let state = std::fs::read_to_string(format!("/proc/{}/stat", sleep)).unwrap_or_default();
+        assert!(state.is_empty() || state.contains(") Z "), "sleep {} is still running: {}", sleep, state);
+    }
+
+    #[test]
+    fn should_capture_output_under_the_cap() {
+        let mut child = process::Command::new("sh");
+        child.arg("-c").arg("echo out; echo err >&2");
+        let output = run_child(child, Some(1024), None, &[]).unwrap();
+
+        assert_eq!("out\n", String::from_utf8_lossy(&output.stdout));
+        assert_eq!("err\n", String::from_utf8_lossy(&output.stderr));
+        assert!(output.status.success());
+    }
+
+    #[test]
+    fn should_stream_output_to_sink() {
+        let (sender, receiver) = channel();
+        let mut ctx = RunContext { output: Some(sender), ..RunContext::default() };
+        let output = execute_command(sh_block(None, "read line || echo no input; echo done"), &mut ctx).unwrap();
+        drop(ctx);
+
+        let streamed: Vec<u8> = receiver.iter().flatten().collect();
+        assert_eq!("no input\ndone\n", String::from_utf8_lossy(&streamed));
+        assert_eq!("no input\ndone\n", String::from_utf8_lossy(&output.stdout));
+    }
+
+    #[test]
+    fn should_pass_outputs_to_later_blocks() {
+        let mut ctx = RunContext::default();
+        let fetch = sh_block(Some("fetch"), "echo i-0abc; echo image=ami-42 >> \"$RINPUT_OUTPUT\"");
+        assert!(execute_command(fetch, &mut ctx).unwrap().status.success());
+
+        let check = sh_block(None, "test ${{ blocks.fetch.stdout }}/\"$RINPUT_FETCH_IMAGE\" = i-0abc/ami-42");
+        assert!(execute_command(check, &mut ctx).unwrap().status.success());
+
+        let unknown = sh_block(None, "echo ${{ blocks.deploy.stdout }}");
+        assert!(execute_command(unknown, &mut ctx).is_err());
+    }
+
+    #[test]
+    fn should_pass_secrets_only_to_blocks_declaring_them() {
+        let (sender, receiver) = channel();
+        let mut ctx = RunContext {
+            output: Some(sender),
+            transcript: Some(Transcript::new()),
+            secrets: [(String::from("API_TOKEN"), String::from("s3cr3t"))].iter().cloned().collect(),
+            ..RunContext::default()
+        };
+        let mut deploy = sh_block(None, "echo token=$API_TOKEN");
+        deploy.script.attrs.insert(String::from("secrets"), String::from("[API_TOKEN]"));
+        assert!(execute_command(deploy, &mut ctx).unwrap().status.success());
+        let mut other = sh_block(None, "test -z \"$API_TOKEN\"");
+        other.script.line = 8;
+        assert!(execute_command(other, &mut ctx).unwrap().status.success());
+        drop(ctx.output.take());
+
+        let streamed: Vec<u8> = receiver.iter().flatten().collect();
+        assert_eq!("token=******\n", String::from_utf8_lossy(&streamed));
+        assert_eq!("token=******\n", ctx.transcript.unwrap()[&(String::new(), 0)].stdout);
+    }
+
+    #[test]
+    fn should_mask_secrets_passed_on_to_later_blocks() {
+        let mut ctx = RunContext {
+            secrets: [(String::from("API_TOKEN"), String::from("s3cr3t"))].iter().cloned().collect(),
+            ..RunContext::default()
+        };
+        let mut login = sh_block(Some("login"), "echo $API_TOKEN; echo token=$API_TOKEN >> \"$RINPUT_OUTPUT\"");
+        login.script.attrs.insert(String::from("secrets"), String::from("API_TOKEN"));
+        assert!(execute_command(login, &mut ctx).unwrap().status.success());
+
+        let check = sh_block(None, "test ${{ blocks.login.stdout }}/\"$RINPUT_LOGIN_TOKEN\" = '******/******'");
+        assert!(execute_command(check, &mut ctx).unwrap().status.success());
+    }
+}
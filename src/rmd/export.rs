@@ -0,0 +1,608 @@
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind, Result};
+
+use crate::rmd::command::{Command, Fixture, OptionFlag};
+use crate::rmd::executor::Transcript;
+use crate::rmd::notebook;
+use crate::rmd::outline::{command_tree, command_word, has_title};
+use crate::rmd::policy::Policy;
+use crate::rmd::sandbox::SandboxMode;
+use crate::rmd::workdir;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Sh,
+    Make,
+    Justfile,
+    Ipynb,
+}
+
+impl Format {
+    pub fn from_name(name: &str) -> Result<Format> {
+        match name {
+            "sh" => Ok(Format::Sh),
+            "make" | "makefile" => Ok(Format::Make),
+            "just" | "justfile" => Ok(Format::Justfile),
+            "ipynb" | "notebook" => Ok(Format::Ipynb),
+            _ => Err(Error::new(ErrorKind::InvalidInput, format!("can't export to `{}`, expected sh, make, justfile or ipynb", name))),
+        }
+    }
+}
+
+// The blocks under one heading, which become one function, target or recipe.
+struct Recipe<'a> {
+    words: Vec<String>,
+    // each with the block its `on-failure` names
+    blocks: Vec<(&'a Command, Option<&'a Command>)>,
+}
+
+impl Recipe<'_> {
+    // blocks outside any command heading run when no command is named
+    fn name(&self, separator: &str) -> String {
+        if self.words.is_empty() {
+            String::from("default")
+        } else {
+            self.words.join(separator)
+        }
+    }
+
+    // blocks under one heading share its description, arguments and options
+    fn head(&self) -> &Command {
+        self.blocks[0].0
+    }
+
+    fn flags(&self) -> impl Iterator<Item=&OptionFlag> {
+        self.head().option_flags.iter().filter(|flag| flag.name != "verbose")
+    }
+}
+
+// Like under `run`, the blocks an `on-failure` names only run when the block naming them fails.
+fn recipes(commands: &[Command]) -> Vec<Recipe<'_>> {
+    let skip = if has_title(&command_tree(commands)) { 1 } else { 0 };
+    let handler = |cmd: &Command| cmd.script.attr("on-failure")
+        .and_then(|id| commands.iter().find(|handler| handler.script.attr("id") == Some(id)));
+    let is_handler = |cmd: &Command| commands.iter().any(|other| handler(other).is_some_and(|handler| std::ptr::eq(handler, cmd)));
+    let mut recipes: Vec<Recipe> = vec![];
+    for cmd in commands.iter().filter(|cmd| cmd.script.attr("skip") != Some("true") && !is_handler(cmd)) {
+        let words: Vec<String> = cmd.path.iter().skip(skip).map(|name| command_word(name)).collect();
+        match recipes.iter_mut().find(|recipe| recipe.words == words) {
+            Some(recipe) => recipe.blocks.push((cmd, handler(cmd))),
+            None => recipes.push(Recipe { words, blocks: vec![(cmd, handler(cmd))] }),
+        }
+    }
+    recipes
+}
+
+// Turns the document's commands into a script that runs without rinput. Arguments and flags
+// reach the blocks as environment variables, as they do under `run`, except that a shell
+// variable can't hold a `-`: the `dry-run` option is exported as `dry_run`. The file blocks are
+// written to a scratch directory each command runs in, and a `cwd=` is taken relative to where
+// the script runs. A notebook keeps the document's text as well, and what the blocks printed
+// when `transcript` holds it. Blocks reading another's output with `${{ ... }}` can't be exported,
+// nor those `run` puts in a container, a sandbox or under limits; a block's `retries`,
+// `expect-exit`, `allow-failure` and `on-failure` become shell around it.
+pub fn export(format: Format, text: &str, commands: &[Command], fixtures: &[Fixture], transcript: &Transcript,
+              interpreters: &HashMap<String, String>, source: &str) -> Result<String> {
+    if format != Format::Ipynb {
+        for cmd in commands {
+            check_exportable(cmd, commands, source)?;
+        }
+    }
+    for fixture in fixtures {
+        workdir::relative_path(fixture)?;
+    }
+    let script = match format {
+        Format::Sh => sh_script(&recipes(commands), fixtures, interpreters, source),
+        Format::Make => makefile(&recipes(commands), fixtures, interpreters, source),
+        Format::Justfile => justfile(&recipes(commands), fixtures, interpreters, source),
+        Format::Ipynb => format!("{}\n", notebook::from_markdown(text, source, commands, transcript).pretty()),
+    };
+    Ok(script)
+}
+
+const LIMITS: [&str; 5] = ["cpu-time", "memory", "open-files", "processes", "max-output"];
+
+fn check_exportable(cmd: &Command, commands: &[Command], source: &str) -> Result<()> {
+    let block = format!("the {} block at {}:{}", cmd.script.executor, source, cmd.script.line);
+    let refuse = |why: String| Err(Error::new(ErrorKind::InvalidInput, format!("{} {}", block, why)));
+    if cmd.script.source.replace("$${{", "").contains("${{") {
+        return refuse(String::from("reads another block's output with `${{ ... }}`, which only `rinput run` can fill in"));
+    }
+    if let Some(image) = cmd.script.attr("image") {
+        return refuse(format!("runs in the `{}` image, which only `rinput run` sets up", image));
+    }
+    if SandboxMode::from_attr(cmd.script.attr("sandbox"))? != SandboxMode::Off {
+        return refuse(String::from("runs in a sandbox, which only `rinput run` sets up"));
+    }
+    if let Some(limit) = LIMITS.iter().find(|limit| cmd.script.attr(limit).is_some()) {
+        return refuse(format!("has a `{}` limit, which only `rinput run` enforces", limit));
+    }
+    let policy = Policy::from_script(&cmd.script).map_err(|err| Error::new(ErrorKind::InvalidInput, format!("{}: {}", block, err)))?;
+    if let Some(handler) = policy.on_failure {
+        if !commands.iter().any(|other| other.script.attr("id") == Some(handler.as_str())) {
+            return refuse(format!("has on-failure={}, which does not name a block with id={}", handler, handler));
+        }
+    }
+    Ok(())
+}
+
+fn var_name(name: &str) -> String {
+    name.chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '_' }).collect()
+}
+
+fn comment(text: &str) -> String {
+    text.lines().map(|line| format!("# {}\n", line).replace("# \n", "#\n")).collect()
+}
+
+// the shell that runs every block under a heading, one after the other
+fn body(recipe: &Recipe, fixtures: &[Fixture], interpreters: &HashMap<String, String>) -> String {
+    let mut out = workdir(fixtures);
+    for (block, handler) in &recipe.blocks {
+        let script = isolated(block, block_script(block, interpreters));
+        let handler = handler.map(|handler| isolated(handler, block_script(handler, interpreters)));
+        out.push_str(&with_policy(&Policy::from_script(&block.script).unwrap_or_default(), script, handler));
+    }
+    out
+}
+
+// The block runs in a subshell of its own with `set -e`, retried until it exits with the status it
+// is expected to; the script only stops once it failed for good and its handler ran.
+fn with_policy(policy: &Policy, script: String, handler: Option<String>) -> String {
+    if *policy == Policy::default() {
+        return script;
+    }
+    let expected = policy.expect_exit;
+    let mut out = String::from("set +e\n");
+    if policy.retries > 0 {
+        out.push_str(&format!("rinput_tries=0\nrinput_delay={}\nwhile :; do\n", policy.backoff.as_secs_f64().ceil() as u64));
+    }
+    out.push_str(&format!("(\nset -e\n{})\nrinput_status=$?\n", script));
+    if policy.retries > 0 {
+        out.push_str(&format!("[ \"$rinput_status\" -ne {} ] && [ \"$rinput_tries\" -lt {} ] || break\n", expected, policy.retries));
+        out.push_str("rinput_tries=$((rinput_tries + 1))\n");
+        out.push_str(&format!("echo \"retry: exit status $rinput_status, retrying in ${{rinput_delay}}s ($rinput_tries/{})\" >&2\n", policy.retries));
+        out.push_str("sleep \"$rinput_delay\"\nrinput_delay=$((rinput_delay * 2))\ndone\n");
+    }
+    out.push_str(&format!("set -e\nif [ \"$rinput_status\" -ne {} ]; then\n", expected));
+    if policy.allow_failure {
+        out.push_str("echo \"allowed: exit status $rinput_status\" >&2\n");
+    } else {
+        if let Some(handler) = handler {
+            out.push_str(&format!("(\nset -e\n{}) || :\n", handler));
+        }
+        out.push_str("exit $((rinput_status ? rinput_status : 1))\n");
+    }
+    out.push_str("fi\n");
+    out
+}
+
+// a scratch directory holding the file blocks, like the one `run` makes
+fn workdir(fixtures: &[Fixture]) -> String {
+    if fixtures.is_empty() {
+        return String::new();
+    }
+    let mut out = String::from("rinput_start=$PWD\nrinput_workdir=$(mktemp -d)\n");
+    for fixture in fixtures {
+        let path = format!("\"$rinput_workdir\"/{}", sh_quote(&fixture.path));
+        let delimiter = delimiter(&fixture.content);
+        out.push_str(&format!("mkdir -p \"$(dirname {})\"\ncat > {} <<'{}'\n{}{}\n", path, path, delimiter, with_newline(&fixture.content), delimiter));
+    }
+    out.push_str("cd \"$rinput_workdir\"\n");
+    out
+}
+
+// A block's `cwd=` and the variables its `env` directives set only apply to it, so it gets a subshell.
+fn isolated(cmd: &Command, script: String) -> String {
+    let cwd = cmd.script.attr("cwd");
+    if cmd.script.env.is_empty() && cwd.is_none() {
+        return script;
+    }
+    let mut out = String::from("(\n");
+    if let Some(cwd) = cwd {
+        out.push_str(&format!("cd \"${{rinput_start:-.}}\"/{}\n", sh_quote(cwd)));
+    }
+    for (name, value) in &cmd.script.env {
+        out.push_str(&format!("export {}={}\n", name, sh_quote(value)));
+    }
+    format!("{}{})\n", out, script)
+}
+
+fn sh_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+// recipes become functions of their own, so a `## install` block can still run `install`
+fn sh_function(recipe: &Recipe) -> String {
+    format!("rinput_cmd_{}", var_name(&recipe.name("_")))
+}
+
+// Shell blocks are kept as they are, other languages get the same interpreter and flag `run`
+// gives them, with their source in a heredoc.
+fn block_script(cmd: &Command, interpreters: &HashMap<String, String>) -> String {
+    let program = |lang: &str| interpreters.get(lang).cloned().unwrap_or_else(|| lang.to_string());
+    let source = &cmd.script.source;
+    match cmd.script.executor.as_str() {
+        "sh" => with_newline(source),
+        "js" | "javascript" | "mjs" => heredoc(&program("node"), "-e", source),
+        "ts" | "typescript" => heredoc("npx ts-node", "-e", source),
+        "py" | "python" => heredoc(&program("python"), "-c", source),
+        "rb" | "ruby" => heredoc(&program("ruby"), "-e", source),
+        "php" => heredoc(&program("php"), "-r", source),
+        // plain rustc, without the `rinput-deps` crates `run` would fetch
+        "rust" => {
+            let delimiter = delimiter(source);
+            format!("rinput_dir=$(mktemp -d)\ncat > \"$rinput_dir/main.rs\" <<'{}'\n{}{}\n\
+                     rustc -o \"$rinput_dir/main\" \"$rinput_dir/main.rs\"\n\"$rinput_dir/main\"\n",
+                    delimiter, with_newline(source), delimiter)
+        }
+        lang => heredoc(&program(lang), "-c", source),
+    }
+}
+
+// `python -c "$(cat <<'EOF' ...)"` runs the source like `run` does and leaves stdin to the user
+fn heredoc(program: &str, flag: &str, source: &str) -> String {
+    let delimiter = delimiter(source);
+    format!("{} {} \"$(cat <<'{}'\n{}{}\n)\"\n", program, flag, delimiter, with_newline(source), delimiter)
+}
+
+fn delimiter(source: &str) -> String {
+    let mut delimiter = String::from("RINPUT_EOF");
+    while source.lines().any(|line| line == delimiter) {
+        delimiter.push('_');
+    }
+    delimiter
+}
+
+fn with_newline(source: &str) -> String {
+    if source.is_empty() || source.ends_with('\n') {
+        source.to_string()
+    } else {
+        format!("{}\n", source)
+    }
+}
+
+fn usage(recipe: &Recipe) -> String {
+    let mut usage = if recipe.words.is_empty() { String::new() } else { recipe.words.join(" ") };
+    for arg in &recipe.head().required_args {
+        usage.push_str(&format!(" <{}>", arg.name));
+    }
+    for flag in recipe.flags() {
+        let name = if flag.long.is_empty() { format!("-{}", flag.short) } else { format!("--{}", flag.long) };
+        match flag.takes_value {
+            true => usage.push_str(&format!(" [{} <{}>]", name, flag.name)),
+            false => usage.push_str(&format!(" [{}]", name)),
+        }
+    }
+    usage.trim().to_string()
+}
+
+fn sh_script(recipes: &[Recipe], fixtures: &[Fixture], interpreters: &HashMap<String, String>, source: &str) -> String {
+    let mut out = format!("#!/bin/sh\n# Generated by `rinput export --format sh` from {}\nset -e\n", source);
+    for recipe in recipes {
+        out.push('\n');
+        out.push_str(&comment(&recipe.head().desc));
+        out.push_str(&format!("{}() (\n", sh_function(recipe)));
+        out.push_str(&sh_parse_args(recipe));
+        out.push_str(&body(recipe, fixtures, interpreters));
+        out.push_str(")\n");
+    }
+
+    out.push_str("\nrinput_usage() {\n    echo \"usage: ${0##*/} <command> [args...]\"\n    cat <<'RINPUT_EOF'\n\ncommands:\n");
+    for recipe in recipes {
+        let desc = recipe.head().desc.lines().next().unwrap_or("");
+        out.push_str(format!("    {:<32} {}", usage(recipe), desc).trim_end());
+        out.push('\n');
+    }
+    out.push_str("RINPUT_EOF\n}\n\n");
+    out.push_str("case \"${1:-}\" in\n    -h|--help) rinput_usage; exit 0 ;;\nesac\n");
+    sh_dispatch(recipes, &[], 0, &mut out);
+    out
+}
+
+// Binds `deploy prod -n --replicas 3` to `env`, `dry_run` and `replicas`, in any order.
+fn sh_parse_args(recipe: &Recipe) -> String {
+    let head = recipe.head();
+    if head.required_args.is_empty() && head.option_flags.is_empty() {
+        return String::new();
+    }
+    let name = recipe.name(" ");
+    let mut out = String::from("    rinput_n=0\n    while [ $# -gt 0 ]; do\n        case \"$1\" in\n");
+    for flag in &head.option_flags {
+        let var = var_name(&flag.name);
+        let mut names = vec![];
+        if !flag.short.is_empty() {
+            names.push(format!("-{}", flag.short));
+        }
+        if !flag.long.is_empty() {
+            names.push(format!("--{}", flag.long));
+        }
+        if !flag.takes_value {
+            out.push_str(&format!("            {}) {}=true ;;\n", names.join("|"), var));
+            continue;
+        }
+        let value = if flag.multiple { format!("\"${{{}:+${} }}", var, var) } else { String::from("\"") };
+        out.push_str(&format!("            {}) {}={}$2\"; shift ;;\n", names.join("|"), var, value));
+        if !flag.long.is_empty() {
+            out.push_str(&format!("            --{}=*) {}={}${{1#*=}}\" ;;\n", flag.long, var, value));
+        }
+    }
+    out.push_str(&format!("            -?*) echo \"{}: unknown option $1\" >&2; exit 1 ;;\n", name));
+    out.push_str("            *)\n                rinput_n=$((rinput_n + 1))\n                case $rinput_n in\n");
+    for (index, arg) in head.required_args.iter().enumerate() {
+        out.push_str(&format!("                    {}) {}=\"$1\" ;;\n", index + 1, var_name(&arg.name)));
+    }
+    out.push_str(&format!("                    *) echo \"{}: too many arguments\" >&2; exit 1 ;;\n", name));
+    out.push_str("                esac ;;\n        esac\n        shift\n    done\n");
+
+    let mut exported = vec![];
+    for arg in &head.required_args {
+        let var = var_name(&arg.name);
+        out.push_str(&format!("    [ -n \"${{{}:-}}\" ] || {{ echo \"{}: missing <{}>\" >&2; exit 1; }}\n", var, name, arg.name));
+        exported.push(var);
+    }
+    exported.extend(head.option_flags.iter().map(|flag| var_name(&flag.name)));
+    out.push_str(&format!("    export {}\n", exported.join(" ")));
+    out
+}
+
+// `case` on the command words, nested like the headings; the words left over go to the function
+fn sh_dispatch(recipes: &[Recipe], prefix: &[String], depth: usize, out: &mut String) {
+    let indent = "    ".repeat(depth);
+    let own = recipes.iter().find(|recipe| recipe.words == prefix);
+    let fallback = match own {
+        Some(recipe) => format!("{} \"$@\"", sh_function(recipe)),
+        None => String::from("rinput_usage >&2; exit 1"),
+    };
+    let mut children: Vec<&String> = vec![];
+    for recipe in recipes {
+        if recipe.words.len() > prefix.len() && recipe.words.starts_with(prefix) && !children.contains(&&recipe.words[prefix.len()]) {
+            children.push(&recipe.words[prefix.len()]);
+        }
+    }
+    if children.is_empty() {
+        out.push_str(&format!("{}{}\n", indent, fallback));
+        return;
+    }
+
+    out.push_str(&format!("{}case \"${{1:-}}\" in\n", indent));
+    for child in children {
+        out.push_str(&format!("{}    {})\n{}        shift\n", indent, child, indent));
+        let mut path = prefix.to_vec();
+        path.push(child.clone());
+        sh_dispatch(recipes, &path, depth + 2, out);
+        out.push_str(&format!("{}        ;;\n", indent));
+    }
+    out.push_str(&format!("{}    *) {} ;;\n{}esac\n", indent, fallback, indent));
+}
+
+// Targets take their arguments and options as variables, `make deploy env=prod dry_run=true`,
+// and each runs in one shell so the blocks' lines stay together.
+fn makefile(recipes: &[Recipe], fixtures: &[Fixture], interpreters: &HashMap<String, String>, source: &str) -> String {
+    let names: Vec<String> = recipes.iter().map(|recipe| recipe.name("-")).collect();
+    let mut out = format!("# Generated by `rinput export --format make` from {}\n\
+                           SHELL := /bin/sh\n.ONESHELL:\n.EXPORT_ALL_VARIABLES:\n.PHONY: {}\n",
+                          source, names.join(" "));
+    for (recipe, name) in recipes.iter().zip(&names) {
+        let mut usage = format!("make {}", name);
+        for arg in &recipe.head().required_args {
+            usage.push_str(&format!(" {}=<{}>", var_name(&arg.name), arg.name));
+        }
+        for flag in recipe.flags() {
+            let value = if flag.takes_value { format!("<{}>", flag.name) } else { String::from("true") };
+            usage.push_str(&format!(" [{}={}]", var_name(&flag.name), value));
+        }
+
+        out.push('\n');
+        out.push_str(&comment(&recipe.head().desc));
+        out.push_str(&format!("# usage: {}\n{}:\n\t@set -e\n", usage, name));
+        for arg in &recipe.head().required_args {
+            out.push_str(&format!("\ttest -n \"$${}\" || {{ echo \"usage: {}\" >&2; exit 1; }}\n", var_name(&arg.name), usage));
+        }
+        for line in body(recipe, fixtures, interpreters).lines() {
+            out.push_str(&format!("\t{}\n", line.replace('$', "$$")));
+        }
+    }
+    out
+}
+
+// Recipes take their arguments in order and then their options, as optional parameters:
+// `just deploy prod true 3`. `set export` hands them to the blocks as variables.
+fn justfile(recipes: &[Recipe], fixtures: &[Fixture], interpreters: &HashMap<String, String>, source: &str) -> String {
+    let mut out = format!("# Generated by `rinput export --format justfile` from {}\nset export\n", source);
+    for recipe in recipes {
+        let mut params: Vec<String> = recipe.head().required_args.iter().map(|arg| var_name(&arg.name)).collect();
+        params.extend(recipe.flags().map(|flag| format!("{}=\"\"", var_name(&flag.name))));
+
+        out.push('\n');
+        out.push_str(&comment(&recipe.head().desc));
+        out.push_str(&format!("{}:\n    #!/bin/sh\n    set -e\n", [vec![recipe.name("-")], params].concat().join(" ")));
+        for line in body(recipe, fixtures, interpreters).lines() {
+            // `{{` starts an interpolation, `{{{{` is a literal one
+            out.push_str(&format!("    {}\n", line.replace("{{", "{{{{")));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use std::fs;
+    use std::process;
+
+    use tempfile::tempdir;
+
+    use crate::rmd::executor::Transcript;
+    use crate::rmd::export::{export, Format};
+    use crate::rmd::Rmd;
+
+    const DOC: &str = "```sh
+echo default
+```
+
+# deploy (env)
+
+> Deploys the current build
+
+**OPTIONS**
+* dry-run
+    * flags: -n --dry-run
+* replicas
+    * flags: -r --replicas
+    * type: number
+
+```sh
+echo \"deploying to $env x${replicas:-1} ${dry_run:+(dry run)}\"
+```
+
+```bash
+echo \"{{ $env }}\"
+```
+
+## rollback
+
+```sh
+echo rolling back
+```
+";
+
+    // writes the script as `name` and runs `program` with `args` next to it
+    fn run(program: &str, script: &str, name: &str, args: &[&str]) -> String {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join(name), script).unwrap();
+        let output = process::Command::new(program).args(args).current_dir(dir.path()).output().unwrap();
+        String::from_utf8_lossy(&output.stdout).to_string() + &String::from_utf8_lossy(&output.stderr)
+    }
+
+    #[test]
+    fn should_export_a_runnable_shell_script() {
+        let commands = Rmd::new(String::from(DOC)).parse();
+        let script = export(Format::Sh, DOC, &commands, &[], &Transcript::new(), &HashMap::new(), "ops.md").unwrap();
+
+        assert_eq!("default\n", run("sh", &script, "ops.sh", &["ops.sh"]));
+        assert_eq!("deploying to prod x3 (dry run)\n{{ prod }}\n",
+                   run("sh", &script, "ops.sh", &["ops.sh", "deploy", "-n", "prod", "--replicas=3"]));
+        assert_eq!("rolling back\n", run("sh", &script, "ops.sh", &["ops.sh", "deploy", "rollback"]));
+        assert_eq!("deploy: missing <env>\n", run("sh", &script, "ops.sh", &["ops.sh", "deploy"]));
+        assert!(run("sh", &script, "ops.sh", &["ops.sh", "--help"]).contains("    deploy <env> [--dry-run] [--replicas <replicas>] Deploys the current build\n"));
+    }
+
+    #[test]
+    fn should_export_fixtures_and_cwd_without_shadowing_commands() {
+        let doc = "# echo
+
+```file path=data/input.txt
+from the fixture
+```
+
+```sh
+echo \"$(cat data/input.txt)\"
+```
+
+```sh cwd=.
+ls
+```
+";
+        let mut rmd = Rmd::new(String::from(doc));
+        let commands = rmd.parse();
+        let script = export(Format::Sh, doc, &commands, rmd.fixtures(), &Transcript::new(), &HashMap::new(), "ops.md").unwrap();
+
+        assert_eq!("from the fixture\nops.sh\n", run("sh", &script, "ops.sh", &["ops.sh", "echo"]));
+    }
+
+    #[test]
+    fn should_refuse_blocks_reading_other_outputs() {
+        let doc = "# show\n\n```sh\necho ${{ blocks.build.stdout }}\n```\n";
+        let commands = Rmd::new(String::from(doc)).parse();
+        let err = export(Format::Sh, doc, &commands, &[], &Transcript::new(), &HashMap::new(), "ops.md").unwrap_err();
+
+        assert!(err.to_string().starts_with("the sh block at ops.md:4 reads another block's output"));
+    }
+
+    #[test]
+    fn should_refuse_blocks_run_apart_from_the_host() {
+        for attrs in ["image=alpine", "sandbox=strict", "memory=64M"] {
+            let doc = format!("# build\n\n```sh {}\nmake\n```\n", attrs);
+            let commands = Rmd::new(doc.clone()).parse();
+            let err = export(Format::Sh, &doc, &commands, &[], &Transcript::new(), &HashMap::new(), "ops.md").unwrap_err();
+
+            assert!(err.to_string().starts_with("the sh block at ops.md:4 "), "{}", err);
+            assert!(err.to_string().contains("only `rinput run`"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn should_export_block_policies() {
+        let doc = "# check
+
+```sh allow-failure=true
+echo lint; false
+echo unreached
+```
+
+```sh expect-exit=3
+exit 3
+```
+
+```sh retries=2 backoff=0s
+n=$(cat tries 2>/dev/null || echo 0); echo $((n + 1)) > tries
+[ \"$n\" -ge 2 ]
+echo passed after $n retries
+```
+
+# deploy
+
+```sh on-failure=rollback
+echo deploying; exit 4
+```
+
+```sh
+echo unreached
+```
+
+```sh id=rollback
+echo rolling back
+```
+";
+        let commands = Rmd::new(String::from(doc)).parse();
+        let script = export(Format::Sh, doc, &commands, &[], &Transcript::new(), &HashMap::new(), "ops.md").unwrap();
+
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("ops.sh"), &script).unwrap();
+        let run = |command: &str| process::Command::new("sh").args(["ops.sh", command]).current_dir(dir.path()).output().unwrap();
+
+        let output = run("check");
+        assert_eq!("lint\npassed after 2 retries\n", String::from_utf8_lossy(&output.stdout));
+        assert_eq!("allowed: exit status 1\nretry: exit status 1, retrying in 0s (1/2)\nretry: exit status 1, retrying in 0s (2/2)\n",
+                   String::from_utf8_lossy(&output.stderr));
+        assert!(output.status.success());
+
+        let output = run("deploy");
+        assert_eq!("deploying\nrolling back\n", String::from_utf8_lossy(&output.stdout));
+        assert_eq!(Some(4), output.status.code());
+    }
+
+    #[test]
+    fn should_export_make_targets() {
+        let commands = Rmd::new(String::from(DOC)).parse();
+        let makefile = export(Format::Make, DOC, &commands, &[], &Transcript::new(), &HashMap::new(), "ops.md").unwrap();
+
+        assert!(makefile.contains("# usage: make deploy env=<env> [dry_run=true] [replicas=<replicas>]\ndeploy:\n"));
+        assert!(makefile.contains("\techo \"deploying to $$env x$${replicas:-1} $${dry_run:+(dry run)}\"\n"));
+        if process::Command::new("make").arg("--version").output().is_ok() {
+            assert_eq!("deploying to prod x1 \n{{ prod }}\n", run("make", &makefile, "Makefile", &["-sf", "Makefile", "deploy", "env=prod"]));
+            assert_eq!("rolling back\n", run("make", &makefile, "Makefile", &["-sf", "Makefile", "deploy-rollback"]));
+        }
+    }
+
+    #[test]
+    fn should_export_just_recipes() {
+        let commands = Rmd::new(String::from(DOC)).parse();
+        let justfile = export(Format::Justfile, DOC, &commands, &[], &Transcript::new(), &HashMap::new(), "ops.md").unwrap();
+
+        assert!(justfile.starts_with("# Generated by `rinput export --format justfile` from ops.md\nset export\n\ndefault:\n"));
+        assert!(justfile.contains("# Deploys the current build\ndeploy env dry_run=\"\" replicas=\"\":\n    #!/bin/sh\n    set -e\n"));
+        assert!(justfile.contains("    echo \"{{{{ $env }}\"\n"));
+        assert!(justfile.contains("\ndeploy-rollback:\n"));
+    }
+}
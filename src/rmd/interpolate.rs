@@ -0,0 +1,223 @@
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind, Result};
+
+// What a block with an `id=` left behind for the blocks after it: its captured streams and the
+// `name=value` lines it appended to `$RINPUT_OUTPUT`.
+#[derive(Debug, Clone, Default)]
+pub struct BlockOutputs {
+    pub stdout: String,
+    pub stderr: String,
+    pub outputs: HashMap<String, String>,
+}
+
+impl BlockOutputs {
+    pub fn new(stdout: String, stderr: String, output_file: &str) -> Self {
+        Self {
+            stdout,
+            stderr,
+            outputs: parse_outputs(output_file),
+        }
+    }
+}
+
+// `name=value` per line, the same shape as GitHub's `$GITHUB_OUTPUT`; a later line wins
+pub fn parse_outputs(text: &str) -> HashMap<String, String> {
+    let mut outputs = HashMap::new();
+    for line in text.lines() {
+        let mut pair = line.splitn(2, '=');
+        let name = pair.next().unwrap_or("").trim();
+        if let (false, Some(value)) = (name.is_empty(), pair.next()) {
+            outputs.insert(name.to_string(), value.to_string());
+        }
+    }
+    outputs
+}
+
+// Every export also reaches later blocks as `RINPUT_<ID>_<NAME>`, e.g. `RINPUT_FETCH_IMAGE_ID`.
+pub fn exported_env(blocks: &HashMap<String, BlockOutputs>) -> Vec<(String, String)> {
+    let mut envs = vec![];
+    for (id, block) in blocks {
+        envs.push((env_name(id, "stdout"), trim_newlines(&block.stdout).to_string()));
+        for (name, value) in &block.outputs {
+            envs.push((env_name(id, name), value.clone()));
+        }
+    }
+    envs
+}
+
+fn env_name(id: &str, name: &str) -> String {
+    format!("RINPUT_{}_{}", id, name).chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+        .collect()
+}
+
+fn trim_newlines(text: &str) -> &str {
+    text.trim_end_matches(['\n', '\r'])
+}
+
+// Expands `${{ blocks.<id>.stdout }}`, `${{ blocks.<id>.stderr }}` and
+// `${{ blocks.<id>.outputs.<name> }}` into a string literal of `lang`, a single-quoted word for
+// the shells, so what one block printed is never run as code by the next: write
+// `echo ${{ blocks.fetch.stdout }}` or `print(${{ blocks.fetch.stdout }})`, without quotes of
+// your own. Only the double brace is special, so shell `${VAR}` is left alone; `$${{` writes a
+// literal `${{`. A reference that cannot be resolved is an error.
+pub fn interpolate(source: &str, lang: &str, blocks: &HashMap<String, BlockOutputs>) -> Result<String> {
+    let mut out = String::new();
+    let mut rest = source;
+    while let Some(start) = rest.find("${{") {
+        if rest[..start].ends_with('$') {
+            out.push_str(&rest[..start - 1]);
+            out.push_str("${{");
+            rest = &rest[start + 3..];
+            continue;
+        }
+
+        out.push_str(&rest[..start]);
+        let end = rest[start..].find("}}")
+            .ok_or_else(|| invalid(format!("unclosed `${{{{` in `{}`", rest[start..].lines().next().unwrap_or(""))))?;
+        out.push_str(&quote(lookup(rest[start + 3..start + end].trim(), blocks)?, lang)?);
+        rest = &rest[start + end + 2..];
+    }
+    out.push_str(rest);
+
+    Ok(out)
+}
+
+fn lookup<'a>(expr: &str, blocks: &'a HashMap<String, BlockOutputs>) -> Result<&'a str> {
+    let parts: Vec<&str> = expr.splitn(4, '.').collect();
+    let (id, field) = match parts.as_slice() {
+        ["blocks", id, field @ ..] if !field.is_empty() => (*id, field),
+        _ => {
+            let msg = format!("unknown reference `{}`, expected blocks.<id>.stdout, blocks.<id>.stderr or blocks.<id>.outputs.<name>", expr);
+            return Err(invalid(msg));
+        }
+    };
+
+    let block = blocks.get(id)
+        .ok_or_else(|| invalid(format!("`{}` refers to block `{}`, but no block with id={} has run yet", expr, id, id)))?;
+    match field {
+        ["stdout"] => Ok(trim_newlines(&block.stdout)),
+        ["stderr"] => Ok(trim_newlines(&block.stderr)),
+        ["outputs", name] => block.outputs.get(*name).map(|value| value.as_str())
+            .ok_or_else(|| invalid(format!("`{}`: block `{}` did not export `{}`", expr, id, name))),
+        _ => Err(invalid(format!("unknown field in `{}`, expected stdout, stderr or outputs.<name>", expr))),
+    }
+}
+
+// Quotes `value` as a literal of the block's language, so what an earlier block printed is data
+// rather than code. A language without a rule here is refused rather than guessed at.
+fn quote(value: &str, lang: &str) -> Result<String> {
+    let quoted = match lang {
+        // single quotes expand nothing in any of these shells
+        "sh" | "bash" | "shell" | "zsh" | "ksh" | "mksh" | "dash" | "ash" | "fish" => format!("'{}'", value.replace('\'', "'\\''")),
+        "php" => format!("'{}'", value.replace('\\', "\\\\").replace('\'', "\\'")),
+        "js" | "javascript" | "mjs" | "node" | "ts" | "typescript" | "py" | "python" | "python3" | "rust"
+        | "rb" | "ruby" | "perl" => double_quoted(value, lang),
+        lang => {
+            let msg = format!("cannot quote `${{{{ ... }}}}` for a {} block, use blocks.<id>.outputs through the environment instead", lang);
+            return Err(invalid(msg));
+        }
+    };
+    Ok(quoted)
+}
+
+fn double_quoted(value: &str, lang: &str) -> String {
+    let mut literal = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => literal.push_str("\\\""),
+            '\\' => literal.push_str("\\\\"),
+            '\n' => literal.push_str("\\n"),
+            '\r' => literal.push_str("\\r"),
+            '\t' => literal.push_str("\\t"),
+            // `#{...}` interpolates in a ruby string
+            '#' if lang == "rb" || lang == "ruby" => literal.push_str("\\#"),
+            // and `$var`, `@list` and `@{[ ... ]}` in a perl one
+            '$' | '@' if lang == "perl" => {
+                literal.push('\\');
+                literal.push(c);
+            }
+            c if c.is_control() && lang == "rust" => literal.push_str(&format!("\\u{{{:x}}}", c as u32)),
+            c if c.is_control() => literal.push_str(&format!("\\u{:04x}", c as u32)),
+            c => literal.push(c),
+        }
+    }
+    literal.push('"');
+    literal
+}
+
+fn invalid(msg: String) -> Error {
+    Error::new(ErrorKind::InvalidInput, msg)
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use crate::rmd::interpolate::{BlockOutputs, exported_env, interpolate};
+
+    fn blocks() -> HashMap<String, BlockOutputs> {
+        let mut blocks = HashMap::new();
+        blocks.insert(String::from("fetch"), BlockOutputs::new(String::from("i-0abc\n"), String::new(), "image-id=ami-42\n\nbroken line\n"));
+        blocks
+    }
+
+    #[test]
+    fn should_expand_block_references() {
+        let source = "start ${{ blocks.fetch.stdout }} --image ${{blocks.fetch.outputs.image-id}}\n";
+
+        assert_eq!("start 'i-0abc' --image 'ami-42'\n", interpolate(source, "sh", &blocks()).unwrap());
+        assert_eq!("print(\"i-0abc\")", interpolate("print(${{ blocks.fetch.stdout }})", "python", &blocks()).unwrap());
+    }
+
+    #[test]
+    fn should_quote_hostile_values() {
+        let mut blocks = HashMap::new();
+        blocks.insert(String::from("evil"), BlockOutputs::new(String::from("$(touch pwned)'; rm -rf ~ #\"\\\n"), String::new(), ""));
+        let source = "${{ blocks.evil.stdout }}";
+
+        assert_eq!("'$(touch pwned)'\\''; rm -rf ~ #\"\\'", interpolate(source, "sh", &blocks).unwrap());
+        assert_eq!("\"$(touch pwned)'; rm -rf ~ #\\\"\\\\\"", interpolate(source, "python", &blocks).unwrap());
+        assert_eq!("\"$(touch pwned)'; rm -rf ~ \\#\\\"\\\\\"", interpolate(source, "ruby", &blocks).unwrap());
+        assert_eq!("'$(touch pwned)\\'; rm -rf ~ #\"\\\\'", interpolate(source, "php", &blocks).unwrap());
+
+        let output = std::process::Command::new("sh").arg("-c").arg(format!("printf %s {}", interpolate(source, "sh", &blocks).unwrap())).output().unwrap();
+        assert_eq!("$(touch pwned)'; rm -rf ~ #\"\\", String::from_utf8_lossy(&output.stdout));
+
+        blocks.insert(String::from("perl"), BlockOutputs::new(String::from("$ENV{HOME} @{[ `id` ]}"), String::new(), ""));
+        assert_eq!("\"\\$ENV{HOME} \\@{[ `id` ]}\"", interpolate("${{ blocks.perl.stdout }}", "perl", &blocks).unwrap());
+        assert_eq!("'$ENV{HOME} @{[ `id` ]}'", interpolate("${{ blocks.perl.stdout }}", "ksh", &blocks).unwrap());
+        let script = interpolate("print ${{ blocks.perl.stdout }}", "perl", &blocks).unwrap();
+        if let Ok(output) = std::process::Command::new("perl").arg("-e").arg(script).output() {
+            assert_eq!("$ENV{HOME} @{[ `id` ]}", String::from_utf8_lossy(&output.stdout));
+        }
+        // a language without quoting rules gets nothing rather than a guess
+        assert!(interpolate(source, "lua", &blocks).unwrap_err().to_string().contains("cannot quote"));
+    }
+
+    #[test]
+    fn should_leave_shell_syntax_and_escapes_alone() {
+        let source = "echo ${HOME} $PATH $${{ blocks.fetch.stdout }}";
+
+        assert_eq!("echo ${HOME} $PATH ${{ blocks.fetch.stdout }}", interpolate(source, "sh", &blocks()).unwrap());
+    }
+
+    #[test]
+    fn should_reject_unknown_references() {
+        assert!(interpolate("${{ blocks.missing.stdout }}", "sh", &blocks()).is_err());
+        assert!(interpolate("${{ blocks.fetch.outputs.nope }}", "sh", &blocks()).is_err());
+        assert!(interpolate("${{ env.HOME }}", "sh", &blocks()).is_err());
+        assert!(interpolate("${{ blocks.fetch.stdout", "sh", &blocks()).is_err());
+    }
+
+    #[test]
+    fn should_export_outputs_as_env() {
+        let mut envs = exported_env(&blocks());
+        envs.sort();
+
+        assert_eq!(vec![
+            (String::from("RINPUT_FETCH_IMAGE_ID"), String::from("ami-42")),
+            (String::from("RINPUT_FETCH_STDOUT"), String::from("i-0abc")),
+        ], envs);
+    }
+}
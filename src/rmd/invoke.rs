@@ -0,0 +1,246 @@
+use std::io::{Error, ErrorKind, Result};
+
+use crate::rmd::command::{Command, OptionFlag};
+use crate::rmd::outline::{command_tree, command_word, commands_root};
+
+// Picks the command named by the first of `words`, as in `rinput deploy staging prod --dry-run`,
+// and binds the words after it to its arguments and flags. Every block under the command's
+// heading is returned, in document order, with the values set; its subcommands are not. Blocks
+// their `on-failure` names come along, the runner only runs those when something fails.
+pub fn resolve(commands: &[Command], words: &[String]) -> Result<Vec<Command>> {
+    let tree = command_tree(commands);
+    let mut nodes = commands_root(&tree);
+    let mut found: Option<&Command> = None;
+    let mut used = 0;
+    for word in words {
+        match nodes.iter().rfind(|node| names(node, word)) {
+            Some(node) => {
+                found = Some(node);
+                nodes = &node.subcommands;
+                used += 1;
+            }
+            None => break,
+        }
+    }
+
+    let found = match found {
+        Some(found) => found,
+        None => {
+            let word = words.first().map(String::as_str).unwrap_or("");
+            let msg = format!("no command `{}`, expected one of: {}", word, choices(nodes));
+            return Err(Error::new(ErrorKind::NotFound, msg));
+        }
+    };
+    let mut blocks: Vec<Command> = commands.iter().filter(|cmd| cmd.path == found.path).cloned().collect();
+    if blocks.is_empty() {
+        let msg = format!("`{}` has no blocks of its own, expected one of its subcommands: {}",
+                          words[..used].join(" "), choices(&found.subcommands));
+        return Err(Error::new(ErrorKind::InvalidInput, msg));
+    }
+
+    // blocks under one heading share its arguments and options
+    let (args, flags) = bind(&blocks[0], &words[used..])?;
+    for block in &mut blocks {
+        for (arg, value) in block.required_args.iter_mut().zip(&args) {
+            arg.val = value.clone();
+        }
+        for flag in &mut block.option_flags {
+            if let Some((_, value)) = flags.iter().find(|(name, _)| *name == flag.name) {
+                flag.val = value.clone();
+            }
+        }
+    }
+    Ok(with_handlers(commands, blocks))
+}
+
+// `blocks` followed by the blocks of `commands` their `on-failure` names, when not among them already
+pub fn with_handlers(commands: &[Command], mut blocks: Vec<Command>) -> Vec<Command> {
+    let handlers: Vec<Command> = commands.iter()
+        .filter(|cmd| cmd.script.attr("id").is_some_and(|id| handles(&blocks, id)))
+        .filter(|cmd| !blocks.iter().any(|block| block.script.file == cmd.script.file && block.script.line == cmd.script.line))
+        .cloned()
+        .collect();
+    blocks.extend(handlers);
+    blocks
+}
+
+// The top-level commands rinput's own subcommands hide: `rinput run` is always rinput's, the
+// runbook's `run` is reached with `rinput -- run`.
+pub fn shadowed(commands: &[Command], builtins: &[&str]) -> Vec<String> {
+    let tree = command_tree(commands);
+    let mut words: Vec<String> = commands_root(&tree).iter()
+        .map(|node| command_word(&node.name))
+        .filter(|word| builtins.contains(&word.as_str()))
+        .collect();
+    words.dedup();
+    words
+}
+
+fn handles(blocks: &[Command], id: &str) -> bool {
+    blocks.iter().any(|block| block.script.attr("on-failure") == Some(id))
+}
+
+fn names(node: &Command, word: &str) -> bool {
+    command_word(&node.name) == word.to_lowercase() || node.name.eq_ignore_ascii_case(word)
+}
+
+fn choices(nodes: &[Command]) -> String {
+    let mut words: Vec<String> = nodes.iter().map(|node| command_word(&node.name)).filter(|word| !word.is_empty()).collect();
+    words.dedup();
+    words.join(", ")
+}
+
+// the values for the command's arguments, in order, and for its flags, by flag name
+type Bound = (Vec<String>, Vec<(String, String)>);
+
+fn bind(cmd: &Command, words: &[String]) -> Result<Bound> {
+    let mut args = vec![];
+    let mut flags: Vec<(String, String)> = vec![];
+    let mut words = words.iter();
+    let mut flags_done = false;
+    while let Some(word) = words.next() {
+        if flags_done || !word.starts_with('-') || word == "-" {
+            args.push(word.clone());
+            continue;
+        }
+        if word == "--" {
+            flags_done = true;
+            continue;
+        }
+
+        let (key, inline) = match word.find('=') {
+            Some(index) if word.starts_with("--") => (&word[..index], Some(word[index + 1..].to_string())),
+            _ => (word.as_str(), None),
+        };
+        let flag = find_flag(cmd, key)
+            .ok_or_else(|| invalid(format!("`{}` has no option {}", cmd.name, key)))?;
+        let value = if flag.takes_value {
+            match inline.or_else(|| words.next().cloned()) {
+                Some(value) => value,
+                None => return Err(invalid(format!("{} needs a value", key))),
+            }
+        } else if inline.is_some() {
+            return Err(invalid(format!("{} does not take a value", key)));
+        } else {
+            String::from("true")
+        };
+        if flag.validate_as_number && value.parse::<f64>().is_err() {
+            return Err(invalid(format!("{} expects a number, got `{}`", key, value)));
+        }
+
+        match flags.iter_mut().find(|(name, _)| *name == flag.name) {
+            Some((_, previous)) if flag.multiple && flag.takes_value => *previous = format!("{} {}", previous, value),
+            Some((_, previous)) => *previous = value,
+            None => flags.push((flag.name.clone(), value)),
+        }
+    }
+
+    if args.len() > cmd.required_args.len() {
+        return Err(invalid(format!("`{}` takes {} argument(s), got {}", cmd.name, cmd.required_args.len(), args.len())));
+    }
+    if let Some(missing) = cmd.required_args.get(args.len()) {
+        return Err(invalid(format!("`{}` is missing its <{}> argument", cmd.name, missing.name)));
+    }
+    Ok((args, flags))
+}
+
+fn find_flag<'a>(cmd: &'a Command, key: &str) -> Option<&'a OptionFlag> {
+    cmd.option_flags.iter().find(|flag| match key.strip_prefix("--") {
+        Some(long) => !flag.long.is_empty() && flag.long == long,
+        None => !flag.short.is_empty() && key.strip_prefix('-') == Some(flag.short.as_str()),
+    })
+}
+
+fn invalid(msg: String) -> Error {
+    Error::new(ErrorKind::InvalidInput, msg)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::rmd::invoke::{resolve, shadowed};
+    use crate::rmd::Rmd;
+
+    const DOC: &str = "# Ops
+
+## deploy (env)
+
+**OPTIONS**
+* dry-run
+    * flags: -n --dry-run
+* replicas
+    * flags: -r --replicas
+    * type: number
+
+```sh
+echo deploying to $env
+```
+
+```sh on-failure=cleanup
+echo done
+```
+
+### rollback
+
+```sh
+echo rolling back
+```
+
+## Database
+
+### migrate
+
+```sh
+echo migrating
+```
+
+## cleanup
+
+```sh id=cleanup
+echo cleaning up
+```
+";
+
+    fn words(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn should_bind_arguments_and_flags() {
+        let commands = Rmd::new(String::from(DOC)).parse();
+        let blocks = resolve(&commands, &words("deploy prod -n --replicas=3")).unwrap();
+
+        assert_eq!(3, blocks.len());
+        assert_eq!("echo cleaning up\n", blocks[2].script.source);
+        for block in &blocks[..2] {
+            assert_eq!("prod", block.required_args[0].val);
+            assert_eq!("true", block.option_flags[0].val);
+            assert_eq!("3", block.option_flags[1].val);
+        }
+        assert_eq!(vec!["echo rolling back\n"], resolve(&commands, &words("Deploy rollback"))
+            .unwrap().iter().map(|block| block.script.source.as_str()).collect::<Vec<_>>());
+        assert_eq!(1, resolve(&commands, &words("database migrate")).unwrap().len());
+    }
+
+    #[test]
+    fn should_reject_bad_command_lines() {
+        let commands = Rmd::new(String::from(DOC)).parse();
+        let error = |line: &str| resolve(&commands, &words(line)).unwrap_err().to_string();
+
+        assert_eq!("no command `build`, expected one of: deploy, database, cleanup", error("build"));
+        assert_eq!("`database` has no blocks of its own, expected one of its subcommands: migrate", error("database"));
+        assert_eq!("`deploy` is missing its <env> argument", error("deploy"));
+        assert_eq!("`deploy` takes 1 argument(s), got 2", error("deploy prod staging"));
+        assert_eq!("`deploy` has no option --force", error("deploy prod --force"));
+        assert_eq!("--replicas expects a number, got `many`", error("deploy prod --replicas many"));
+    }
+
+    #[test]
+    fn should_find_commands_shadowed_by_builtins() {
+        let doc = "# Ops\n\n## Run\n\n```sh\necho run\n```\n\n## deploy\n\n```sh\necho deploy\n```\n";
+        let commands = Rmd::new(String::from(doc)).parse();
+
+        assert_eq!(vec!["run"], shadowed(&commands, &["run", "list"]));
+        assert!(shadowed(&commands, &["list"]).is_empty());
+        assert_eq!(1, resolve(&commands, &words("run")).unwrap().len());
+    }
+}
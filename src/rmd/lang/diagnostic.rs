@@ -0,0 +1,89 @@
+use colored::*;
+
+use crate::rmd::json::Json;
+
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    pub level: String,
+    pub code: String,
+    pub message: String,
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub source_line: String,
+    pub highlight: (usize, usize),
+    pub label: String,
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn new(level: String, message: String) -> Self {
+        Self {
+            level,
+            code: "".to_string(),
+            message,
+            file: "".to_string(),
+            line: 0,
+            column: 0,
+            source_line: "".to_string(),
+            highlight: (0, 0),
+            label: "".to_string(),
+            notes: vec![],
+        }
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.level == "error"
+    }
+
+    // where it points, for a JSON log event
+    pub fn fields(&self) -> Vec<(String, Json)> {
+        let text = |value: &str| Json::String(value.to_string());
+        vec![
+            (String::from("code"), text(&self.code)),
+            (String::from("file"), text(&self.file)),
+            (String::from("line"), Json::Number(self.line as f64)),
+            (String::from("column"), Json::Number(self.column as f64)),
+            (String::from("label"), text(&self.label)),
+            (String::from("notes"), Json::Array(self.notes.iter().map(|note| text(note)).collect())),
+        ]
+    }
+
+    pub fn render(&self) -> String {
+        let level = if self.code.is_empty() {
+            self.level.clone()
+        } else {
+            format!("{}[{}]", self.level, self.code)
+        };
+        let level = match self.level.as_ref() {
+            "error" => level.red().bold(),
+            "warning" => level.yellow().bold(),
+            _ => level.cyan().bold(),
+        };
+
+        let mut out = format!("{}{} {}\n", level, ":".bold(), self.message.bold());
+        let gutter = " ".repeat(self.line.to_string().len());
+        if !self.file.is_empty() {
+            out.push_str(&format!("{}{} {}:{}:{}\n", gutter, "-->".blue().bold(), self.file, self.line, self.column));
+        }
+
+        if !self.source_line.is_empty() {
+            let (start, end) = self.highlight;
+            let marker = format!("{}{} {}",
+                " ".repeat(start.saturating_sub(1)),
+                "^".repeat(end.saturating_sub(start).max(1)),
+                self.label);
+            let marker = if self.is_error() { marker.red().bold() } else { marker.yellow().bold() };
+
+            out.push_str(&format!("{} {}\n", gutter, "|".blue().bold()));
+            out.push_str(&format!("{} {} {}\n", self.line.to_string().blue().bold(), "|".blue().bold(), self.source_line));
+            out.push_str(&format!("{} {} {}\n", gutter, "|".blue().bold(), marker));
+        }
+
+        for note in &self.notes {
+            out.push_str(&format!("{} {} {}\n", gutter, "=".blue().bold(), note));
+        }
+
+        out
+    }
+}
@@ -1,31 +1,99 @@
-use std::{env, fs};
+use std::{env, fmt, fs, io};
 use std::collections::hash_map::RandomState;
 use std::collections::HashMap;
 use std::fs::File;
-use std::io::Write;
+use std::io::{ErrorKind, Result, Write};
 use std::path::PathBuf;
-use std::process::Command;
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::mpsc::Sender;
 
 use regex::{Captures, Regex};
 
+use crate::rmd::log;
+
+pub use self::diagnostic::Diagnostic;
+pub use self::node_exec::NodeExec;
 pub use self::python_exec::PythonExec;
 pub use self::rust_exec::RustExec;
 
+mod diagnostic;
+mod node_exec;
 mod python_exec;
 mod rust_exec;
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Dependency {
     pub name: String,
     pub version: String,
     pub artifact_id: String,
     pub group_id: String,
+    pub features: Vec<String>,
+    pub path: String,
+    pub git: String,
+    pub branch: String,
+    pub tag: String,
+    pub rev: String,
+    // `cargo`, `npm` or `pip`; empty when the dependency applies to whichever executor reads it
+    pub namespace: String,
+}
+
+impl Dependency {
+    pub fn new(name: String) -> Dependency {
+        Dependency {
+            name,
+            version: "".to_string(),
+            artifact_id: "".to_string(),
+            group_id: "".to_string(),
+            features: vec![],
+            path: "".to_string(),
+            git: "".to_string(),
+            branch: "".to_string(),
+            tag: "".to_string(),
+            rev: "".to_string(),
+            namespace: "".to_string(),
+        }
+    }
+
+    pub fn applies_to(&self, namespace: &str) -> bool {
+        self.namespace.is_empty() || self.namespace == namespace
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct DepError {
+    pub input: String,
+    pub message: String,
+}
+
+impl DepError {
+    fn new(input: &str, message: &str) -> DepError {
+        DepError {
+            input: input.to_string(),
+            message: message.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for DepError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid dependency `{}`: {}", self.input, self.message)
+    }
+}
+
+impl std::error::Error for DepError {}
+
+impl From<DepError> for io::Error {
+    fn from(err: DepError) -> io::Error {
+        io::Error::new(ErrorKind::InvalidInput, err)
+    }
 }
 
 #[derive(Clone, Debug)]
 pub struct ProjectInfo {
     pub deps: Vec<Dependency>,
     pub name: String,
+    pub edition: String,
+    pub release: bool,
 }
 
 impl ProjectInfo {
@@ -33,20 +101,53 @@ impl ProjectInfo {
         ProjectInfo {
             deps: vec![],
             name: "".to_string(),
+            edition: "".to_string(),
+            release: false,
         }
     }
+
+    // reads the `rinput-*` comments of a snippet, keeping only the deps meant for `namespace`
+    pub fn from_source(source: &str, namespace: &str) -> Result<ProjectInfo> {
+        let map = build_key_value_from_comment(source.to_string());
+        let mut project_info = ProjectInfo::new();
+        project_info.name = String::from("hello");
+
+        for (key, value) in map {
+            let value = value.trim().to_string();
+            match &key[..] {
+                "deps" => {
+                    project_info.deps = parse_deps(value)?.into_iter()
+                        .filter(|dep| dep.applies_to(namespace))
+                        .collect();
+                }
+                "name" => {
+                    project_info.name = value;
+                }
+                "edition" => {
+                    project_info.edition = value;
+                }
+                "release" => {
+                    project_info.release = value == "true";
+                }
+                _ => {}
+            }
+        }
+
+        Ok(project_info)
+    }
 }
 
 pub trait LangExecutor {
-    fn parse_project_info(&mut self) -> ProjectInfo;
+    fn parse_project_info(&mut self) -> Result<ProjectInfo>;
     fn build_project(&mut self);
-    fn install_dependency(&self);
+    fn install_dependency(&self) -> Result<()>;
     fn try_run(&self);
-    fn execute(&mut self) -> Command;
+    fn execute(&mut self) -> Result<Command>;
 }
 
 pub trait CompiledLangExecutor: LangExecutor {
     fn compile(&self) -> Command;
+    fn diagnostics(&self, output: &str) -> Vec<Diagnostic>;
 }
 
 
@@ -69,9 +170,48 @@ pub fn create_lang_dir(lang: String, project_name: String) -> PathBuf {
     dir
 }
 
+// FNV-1a, which unlike std's hasher gives the same hash on every Rust release, so cached builds
+// and project dirs outlive a toolchain update
+pub fn content_hash(source: &str) -> String {
+    let hash = source.bytes().fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3)
+    });
+    format!("{:016x}", hash)
+}
+
+// Runs a build step such as `npm install`, with what it prints going to `output` instead of the
+// terminal when there is one.
+pub fn build_step(step: &mut Command, output: Option<&Sender<Vec<u8>>>) -> Result<ExitStatus> {
+    match output {
+        Some(output) => {
+            let done = step.stdin(Stdio::null()).output()?;
+            let _ = output.send([done.stdout, done.stderr].concat());
+            Ok(done.status)
+        }
+        None => step.status(),
+    }
+}
+
+// a build step that went wrong, told in the same place as what it printed
+pub fn report(output: Option<&Sender<Vec<u8>>>, line: &str) {
+    match output {
+        Some(output) => {
+            let _ = output.send(format!("{}\n", line).into_bytes());
+        }
+        None => log::error("build", line),
+    }
+}
+
+pub fn find_executable(name: &str) -> Option<PathBuf> {
+    let paths = env::var_os("PATH")?;
+    env::split_paths(&paths)
+        .map(|dir| dir.join(name))
+        .find(|candidate| candidate.is_file())
+}
+
 pub fn build_key_value_from_comment(str: String) -> HashMap<String, String> {
     let mut info = HashMap::new();
-    let re = Regex::new(r"(?x)//\s?rinput-(?P<key>([a-zA-z]+)):\s?(?P<value>(.*))").unwrap();
+    let re = Regex::new(r"(?x)(//|\#)\s?rinput-(?P<key>([a-zA-z]+)):\s?(?P<value>(.*))").unwrap();
     let mut split = str.split("\n");
     let vec: Vec<&str> = split.collect();
 
@@ -90,37 +230,99 @@ pub fn build_key_value_from_comment(str: String) -> HashMap<String, String> {
     info
 }
 
-pub fn parse_deps(str: String) -> Vec<Dependency> {
-    let mut split = str.split(",");
-    let vec: Vec<&str> = split.collect();
-    let re = Regex::new(r"(?x)(?P<name>([a-zA-Z-]+))(;(?P<key>(\w+))=(?P<version>([a-zA-Z0-9.]+)))?").unwrap();
-
+// Dependencies are comma separated, each one of
+//
+//     [namespace:]name[@version][;key=value]...
+//
+// where the keys are `version`, `features` (separated by `|`), `path`, `git`, `branch`, `tag`
+// and `rev`, and the namespace limits the dependency to one executor (`cargo:`, `npm:`, `pip:`).
+pub fn parse_deps(str: String) -> std::result::Result<Vec<Dependency>, DepError> {
     let mut deps: Vec<Dependency> = Vec::new();
-    for line in vec {
-        match re.captures(&line) {
-            None => {}
-            Some(caps) => {
-                let name = &caps["name"];
-                let version = &caps["version"];
-
-                let dep = Dependency {
-                    name: String::from(name),
-                    version: String::from(version),
-                    artifact_id: "".to_string(),
-                    group_id: "".to_string(),
-                };
-                deps.push(dep);
+    for entry in str.split(',').map(|entry| entry.trim()).filter(|entry| !entry.is_empty()) {
+        deps.push(parse_dep(entry)?);
+    }
+
+    Ok(deps)
+}
+
+fn parse_dep(entry: &str) -> std::result::Result<Dependency, DepError> {
+    let mut parts = entry.split(';');
+    let mut head = parts.next().unwrap_or("").trim();
+
+    let mut namespace = "";
+    if let Some(index) = head.find(':') {
+        namespace = match &head[..index] {
+            "cargo" | "rust" | "crates" => "cargo",
+            "npm" | "node" | "js" => "npm",
+            "pip" | "python" | "py" => "pip",
+            _ => return Err(DepError::new(entry, &format!("unknown namespace `{}`", &head[..index]))),
+        };
+        head = &head[index + 1..];
+    }
+
+    // scoped npm packages start with `@`, so the version separator is the next `@`
+    let (name, version) = match head.char_indices().skip(1).find(|&(_, c)| c == '@') {
+        Some((index, _)) => (&head[..index], Some(&head[index + 1..])),
+        None => (head, None),
+    };
+
+    let valid_name = Regex::new(r"^@?[A-Za-z0-9_.-]+(/[A-Za-z0-9_.-]+)?$").unwrap();
+    if name.is_empty() {
+        return Err(DepError::new(entry, "missing dependency name"));
+    }
+    if !valid_name.is_match(name) {
+        return Err(DepError::new(entry, &format!("`{}` is not a valid package name", name)));
+    }
+
+    let mut dep = Dependency::new(String::from(name));
+    dep.namespace = String::from(namespace);
+    if let Some(version) = version {
+        if version.is_empty() {
+            return Err(DepError::new(entry, "missing version after `@`"));
+        }
+        dep.version = String::from(version);
+    }
+
+    for attr in parts {
+        let attr = attr.trim();
+        let mut pair = attr.splitn(2, '=');
+        let key = pair.next().unwrap_or("").trim();
+        let value = match pair.next().map(|value| value.trim()) {
+            Some(value) if !value.is_empty() => value,
+            _ => return Err(DepError::new(entry, &format!("expected `key=value`, found `{}`", attr))),
+        };
+
+        match key {
+            "version" => {
+                if !dep.version.is_empty() {
+                    return Err(DepError::new(entry, "version is given twice"));
+                }
+                dep.version = String::from(value);
             }
+            "features" => dep.features = value.split('|').map(|f| f.trim().to_string()).collect(),
+            "path" => dep.path = String::from(value),
+            "git" => dep.git = String::from(value),
+            "branch" => dep.branch = String::from(value),
+            "tag" => dep.tag = String::from(value),
+            "rev" => dep.rev = String::from(value),
+            _ => return Err(DepError::new(entry, &format!("unknown key `{}`", key))),
         }
     }
 
-    deps
+    if !dep.path.is_empty() && !dep.git.is_empty() {
+        return Err(DepError::new(entry, "`path` and `git` cannot both be set"));
+    }
+    if dep.git.is_empty() && !(dep.branch.is_empty() && dep.tag.is_empty() && dep.rev.is_empty()) {
+        return Err(DepError::new(entry, "`branch`, `tag` and `rev` need a `git` source"));
+    }
+
+    Ok(dep)
 }
 
 
 #[cfg(test)]
 mod test {
-    use crate::rmd::lang::{build_key_value_from_comment, LangExecutor, RustExec, parse_deps};
+    use crate::rmd::lang::{build_key_value_from_comment, LangExecutor, ProjectInfo, RustExec, parse_deps};
 
     #[test]
     fn should_parse_key_values() {
@@ -135,7 +337,7 @@ mod test {
     #[test]
     fn should_parse_one_dep() {
         let string = String::from("    colored;version=1.8.0");
-        let deps = parse_deps(string);
+        let deps = parse_deps(string).unwrap();
 
         assert_eq!(1, deps.len());
         let first_dep = deps.get(0).unwrap();
@@ -146,11 +348,62 @@ mod test {
     #[test]
     fn should_parse_deps() {
         let string = String::from("colored;version=1.8.0, pulldown-cmark;version=0.7");
-        let deps = parse_deps(string);
+        let deps = parse_deps(string).unwrap();
 
         assert_eq!(2, deps.len());
         let first_dep = deps.get(1).unwrap();
         assert_eq!("pulldown-cmark", first_dep.name);
         assert_eq!("0.7", first_dep.version);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn should_parse_dep_features_and_sources() {
+        let string = String::from("serde;version=1.0;features=derive|rc, local-lib;path=../local-lib");
+        let deps = parse_deps(string).unwrap();
+
+        assert_eq!(2, deps.len());
+        assert_eq!(vec!["derive", "rc"], deps[0].features);
+        assert_eq!("../local-lib", deps[1].path);
+        assert_eq!("", deps[1].version);
+    }
+
+    #[test]
+    fn should_parse_bare_names_and_at_versions() {
+        let deps = parse_deps(String::from("colored, base64@0.12, my_crate2, @types/node@14.0")).unwrap();
+
+        assert_eq!(4, deps.len());
+        assert_eq!("", deps[0].version);
+        assert_eq!(("base64", "0.12"), (&deps[1].name[..], &deps[1].version[..]));
+        assert_eq!("my_crate2", deps[2].name);
+        assert_eq!(("@types/node", "14.0"), (&deps[3].name[..], &deps[3].version[..]));
+    }
+
+    #[test]
+    fn should_parse_git_sources_and_namespaces() {
+        let deps = parse_deps(String::from("cargo:tokio;git=https://github.com/tokio-rs/tokio;branch=master, npm:lodash@4.17")).unwrap();
+
+        assert_eq!("cargo", deps[0].namespace);
+        assert_eq!("https://github.com/tokio-rs/tokio", deps[0].git);
+        assert_eq!("master", deps[0].branch);
+        assert!(!deps[1].applies_to("cargo"));
+        assert!(deps[1].applies_to("npm"));
+    }
+
+    #[test]
+    fn should_report_invalid_deps() {
+        assert_eq!("unknown key `verison`", parse_deps(String::from("colored;verison=1.8")).unwrap_err().message);
+        assert_eq!("unknown namespace `gem`", parse_deps(String::from("gem:rails")).unwrap_err().message);
+        assert_eq!("missing version after `@`", parse_deps(String::from("colored@")).unwrap_err().message);
+        assert_eq!("version is given twice", parse_deps(String::from("colored@1;version=2")).unwrap_err().message);
+        assert!(parse_deps(String::from("bad name!")).is_err());
+        assert!(parse_deps(String::from("x;path=a;git=b")).is_err());
+    }
+
+    #[test]
+    fn should_keep_deps_for_namespace() {
+        let info = ProjectInfo::from_source("# rinput-deps: pip:requests, npm:lodash, six", "pip").unwrap();
+
+        let names: Vec<&str> = info.deps.iter().map(|dep| &dep.name[..]).collect();
+        assert_eq!(vec!["requests", "six"], names);
+    }
+}
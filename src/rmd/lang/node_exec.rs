@@ -0,0 +1,278 @@
+use std::{env, process};
+use std::io::{Error, ErrorKind, Result};
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::mpsc::Sender;
+
+use regex::Regex;
+
+use super::{LangExecutor, ProjectInfo};
+use crate::rmd::lang::{build_step, content_hash, create_lang_dir, find_executable, write_content_to_file};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum NodeDialect {
+    Script,
+    Module,
+    TypeScript,
+}
+
+pub struct NodeExec {
+    filename: String,
+    source_code: String,
+    // the node binary, `node` unless configured otherwise
+    program: String,
+    dialect: NodeDialect,
+    dir: String,
+    dir_buf: PathBuf,
+    project: ProjectInfo,
+    // where what npm and tsc print goes instead of the terminal
+    output: Option<Sender<Vec<u8>>>,
+}
+
+impl NodeExec {
+    pub fn new(source: String) -> NodeExec {
+        let dialect = if is_es_module(&source) {
+            NodeDialect::Module
+        } else {
+            NodeDialect::Script
+        };
+
+        NodeExec::with_dialect(source, dialect)
+    }
+
+    pub fn typescript(source: String) -> NodeExec {
+        NodeExec::with_dialect(source, NodeDialect::TypeScript)
+    }
+
+    fn with_dialect(source: String, dialect: NodeDialect) -> NodeExec {
+        NodeExec {
+            filename: "".to_string(),
+            source_code: source.to_string(),
+            program: String::from("node"),
+            dialect,
+            dir: "".to_string(),
+            dir_buf: Default::default(),
+            project: ProjectInfo::new(),
+            output: None,
+        }
+    }
+
+    pub fn with_program(mut self, program: Option<&String>) -> NodeExec {
+        if let Some(program) = program {
+            self.program = program.clone();
+        }
+        self
+    }
+
+    pub fn with_output(mut self, output: Option<Sender<Vec<u8>>>) -> NodeExec {
+        self.output = output;
+        self
+    }
+
+    fn create_package_json(&self) -> String {
+        let mut deps = vec![];
+        for dep in self.project.deps.clone() {
+            let version = if !dep.path.is_empty() {
+                format!("file:{}", dep.path)
+            } else if !dep.git.is_empty() {
+                match vec![&dep.branch, &dep.tag, &dep.rev].into_iter().find(|r| !r.is_empty()) {
+                    Some(reference) => format!("git+{}#{}", dep.git, reference),
+                    None => format!("git+{}", dep.git),
+                }
+            } else if dep.version.is_empty() {
+                String::from("*")
+            } else {
+                dep.version
+            };
+            deps.push(format!("    \"{}\": \"{}\"", dep.name, version));
+        }
+
+        let package = format!("{{
+  \"name\": \"{}\",
+  \"version\": \"0.1.0\",
+  \"private\": true,
+  \"dependencies\": {{
+{}
+  }}
+}}
+", self.project.name, deps.join(",\n"));
+
+        write_content_to_file(package.clone(), self.dir_buf.join("package.json"));
+        package
+    }
+
+    fn entry_file(&self) -> &'static str {
+        match self.dialect {
+            NodeDialect::Script => "main.js",
+            NodeDialect::Module => "main.mjs",
+            NodeDialect::TypeScript => "main.ts",
+        }
+    }
+
+    fn local_bin(&self, name: &str) -> Option<PathBuf> {
+        let bin = self.dir_buf.join("node_modules").join(".bin").join(name);
+        if bin.is_file() { Some(bin) } else { None }
+    }
+
+    fn typescript_command(&self) -> Result<Command> {
+        // deno resolves its own imports, so only prefer it when no npm deps were requested
+        if self.project.deps.is_empty() {
+            if let Some(deno) = find_executable("deno") {
+                let mut child = process::Command::new(deno);
+                child.arg("run").arg("--quiet").arg("--allow-all").arg(self.dir.clone());
+                return Ok(child);
+            }
+        }
+
+        if let Some(ts_node) = self.local_bin("ts-node").or_else(|| find_executable("ts-node")) {
+            let mut child = process::Command::new(ts_node);
+            child.arg(self.dir.clone());
+            return Ok(child);
+        }
+
+        let tsc = self.local_bin("tsc").or_else(|| find_executable("tsc"))
+            .unwrap_or_else(|| PathBuf::from("tsc"));
+        let mut compile = process::Command::new(tsc);
+        compile.arg("--outDir").arg(self.dir_buf.join("dist")).arg(self.dir.clone());
+        let status = build_step(&mut compile, self.output.as_ref())
+            .map_err(|err| Error::new(err.kind(), format!("failed to run tsc: {}", err)))?;
+        if !status.success() {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("tsc failed to compile the typescript block, it exited with {}", status)));
+        }
+
+        let mut child = process::Command::new(&self.program);
+        child.arg(self.dir_buf.join("dist").join("main.js"));
+        Ok(child)
+    }
+}
+
+impl LangExecutor for NodeExec {
+    fn parse_project_info(&mut self) -> Result<ProjectInfo> {
+        ProjectInfo::from_source(&self.source_code, "npm")
+    }
+
+    fn build_project(&mut self) {
+        // one dir per source, so blocks sharing the default name don't overwrite each other's files
+        let project_dir = format!("{}-{}", self.project.name, content_hash(&self.source_code));
+        let base_dir = create_lang_dir(String::from("node"), project_dir);
+        self.dir_buf = base_dir.clone();
+        self.filename = String::from(self.entry_file());
+        self.dir = write_content_to_file(self.source_code.clone(), base_dir.join(self.entry_file()));
+        self.create_package_json();
+    }
+
+    fn install_dependency(&self) -> Result<()> {
+        let missing = self.project.deps.iter()
+            .any(|dep| !self.dir_buf.join("node_modules").join(&dep.name).exists());
+        if !missing {
+            return Ok(());
+        }
+
+        // installs are resolved from the local cache first, and from a local registry when one is configured
+        let mut npm = process::Command::new("npm");
+        npm.arg("install").arg("--prefer-offline").arg("--no-audit").arg("--no-fund")
+            .current_dir(self.dir_buf.clone());
+        if let Ok(registry) = env::var("RINPUT_NPM_REGISTRY") {
+            npm.arg("--registry").arg(registry);
+        }
+        if let Ok(cache) = env::var("RINPUT_NPM_CACHE") {
+            npm.arg("--cache").arg(cache);
+        }
+
+        // the block can't run without its packages, so a failed install fails it like tsc does
+        let status = build_step(&mut npm, self.output.as_ref())
+            .map_err(|err| Error::new(err.kind(), format!("failed to run npm install: {}", err)))?;
+        if !status.success() {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("npm install failed to install the block's dependencies, it exited with {}", status)));
+        }
+        Ok(())
+    }
+
+    fn try_run(&self) {}
+
+    fn execute(&mut self) -> Result<Command> {
+        self.project = self.parse_project_info()?;
+        self.build_project();
+        self.install_dependency()?;
+
+        if self.dialect == NodeDialect::TypeScript {
+            return self.typescript_command();
+        }
+
+        let mut child = process::Command::new(&self.program);
+        child.arg(self.dir.clone()).current_dir(self.dir_buf.clone());
+        Ok(child)
+    }
+}
+
+fn is_es_module(source: &str) -> bool {
+    let re = Regex::new(r"(?m)^\s*(import\s.*from\s|import\s*['\x22]|export\s)").unwrap();
+    re.is_match(source)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::rmd::lang::{find_executable, LangExecutor, NodeExec};
+    use crate::rmd::lang::node_exec::NodeDialect;
+
+    #[test]
+    fn should_detect_es_module() {
+        let exec = NodeExec::new(String::from("import { readFileSync } from 'fs';\nconsole.log(1);\n"));
+        assert_eq!(NodeDialect::Module, exec.dialect);
+
+        let exec = NodeExec::new(String::from("const fs = require('fs');\n"));
+        assert_eq!(NodeDialect::Script, exec.dialect);
+    }
+
+    #[test]
+    fn should_create_package_json() {
+        let mut exec = NodeExec::new(String::from("// rinput-deps: lodash;version=4.17, cargo:serde
+// rinput-name: node-deps
+const _ = require('lodash');
+"));
+        exec.project = exec.parse_project_info().unwrap();
+        exec.build_project();
+
+        assert_eq!("{
+  \"name\": \"node-deps\",
+  \"version\": \"0.1.0\",
+  \"private\": true,
+  \"dependencies\": {
+    \"lodash\": \"4.17\"
+  }
+}
+", exec.create_package_json());
+    }
+
+    #[test]
+    fn should_keep_unnamed_blocks_apart() {
+        let mut first = NodeExec::new(String::from("console.log(1);\n"));
+        let mut second = NodeExec::new(String::from("console.log(2);\n"));
+        first.execute().unwrap();
+        second.execute().unwrap();
+
+        assert_ne!(first.dir_buf, second.dir_buf);
+    }
+
+    #[test]
+    fn should_fail_when_typescript_does_not_compile() {
+        // deno and ts-node compile when the block runs, tsc before it
+        if find_executable("deno").is_some() || find_executable("ts-node").is_some() {
+            return;
+        }
+        let mut exec = NodeExec::typescript(String::from("const x: number = \"no\";\n"));
+        assert!(exec.execute().is_err());
+    }
+
+    #[test]
+    fn should_success_run_module() {
+        let mut exec = NodeExec::new(String::from("// rinput-name: node-esm
+import { join } from 'path';
+console.log(join('a', 'b'));
+"));
+        let mut cmd = exec.execute().unwrap();
+
+        assert_eq!("main.mjs", exec.filename);
+        assert_eq!(0, cmd.spawn().unwrap().wait().unwrap().code().unwrap())
+    }
+}
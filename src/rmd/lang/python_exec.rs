@@ -1,4 +1,5 @@
 use crate::rmd::lang::{LangExecutor, ProjectInfo};
+use std::io::Result;
 use std::process;
 use std::process::Command;
 
@@ -8,6 +9,8 @@ pub struct PythonExec {
     source_code: String,
     dir: String,
     pub(crate) output_dir: String,
+    // the interpreter, `python` unless configured otherwise
+    program: String,
 }
 
 impl PythonExec {
@@ -18,20 +21,28 @@ impl PythonExec {
             source_code: source.to_string(),
             dir: "".to_string(),
             output_dir: "".to_string(),
+            program: "python".to_string(),
         }
     }
+
+    pub fn with_program(mut self, program: Option<&String>) -> PythonExec {
+        if let Some(program) = program {
+            self.program = program.clone();
+        }
+        self
+    }
 }
 
 impl LangExecutor for PythonExec {
-    fn parse_project_info(&mut self) -> ProjectInfo {
-        unimplemented!()
+    fn parse_project_info(&mut self) -> Result<ProjectInfo> {
+        ProjectInfo::from_source(&self.source_code, "pip")
     }
 
     fn build_project(&mut self) {
         unimplemented!()
     }
 
-    fn install_dependency(&self) {
+    fn install_dependency(&self) -> Result<()> {
         unimplemented!()
     }
 
@@ -39,10 +50,10 @@ impl LangExecutor for PythonExec {
         unimplemented!()
     }
 
-    fn execute(&mut self) -> Command {
-        let mut child = process::Command::new("python");
+    fn execute(&mut self) -> Result<Command> {
+        let mut child = process::Command::new(&self.program);
         child.arg("-c").arg(self.source_code.clone());
 
-        child
+        Ok(child)
     }
 }
\ No newline at end of file
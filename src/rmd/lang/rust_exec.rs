@@ -1,8 +1,23 @@
-use super::{LangExecutor, CompiledLangExecutor, ProjectInfo};
-use crate::rmd::lang::{create_lang_dir, write_content_to_file, build_key_value_from_comment, parse_deps};
-use std::{process, fs};
-use std::process::Command;
-use std::path::PathBuf;
+use super::{LangExecutor, CompiledLangExecutor, Diagnostic, ProjectInfo};
+use crate::rmd::cache::{BuildCache, toolchain_version};
+use crate::rmd::json::Json;
+use crate::rmd::lang::{create_lang_dir, write_content_to_file, content_hash, report};
+use crate::rmd::log;
+use std::{env, process, fs};
+use std::io::{Error, ErrorKind, Result};
+use std::process::{Command, Stdio};
+use std::sync::mpsc::Sender;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use regex::Regex;
+
+const RESERVED_NAMES: [&str; 46] = [
+    "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern",
+    "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub",
+    "ref", "return", "self", "static", "struct", "super", "trait", "true", "type", "unsafe", "use",
+    "where", "while", "std", "core", "alloc", "proc_macro", "test", "build", "deps",
+    "examples", "incremental",
+];
 
 pub struct RustExec {
     filename: String,
@@ -12,6 +27,14 @@ pub struct RustExec {
     dir_buf: PathBuf,
     pub(crate) output_dir: String,
     project: ProjectInfo,
+    // the line of `main.rs` that was injected to open `fn main`, if the snippet had to be wrapped
+    main_line: Option<usize>,
+    cache: BuildCache,
+    // where the snippet came from, so diagnostics can point back at the document
+    origin_file: String,
+    origin_line: usize,
+    // where what cargo prints goes instead of the terminal
+    output: Option<Sender<Vec<u8>>>,
 }
 
 impl RustExec {
@@ -24,49 +47,189 @@ impl RustExec {
             dir_buf: Default::default(),
             output_dir: "".to_string(),
             project: ProjectInfo::new(),
+            main_line: None,
+            cache: BuildCache::new(),
+            origin_file: "".to_string(),
+            origin_line: 0,
+            output: None,
+        }
+    }
+
+    pub fn with_origin(mut self, file: String, line: usize) -> RustExec {
+        self.origin_file = file;
+        self.origin_line = line;
+        self
+    }
+
+    pub fn with_output(mut self, output: Option<Sender<Vec<u8>>>) -> RustExec {
+        self.output = output;
+        self
+    }
+
+    // maps a line of the generated `main.rs` back to the line of the document it came from
+    fn source_line(&self, generated: usize) -> usize {
+        let snippet_lines = self.source_code.lines().count().max(1);
+        let line = match self.main_line {
+            Some(main_line) if generated > main_line => generated - 1,
+            _ => generated,
+        };
+        let line = line.min(snippet_lines);
+
+        if self.origin_line > 0 {
+            self.origin_line + line - 1
+        } else {
+            line
+        }
+    }
+
+    fn origin_name(&self) -> String {
+        if self.origin_file.is_empty() {
+            String::from("<snippet>")
+        } else {
+            self.origin_file.clone()
+        }
+    }
+
+    // cargo wants a name that starts with a letter and isn't a keyword or a built-in crate
+    fn package_name(&self) -> String {
+        let name: String = self.project.name.chars()
+            .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+            .collect();
+        let reserved = RESERVED_NAMES.contains(&name.replace('-', "_").as_str());
+        if !name.starts_with(|c: char| c.is_ascii_alphabetic()) || reserved {
+            return format!("rinput_{}", name);
         }
+        name
     }
 
     fn create_cargo_project(&self) -> String {
-        let mut default_package = "[package]
-name = \"hello_world\"
+        let mut default_package = format!("[package]
+name = \"{}\"
 version = \"0.1.0\"
+edition = \"{}\"
 
 [dependencies]
-".to_owned();
+", self.package_name(), self.project.edition);
 
         for dep in self.project.deps.clone() {
-            let result = format!("{} = \"{}\"\n", dep.name, dep.version);
-            let pkg = default_package.push_str(&result);
+            let mut fields = vec![];
+            if !dep.version.is_empty() {
+                fields.push(format!("version = \"{}\"", dep.version));
+            }
+            if !dep.path.is_empty() {
+                fields.push(format!("path = \"{}\"", resolve_dep_path(&dep.path, &self.origin_file)));
+            }
+            if !dep.git.is_empty() {
+                fields.push(format!("git = \"{}\"", dep.git));
+            }
+            for (key, value) in &[("branch", &dep.branch), ("tag", &dep.tag), ("rev", &dep.rev)] {
+                if !value.is_empty() {
+                    fields.push(format!("{} = \"{}\"", key, value));
+                }
+            }
+            if !dep.features.is_empty() {
+                let features: Vec<String> = dep.features.iter().map(|f| format!("\"{}\"", f)).collect();
+                fields.push(format!("features = [{}]", features.join(", ")));
+            }
+
+            let result = if fields.len() == 1 && !dep.version.is_empty() {
+                format!("{} = \"{}\"\n", dep.name, dep.version)
+            } else if fields.is_empty() {
+                format!("{} = \"*\"\n", dep.name)
+            } else {
+                format!("{} = {{ {} }}\n", dep.name, fields.join(", "))
+            };
+            default_package.push_str(&result);
         }
 
         write_content_to_file(default_package.clone(), self.dir_buf.join("Cargo.toml"));
         default_package
     }
+
+    fn profile(&self) -> &'static str {
+        if self.project.release { "release" } else { "debug" }
+    }
+
+    fn binary_path(&self) -> PathBuf {
+        self.dir_buf.join("target").join(self.profile()).join(self.package_name())
+    }
+
+    fn cache_key(&self) -> Option<String> {
+        // local path deps can change without the snippet changing, so they always rebuild
+        if self.project.deps.iter().any(|dep| !dep.path.is_empty()) {
+            return None;
+        }
+
+        Some(BuildCache::key(&[
+            &self.source_code,
+            &format!("{:?}", self.project.deps),
+            &toolchain_version("rustc"),
+            &self.project.edition,
+            self.profile(),
+        ]))
+    }
+
+    // like rustdoc, snippets without a `main` get their body wrapped in one; leading comments,
+    // attributes, `extern crate` and `use` lines stay at the top level
+    fn wrap_main(&mut self) -> String {
+        let main_re = Regex::new(r"(?m)^\s*(pub\s+)?fn\s+main\s*\(").unwrap();
+        if main_re.is_match(&self.source_code) {
+            self.main_line = None;
+            return self.source_code.clone();
+        }
+
+        let lines: Vec<&str> = self.source_code.lines().collect();
+        let prelude = lines.iter()
+            .take_while(|line| {
+                let line = line.trim();
+                line.is_empty() || line.starts_with("//") || line.starts_with("#!")
+                    || line.starts_with("extern crate") || line.starts_with("use ")
+            })
+            .count();
+
+        let mut wrapped = String::new();
+        for line in &lines[..prelude] {
+            wrapped.push_str(line);
+            wrapped.push('\n');
+        }
+        wrapped.push_str("fn main() {\n");
+        for line in &lines[prelude..] {
+            wrapped.push_str(line);
+            wrapped.push('\n');
+        }
+        wrapped.push_str("}\n");
+
+        self.main_line = Some(prelude + 1);
+        wrapped
+    }
+}
+
+// a relative `path=` is taken from the document's directory, like a `cwd=`, wherever rinput runs
+fn resolve_dep_path(path: &str, origin_file: &str) -> String {
+    let path = PathBuf::from(path);
+    let doc_dir = Path::new(origin_file).parent().unwrap_or_else(|| Path::new(""));
+    let absolute = if path.is_absolute() {
+        path
+    } else {
+        env::current_dir().unwrap_or_default().join(doc_dir).join(path)
+    };
+
+    fs::canonicalize(&absolute).unwrap_or(absolute).display().to_string()
 }
 
 impl LangExecutor for RustExec {
-    fn parse_project_info(&mut self) -> ProjectInfo {
-        let map = build_key_value_from_comment(self.source_code.clone());
-        let mut project_info = ProjectInfo::new();
-        project_info.name = String::from("hello");
-
-        for (key, value) in map {
-            match &key[..] {
-                "deps" => {
-                    project_info.deps = parse_deps(value.clone());
-                }
-                "name" => {
-                    project_info.name = String::from(value.clone());
-                }
-                _ => {}
-            }
+    fn parse_project_info(&mut self) -> Result<ProjectInfo> {
+        let mut project_info = ProjectInfo::from_source(&self.source_code, "cargo")?;
+        if project_info.edition.is_empty() {
+            project_info.edition = String::from("2018");
         }
 
-        project_info
+        Ok(project_info)
     }
     fn build_project(&mut self) {
-        let mut base_dir = create_lang_dir(String::from("rust"), String::from(self.project.name.clone()));
+        // keyed by content, so two documents sharing a `rinput-name` never overwrite each other
+        let project_dir = format!("{}-{}", self.package_name(), content_hash(&self.source_code));
+        let base_dir = create_lang_dir(String::from("rust"), project_dir);
         let mut output = base_dir.clone();
 
         let mut dir = base_dir.clone().join("src");
@@ -77,16 +240,47 @@ impl LangExecutor for RustExec {
         dir.push("main.rs");
         output.push("main");
 
-        self.dir = write_content_to_file(self.source_code.clone(), dir);
+        let main_rs = self.wrap_main();
+        self.dir = write_content_to_file(main_rs, dir);
         self.create_cargo_project();
     }
-    fn install_dependency(&self) {}
+    fn install_dependency(&self) -> Result<()> {
+        Ok(())
+    }
     fn try_run(&self) {}
-    fn execute(&mut self) -> Command {
-        self.project = self.parse_project_info();
+    fn execute(&mut self) -> Result<Command> {
+        self.project = self.parse_project_info()?;
+        let key = self.cache_key();
+        if let Some(binary) = key.as_ref().and_then(|key| self.cache.lookup("rust", key)) {
+            log::debug("build", &format!("using the cached build {}", binary.display()));
+            return Ok(process::Command::new(binary));
+        }
+
         self.build_project();
-        let child = self.compile();
-        child
+        let started = Instant::now();
+        let stderr = if self.output.is_some() { Stdio::piped() } else { Stdio::inherit() };
+        let output = self.compile().stdout(Stdio::piped()).stderr(stderr).output()?;
+        if let Some(sink) = &self.output {
+            let _ = sink.send(output.stderr.clone());
+        }
+        log::timed("build", &format!("compiled the rust block at {}:{}", self.origin_name(), self.source_line(1)), started.elapsed());
+        for diagnostic in self.diagnostics(&String::from_utf8_lossy(&output.stdout)) {
+            match &self.output {
+                None if log::is_json() => log::emit(&diagnostic.level, "build", &diagnostic.message, diagnostic.fields()),
+                None => eprintln!("{}", diagnostic.render()),
+                Some(_) => report(self.output.as_ref(), &diagnostic.render()),
+            }
+        }
+        if !output.status.success() {
+            let msg = format!("failed to compile the rust block at {}:{}", self.origin_name(), self.source_line(1));
+            return Err(Error::new(ErrorKind::Other, msg));
+        }
+
+        let binary = match key {
+            Some(key) => self.cache.store("rust", &key, &self.binary_path())?,
+            None => self.binary_path(),
+        };
+        Ok(process::Command::new(binary))
     }
 }
 
@@ -94,17 +288,82 @@ impl CompiledLangExecutor for RustExec {
     fn compile(&self) -> Command {
         let path = self.dir_buf.join("Cargo.toml").into_os_string().into_string().unwrap();
         let mut child = process::Command::new("cargo");
-        child.arg("run").arg("--manifest-path").arg(path.clone());
+        child.arg("build").arg("--manifest-path").arg(path.clone())
+            .arg("--target-dir").arg(self.dir_buf.join("target"))
+            .arg("--message-format=json");
+        if self.project.release {
+            child.arg("--release");
+        }
 
-        println!("{}", path.clone());
+        log::debug("build", &format!("cargo build --manifest-path {}", path));
         child
     }
+
+    fn diagnostics(&self, output: &str) -> Vec<Diagnostic> {
+        let mut diagnostics = vec![];
+        for line in output.lines() {
+            let json = match Json::parse(line) {
+                Ok(json) => json,
+                Err(_) => continue,
+            };
+            let message = match json.get("message") {
+                Some(message) if json.str_or_empty("reason") == "compiler-message" => message,
+                _ => continue,
+            };
+
+            let text = message.str_or_empty("message");
+            if text.starts_with("aborting due to") || text.ends_with("warning emitted") || text.ends_with("warnings emitted") {
+                continue;
+            }
+
+            let mut diagnostic = Diagnostic::new(message.str_or_empty("level"), text);
+            if let Some(code) = message.get("code") {
+                diagnostic.code = code.str_or_empty("code");
+            }
+
+            let spans = message.get("spans").and_then(|spans| spans.as_array()).cloned().unwrap_or_default();
+            let primary = spans.iter().find(|span| span.get("is_primary").and_then(|p| p.as_bool()) == Some(true));
+            if let Some(span) = primary {
+                let line = span.get("line_start").and_then(|l| l.as_u64()).unwrap_or(0) as usize;
+                diagnostic.column = span.get("column_start").and_then(|c| c.as_u64()).unwrap_or(0) as usize;
+                diagnostic.label = span.str_or_empty("label");
+
+                if span.str_or_empty("file_name").ends_with("main.rs") {
+                    diagnostic.file = self.origin_name();
+                    diagnostic.line = self.source_line(line);
+                } else {
+                    diagnostic.file = span.str_or_empty("file_name");
+                    diagnostic.line = line;
+                }
+
+                if let Some(text) = span.get("text").and_then(|text| text.as_array()).and_then(|text| text.first()) {
+                    diagnostic.source_line = text.str_or_empty("text");
+                    diagnostic.highlight = (
+                        text.get("highlight_start").and_then(|h| h.as_u64()).unwrap_or(0) as usize,
+                        text.get("highlight_end").and_then(|h| h.as_u64()).unwrap_or(0) as usize,
+                    );
+                }
+            }
+
+            for child in message.get("children").and_then(|children| children.as_array()).cloned().unwrap_or_default() {
+                diagnostic.notes.push(format!("{}: {}", child.str_or_empty("level"), child.str_or_empty("message")));
+            }
+
+            diagnostics.push(diagnostic);
+        }
+
+        diagnostics
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::rmd::lang::{RustExec, LangExecutor};
-    use std::process;
+    use crate::rmd::cache::BuildCache;
+    use crate::rmd::lang::{content_hash, CompiledLangExecutor, RustExec, LangExecutor};
+    use std::fs;
+    use std::path::PathBuf;
+    use std::sync::mpsc::channel;
+    use tempfile::{tempdir, TempDir};
 
     fn get_hello_world_code() -> &'static str {
         "// rinput-deps: colored;version=1.8.0
@@ -115,10 +374,21 @@ fn main() {
 "
     }
 
+    fn rust_exec(source: &str, cache: &TempDir) -> RustExec {
+        let mut exec = RustExec::new(String::from(source));
+        exec.cache = BuildCache::at(cache.path().to_path_buf());
+        exec
+    }
+
+    fn prepare(exec: &mut RustExec) {
+        exec.project = exec.parse_project_info().unwrap();
+        exec.build_project();
+    }
+
     #[test]
     fn should_parse_project_deps() {
         let mut exec = RustExec::new(String::from(get_hello_world_code()));
-        exec.execute();
+        prepare(&mut exec);
 
         assert_eq!(1, exec.project.deps.len())
     }
@@ -126,32 +396,157 @@ fn main() {
     #[test]
     fn should_parse_project_info() {
         let mut exec = RustExec::new(String::from(get_hello_world_code()));
-        exec.execute();
+        prepare(&mut exec);
 
         assert_eq!("demo", exec.project.name)
     }
 
     #[test]
     fn should_success_run_hello_world() {
-        let mut exec = RustExec::new(String::from("// rinput-name: hello2
+        let cache = tempdir().unwrap();
+        let mut exec = rust_exec("// rinput-name: hello2
 fn main() {println!(\"Hello World!\");}
-"));
-        let mut cmd = exec.execute();
+", &cache);
+        let mut cmd = exec.execute().unwrap();
         assert_eq!(0, cmd.spawn().unwrap().wait().unwrap().code().unwrap())
     }
 
     #[test]
     fn should_create_cargo_tomal() {
         let mut exec = RustExec::new(String::from(get_hello_world_code()));
-        exec.execute();
+        prepare(&mut exec);
         let dep = exec.create_cargo_project();
 
         assert_eq!("[package]
-name = \"hello_world\"
+name = \"demo\"
 version = \"0.1.0\"
+edition = \"2018\"
 
 [dependencies]
 colored = \"1.8.0\"
 ", String::from(dep))
     }
+
+    #[test]
+    fn should_name_packages_the_way_cargo_accepts() {
+        let name = |name: &str| {
+            let mut exec = RustExec::new(String::new());
+            exec.project.name = String::from(name);
+            exec.package_name()
+        };
+        assert_eq!("serde-demo", name("serde-demo"));
+        assert_eq!("rinput_2fa_demo", name("2fa demo"));
+        assert_eq!("rinput___", name("__"));
+        assert_eq!("rinput_test", name("test"));
+        assert_eq!("rinput_", name(""));
+        // the same on every toolchain, so cached builds survive an update
+        assert_eq!("af63dc4c8601ec8c", content_hash("a"));
+    }
+
+    #[test]
+    fn should_create_cargo_toml_with_edition_and_features() {
+        let mut exec = RustExec::new(String::from("// rinput-name: serde demo
+// rinput-edition: 2015
+// rinput-deps: serde;version=1.0;features=derive|rc, tokio;git=https://github.com/tokio-rs/tokio;tag=1.0.0
+fn main() {}
+"));
+        prepare(&mut exec);
+
+        assert_eq!("[package]
+name = \"serde_demo\"
+version = \"0.1.0\"
+edition = \"2015\"
+
+[dependencies]
+serde = { version = \"1.0\", features = [\"derive\", \"rc\"] }
+tokio = { git = \"https://github.com/tokio-rs/tokio\", tag = \"1.0.0\" }
+", exec.create_cargo_project())
+    }
+
+    #[test]
+    fn should_take_path_deps_from_the_document_dir() {
+        let root = tempdir().unwrap();
+        fs::create_dir_all(root.path().join("docs")).unwrap();
+        fs::create_dir_all(root.path().join("crates/helper")).unwrap();
+        let doc = root.path().join("docs/runbook.md").display().to_string();
+        let mut exec = RustExec::new(String::from("// rinput-deps: helper;path=../crates/helper\nfn main() {}\n"))
+            .with_origin(doc, 1);
+        prepare(&mut exec);
+
+        // the tests run from the crate's root, not from `docs`
+        let helper = fs::canonicalize(root.path().join("crates/helper")).unwrap();
+        assert!(exec.create_cargo_project().contains(&format!("helper = {{ path = \"{}\" }}", helper.display())));
+    }
+
+    #[test]
+    fn should_wrap_snippet_without_main() {
+        let cache = tempdir().unwrap();
+        let mut exec = rust_exec("// rinput-name: wrapped
+use std::collections::HashMap;
+let mut map = HashMap::new();
+map.insert(1, \"one\");
+println!(\"{:?}\", map);
+", &cache);
+        let mut cmd = exec.execute().unwrap();
+
+        assert_eq!(Some(3), exec.main_line);
+        assert_eq!(0, cmd.spawn().unwrap().wait().unwrap().code().unwrap())
+    }
+
+    #[test]
+    fn should_key_project_dir_by_content() {
+        let mut first = RustExec::new(String::from("// rinput-name: same\nfn main() {}\n"));
+        let mut second = RustExec::new(String::from("// rinput-name: same\nfn main() { println!(); }\n"));
+        prepare(&mut first);
+        prepare(&mut second);
+
+        assert_ne!(first.dir_buf, second.dir_buf);
+    }
+
+    #[test]
+    fn should_reuse_cached_binary() {
+        let cache = tempdir().unwrap();
+        let source = "// rinput-name: cached\nfn main() {}\n";
+        let mut first = rust_exec(source, &cache);
+        first.execute().unwrap();
+        assert_eq!(1, first.cache.entries().unwrap().len());
+
+        let mut second = rust_exec(source, &cache);
+        let cmd = second.execute().unwrap();
+
+        assert_eq!(PathBuf::new(), second.dir_buf);
+        assert!(PathBuf::from(cmd.get_program()).starts_with(cache.path()));
+    }
+
+    #[test]
+    fn should_fail_on_build_error() {
+        let cache = tempdir().unwrap();
+        let (sender, receiver) = channel();
+        let mut exec = rust_exec("// rinput-name: broken\nfn main() { let x: u8 = \"no\"; }\n", &cache)
+            .with_output(Some(sender));
+
+        assert!(exec.execute().is_err());
+        assert_eq!(0, exec.cache.entries().unwrap().len());
+        // what cargo printed went to the sink rather than the terminal
+        let printed: Vec<u8> = receiver.try_iter().flatten().collect();
+        assert!(String::from_utf8_lossy(&printed).contains("mismatched types"));
+    }
+
+    #[test]
+    fn should_map_diagnostics_to_markdown_lines() {
+        let cache = tempdir().unwrap();
+        let mut exec = rust_exec("// rinput-name: mapped
+let x: u8 = \"no\";
+", &cache).with_origin(String::from("doc.md"), 10);
+        prepare(&mut exec);
+
+        let output = exec.compile().output().unwrap();
+        let diagnostics = exec.diagnostics(&String::from_utf8_lossy(&output.stdout));
+        let error = diagnostics.iter().find(|d| d.is_error()).unwrap();
+
+        assert_eq!("E0308", error.code);
+        assert_eq!("doc.md", error.file);
+        assert_eq!(11, error.line);
+        assert_eq!(13, error.column);
+    }
 }
@@ -0,0 +1,211 @@
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::process::CommandExt;
+use std::process;
+use std::time::Duration;
+
+use crate::rmd::command::Script;
+
+// Per-block resource limits, read from the `cpu-time`, `memory`, `open-files`, `processes` and
+// `max-output` attributes. The rlimits apply to the block's process and everything it spawns.
+// The kernel counts processes per user rather than per process tree, so `processes=N` lets the
+// block start N more than the user already runs when it starts, threads included; what the user
+// starts elsewhere while it runs counts against it too. A container's limit is its own.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Limits {
+    pub cpu_seconds: Option<u64>,
+    pub address_space: Option<u64>,
+    pub open_files: Option<u64>,
+    pub processes: Option<u64>,
+    pub max_output: Option<usize>,
+}
+
+impl Limits {
+    pub fn from_script(script: &Script) -> Result<Limits> {
+        let mut limits = Limits::default();
+        if let Some(value) = script.attr("cpu-time") {
+            limits.cpu_seconds = Some(parse_duration(value)?.as_secs().max(1));
+        }
+        if let Some(value) = script.attr("memory") {
+            limits.address_space = Some(parse_size(value)?);
+        }
+        if let Some(value) = script.attr("open-files") {
+            limits.open_files = Some(parse_count(value)?);
+        }
+        if let Some(value) = script.attr("processes") {
+            limits.processes = Some(parse_count(value)?);
+        }
+        if let Some(value) = script.attr("max-output") {
+            limits.max_output = Some(parse_size(value)? as usize);
+        }
+
+        Ok(limits)
+    }
+
+    fn rlimits(&self) -> Vec<(libc::c_int, u64)> {
+        let mut rlimits = vec![];
+        if let Some(value) = self.cpu_seconds {
+            rlimits.push((libc::RLIMIT_CPU as libc::c_int, value));
+        }
+        if let Some(value) = self.address_space {
+            rlimits.push((libc::RLIMIT_AS as libc::c_int, value));
+        }
+        if let Some(value) = self.open_files {
+            rlimits.push((libc::RLIMIT_NOFILE as libc::c_int, value));
+        }
+        if let Some(value) = self.processes {
+            rlimits.push((libc::RLIMIT_NPROC as libc::c_int, user_tasks() + value));
+        }
+        rlimits
+    }
+
+    pub fn apply(&self, child: &mut process::Command) {
+        let rlimits = self.rlimits();
+        if rlimits.is_empty() {
+            return;
+        }
+
+        unsafe {
+            child.pre_exec(move || {
+                for (resource, value) in &rlimits {
+                    let limit = libc::rlimit {
+                        rlim_cur: *value as libc::rlim_t,
+                        rlim_max: *value as libc::rlim_t,
+                    };
+                    if libc::setrlimit(*resource as _, &limit) == -1 {
+                        return Err(Error::last_os_error());
+                    }
+                }
+                Ok(())
+            });
+        }
+    }
+
+    // the same limits as flags for `podman run` / `docker run`, which apply them inside the container
+    pub fn runtime_args(&self) -> Vec<String> {
+        let mut args = vec![];
+        if let Some(value) = self.cpu_seconds {
+            args.push(format!("--ulimit=cpu={}", value));
+        }
+        if let Some(value) = self.address_space {
+            args.push(format!("--memory={}", value));
+        }
+        if let Some(value) = self.open_files {
+            args.push(format!("--ulimit=nofile={}", value));
+        }
+        if let Some(value) = self.processes {
+            args.push(format!("--pids-limit={}", value));
+        }
+        args
+    }
+}
+
+// the processes and threads of the current user, which `RLIMIT_NPROC` counts
+fn user_tasks() -> u64 {
+    let uid = unsafe { libc::getuid() };
+    let entries = match fs::read_dir("/proc") {
+        Ok(entries) => entries,
+        Err(_) => return 0,
+    };
+    entries.filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_name().to_string_lossy().chars().all(|c| c.is_ascii_digit()))
+        .filter(|entry| entry.metadata().is_ok_and(|metadata| metadata.uid() == uid))
+        .map(|entry| fs::read_dir(entry.path().join("task")).map(|tasks| tasks.count() as u64).unwrap_or(1))
+        .sum()
+}
+
+fn invalid(msg: String) -> Error {
+    Error::new(ErrorKind::InvalidInput, msg)
+}
+
+fn parse_count(value: &str) -> Result<u64> {
+    value.trim().parse::<u64>().map_err(|_| invalid(format!("`{}` is not a number", value)))
+}
+
+// `512`, `64K`, `512M`, `2G`, in bytes
+pub fn parse_size(value: &str) -> Result<u64> {
+    let value = value.trim();
+    let (number, unit) = value.split_at(value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len()));
+    let multiplier = match unit.to_ascii_uppercase().trim_end_matches('B').trim_end_matches('I') {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        _ => return Err(invalid(format!("`{}` is not a size, expected e.g. 512K, 64M or 2G", value))),
+    };
+
+    parse_count(number).map(|number| number * multiplier)
+}
+
+// `250ms`, `10s`, `2m`, `1h`; a bare number means seconds
+pub fn parse_duration(value: &str) -> Result<Duration> {
+    let value = value.trim();
+    let (number, unit) = value.split_at(value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len()));
+    let number = parse_count(number)?;
+    match unit {
+        "ms" => Ok(Duration::from_millis(number)),
+        "" | "s" => Ok(Duration::from_secs(number)),
+        "m" => Ok(Duration::from_secs(number * 60)),
+        "h" => Ok(Duration::from_secs(number * 60 * 60)),
+        _ => Err(invalid(format!("`{}` is not a duration, expected e.g. 500ms, 10s or 2m", value))),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::process;
+    use std::time::Duration;
+
+    use crate::rmd::command::Script;
+    use crate::rmd::limits::{Limits, parse_duration, parse_size};
+
+    #[test]
+    fn should_parse_sizes_and_durations() {
+        assert_eq!(512, parse_size("512").unwrap());
+        assert_eq!(64 * 1024, parse_size("64K").unwrap());
+        assert_eq!(512 * 1024 * 1024, parse_size("512MiB").unwrap());
+        assert!(parse_size("lots").is_err());
+
+        assert_eq!(Duration::from_millis(250), parse_duration("250ms").unwrap());
+        assert_eq!(Duration::from_secs(120), parse_duration("2m").unwrap());
+        assert!(parse_duration("2 weeks").is_err());
+    }
+
+    #[test]
+    fn should_read_limits_from_attributes() {
+        let mut script = Script::new();
+        script.attrs.insert(String::from("cpu-time"), String::from("10s"));
+        script.attrs.insert(String::from("open-files"), String::from("16"));
+        script.attrs.insert(String::from("max-output"), String::from("1K"));
+        let limits = Limits::from_script(&script).unwrap();
+
+        assert_eq!(Some(10), limits.cpu_seconds);
+        assert_eq!(Some(16), limits.open_files);
+        assert_eq!(Some(1024), limits.max_output);
+        assert_eq!(vec!["--ulimit=cpu=10", "--ulimit=nofile=16"], limits.runtime_args());
+    }
+
+    #[test]
+    fn should_count_processes_on_top_of_the_users_own() {
+        let limits = Limits { processes: Some(8), ..Limits::default() };
+        let mut child = process::Command::new("sh");
+        child.arg("-c").arg("grep 'Max processes' /proc/self/limits");
+        limits.apply(&mut child);
+
+        let output = child.output().unwrap();
+        let limit: u64 = String::from_utf8_lossy(&output.stdout).split_whitespace().nth(2).unwrap().parse().unwrap();
+        assert!(limit > 8);
+    }
+
+    #[test]
+    fn should_apply_rlimits_to_child() {
+        let limits = Limits { open_files: Some(17), ..Limits::default() };
+        let mut child = process::Command::new("sh");
+        child.arg("-c").arg("ulimit -n");
+        limits.apply(&mut child);
+
+        let output = child.output().unwrap();
+        assert_eq!("17\n", String::from_utf8_lossy(&output.stdout));
+    }
+}
@@ -0,0 +1,173 @@
+use std::fmt::Display;
+use std::io::{Error, ErrorKind, Result};
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use colored::*;
+
+use crate::rmd::json::Json;
+
+// How much rinput tells about what it does, on stderr: `-q` leaves only errors, `-v` adds a
+// line per phase and block, `-vv` the details of each.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub enum Level {
+    Quiet = 0,
+    Normal = 1,
+    Verbose = 2,
+    Debug = 3,
+}
+
+impl Level {
+    pub fn from_flags(verbose: u64, quiet: bool) -> Level {
+        match (quiet, verbose) {
+            (true, _) => Level::Quiet,
+            (false, 0) => Level::Normal,
+            (false, 1) => Level::Verbose,
+            _ => Level::Debug,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Level::Quiet => "quiet",
+            Level::Normal => "normal",
+            Level::Verbose => "info",
+            Level::Debug => "debug",
+        }
+    }
+}
+
+static LEVEL: AtomicU8 = AtomicU8::new(Level::Normal as u8);
+// one JSON object per line instead of text, for CI to read
+static JSON: AtomicBool = AtomicBool::new(false);
+
+pub fn init(level: Level, format: &str) -> Result<()> {
+    let json = match format {
+        "text" => false,
+        "json" => true,
+        other => return Err(Error::new(ErrorKind::InvalidInput, format!("unknown log format `{}`, expected text or json", other))),
+    };
+    LEVEL.store(level as u8, Ordering::SeqCst);
+    JSON.store(json, Ordering::SeqCst);
+    if json {
+        // what still prints as text, such as --help, is read by programs then
+        colored::control::set_override(false);
+    }
+    Ok(())
+}
+
+pub fn is_json() -> bool {
+    JSON.load(Ordering::SeqCst)
+}
+
+// at least `level`, so `rinput deploy --verbose` can ask for more than the global flags did
+pub fn raise(level: Level) {
+    LEVEL.fetch_max(level as u8, Ordering::SeqCst);
+}
+
+pub fn enabled(level: Level) -> bool {
+    LEVEL.load(Ordering::SeqCst) >= level as u8
+}
+
+pub fn info(phase: &str, message: &str) {
+    log(Level::Verbose, phase, message, None);
+}
+
+pub fn debug(phase: &str, message: &str) {
+    log(Level::Debug, phase, message, None);
+}
+
+// a phase or a block that took `elapsed`
+pub fn timed(phase: &str, message: &str, elapsed: Duration) {
+    log(Level::Verbose, phase, message, Some(elapsed));
+}
+
+// Something that went wrong, which shows whatever the level: `ERROR: message` or an event.
+pub fn error(phase: &str, message: &str) {
+    if is_json() {
+        emit("error", phase, message, vec![]);
+    } else {
+        eprintln!("{} {}", "ERROR:".red(), message);
+    }
+}
+
+// What rinput tells unless it's asked to be quiet: `text` as it is, or an event with `message`.
+pub fn status(phase: &str, message: &str, text: impl Display) {
+    if !enabled(Level::Normal) {
+        return;
+    }
+    if is_json() {
+        emit(Level::Normal.name(), phase, message, vec![]);
+    } else {
+        eprintln!("{}", text);
+    }
+}
+
+// one event with `fields` after the usual ones, whatever the format and level
+pub fn emit(level: &str, phase: &str, message: &str, fields: Vec<(String, Json)>) {
+    eprintln!("{}", with_fields(record(level, phase, message), fields));
+}
+
+fn with_fields(mut event: Json, fields: Vec<(String, Json)>) -> Json {
+    if let Json::Object(all) = &mut event {
+        all.extend(fields);
+    }
+    event
+}
+
+fn log(level: Level, phase: &str, message: &str, elapsed: Option<Duration>) {
+    if !enabled(level) {
+        return;
+    }
+    if is_json() {
+        eprintln!("{}", event(level, phase, message, elapsed));
+        return;
+    }
+    let line = match elapsed {
+        Some(elapsed) => format!("[{}] {} ({:.1?})", phase, message, elapsed),
+        None => format!("[{}] {}", phase, message),
+    };
+    eprintln!("{}", line.dimmed());
+}
+
+fn event(level: Level, phase: &str, message: &str, elapsed: Option<Duration>) -> Json {
+    let mut event = record(level.name(), phase, message);
+    if let (Json::Object(fields), Some(elapsed)) = (&mut event, elapsed) {
+        fields.push((String::from("elapsed_ms"), Json::Number(elapsed.as_secs_f64() * 1000.0)));
+    }
+    event
+}
+
+fn record(level: &str, phase: &str, message: &str) -> Json {
+    let time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    Json::Object(vec![
+        (String::from("time"), Json::Number(time.as_millis() as f64 / 1000.0)),
+        (String::from("level"), Json::String(level.to_string())),
+        (String::from("phase"), Json::String(phase.to_string())),
+        (String::from("message"), Json::String(message.to_string())),
+    ])
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use crate::rmd::json::Json;
+    use crate::rmd::log::{event, record, with_fields, Level};
+
+    #[test]
+    fn should_write_events_as_json() {
+        assert_eq!(Level::Quiet, Level::from_flags(2, true));
+        assert_eq!(Level::Debug, Level::from_flags(3, false));
+
+        let line = event(Level::Verbose, "run", "deploy passed", Some(Duration::from_millis(1500))).to_string();
+        assert!(line.contains("\"level\":\"info\""));
+        assert!(line.contains("\"phase\":\"run\""));
+        assert!(line.contains("\"message\":\"deploy passed\""));
+        assert!(line.contains("\"elapsed_ms\":1500"));
+
+        let line = with_fields(record("normal", "summary", "deploy failed"), vec![(String::from("exit_code"), Json::Number(2.0))]).to_string();
+        assert!(line.contains("\"phase\":\"summary\""));
+        assert!(line.contains("\"exit_code\":2"));
+    }
+}
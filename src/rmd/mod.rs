@@ -1,6 +1,31 @@
+pub use command::Command;
 pub use parser::Rmd;
 
 mod parser;
 mod command;
 mod lang;
+pub mod browser;
+pub mod cache;
+pub mod completions;
+pub mod config;
+mod container;
+pub mod document;
+pub mod export;
+mod interpolate;
+pub mod invoke;
+mod json;
+mod limits;
+pub mod log;
+pub mod notebook;
+mod org;
+pub mod outline;
+mod policy;
+pub mod runbook;
+pub mod runner;
+mod rst;
+mod sandbox;
+pub mod secrets;
+pub mod view;
+pub mod watch;
 pub mod executor;
+pub mod workdir;
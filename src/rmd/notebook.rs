@@ -0,0 +1,376 @@
+use std::io::{Error, ErrorKind, Result};
+
+use pulldown_cmark::{CodeBlockKind, Event, Parser, Tag};
+
+use crate::rmd::command::Command;
+use crate::rmd::executor::Transcript;
+use crate::rmd::interpolate::BlockOutputs;
+use crate::rmd::json::Json;
+use crate::rmd::parser::{is_supported_lang, line_of, parse_info_string, split_front_matter};
+
+// the cell magics that hand a cell of a Python notebook to another program
+const LANGUAGE_MAGICS: [&str; 8] = ["bash", "sh", "javascript", "js", "ruby", "perl", "python", "python3"];
+
+// Reads a Jupyter notebook as markdown: markdown and raw cells as they are, their fences of code as `text`,
+// code cells as blocks in the kernel's language, or the one a `%%bash` or `%%script node` magic on
+// their first line names.
+// A kernel keeps its variables from one cell to the next, so consecutive cells in its language
+// become one block, with IPython's `%` magics commented out and its `!` commands run by a shell.
+pub fn to_markdown(text: &str) -> Result<String> {
+    let notebook = Json::parse(text)?;
+    let cells = notebook.get("cells").and_then(Json::as_array)
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "not a notebook, it has no cells"))?;
+    let kernel = kernel_language(&notebook);
+
+    let mut out = String::new();
+    let mut session: Vec<String> = vec![];
+    for cell in cells {
+        let source = cell_source(cell);
+        if source.trim().is_empty() {
+            continue;
+        }
+        let is_code = cell.str_or_empty("cell_type") == "code";
+        if is_code && info_of(cell).is_none() && cell_magic(&source).0.is_none() {
+            session.push(without_magics(&source, &kernel));
+            continue;
+        }
+        push_session(&mut out, &mut session, &kernel);
+        let text = if is_code { code_block(info_of(cell), &source, &kernel) } else { inert(&source) };
+        push_text(&mut out, &text);
+    }
+    push_session(&mut out, &mut session, &kernel);
+    Ok(out)
+}
+
+fn push_text(out: &mut String, text: &str) {
+    out.push_str(text.trim_end_matches('\n'));
+    out.push_str("\n\n");
+}
+
+// A fence in a markdown cell is only an example to read; as `text` it is not run with the cells.
+fn inert(source: &str) -> String {
+    let mut out = String::new();
+    let mut copied = 0;
+    for (event, range) in Parser::new(source).into_offset_iter() {
+        let info = match event {
+            Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(info))) => info,
+            _ => continue,
+        };
+        if !is_supported_lang(&parse_info_string(&info).0) {
+            continue;
+        }
+        let opening = &source[range.start..];
+        let fence_end = opening.find(|c: char| c != ' ' && c != '`' && c != '~').unwrap_or(opening.len());
+        let line_end = opening.find('\n').unwrap_or(opening.len());
+        out.push_str(&source[copied..range.start]);
+        out.push_str(&opening[..fence_end]);
+        out.push_str("text");
+        copied = range.start + line_end;
+    }
+    out.push_str(&source[copied..]);
+    out
+}
+
+// the kernel cells read so far, as one block
+fn push_session(out: &mut String, session: &mut Vec<String>, kernel: &str) {
+    if session.is_empty() {
+        return;
+    }
+    let source: Vec<&str> = session.iter().map(|source| source.trim_end_matches('\n')).collect();
+    push_text(out, &code_block(None, &source.join("\n"), kernel));
+    session.clear();
+}
+
+// A cell written by `from_markdown` keeps its block's info string, `sh id=build`, in its metadata.
+fn info_of(cell: &Json) -> Option<String> {
+    cell.get("metadata").and_then(|metadata| metadata.get("rinput")).map(|rinput| rinput.str_or_empty("info"))
+        .filter(|info| !info.is_empty())
+}
+
+// `%time` and `%%time` leave the code they time, other magics only make sense to IPython
+fn without_magics(source: &str, kernel: &str) -> String {
+    if kernel != "python" {
+        return source.to_string();
+    }
+    let mut out = String::new();
+    for line in source.lines() {
+        let code = line.trim_start();
+        let indent = &line[..line.len() - code.len()];
+        let timed = ["%%timeit", "%%time", "%timeit", "%time"].iter().find_map(|magic| code.strip_prefix(magic));
+        match (timed, code.strip_prefix('!')) {
+            (Some(rest), _) if rest.trim().is_empty() => {}
+            (Some(rest), _) => out.push_str(&format!("{}{}", indent, rest.trim_start())),
+            (None, Some(command)) => {
+                out.push_str(&format!("{}__import__(\"subprocess\").run({}, shell=True, check=True)", indent, quote_python(command.trim())))
+            }
+            (None, None) if code.starts_with('%') => out.push_str(&format!("{}# {}", indent, code)),
+            (None, None) => out.push_str(line),
+        }
+        out.push('\n');
+    }
+    out
+}
+
+fn quote_python(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn kernel_language(notebook: &Json) -> String {
+    let metadata = notebook.get("metadata");
+    let language = metadata.and_then(|metadata| metadata.get("kernelspec")).map(|spec| spec.str_or_empty("language"))
+        .filter(|language| !language.is_empty())
+        .or_else(|| metadata.and_then(|metadata| metadata.get("language_info")).map(|info| info.str_or_empty("name")))
+        .filter(|language| !language.is_empty())
+        .unwrap_or_else(|| String::from("python"));
+    normalize(&language)
+}
+
+fn normalize(language: &str) -> String {
+    match language.to_lowercase().as_str() {
+        "python3" | "py" => String::from("python"),
+        language => language.to_string(),
+    }
+}
+
+// a cell's source is either one string or a list of lines
+fn cell_source(cell: &Json) -> String {
+    match cell.get("source") {
+        Some(Json::Array(lines)) => lines.iter().filter_map(Json::as_str).collect(),
+        Some(source) => source.as_str().unwrap_or("").to_string(),
+        None => String::new(),
+    }
+}
+
+fn code_block(info: Option<String>, source: &str, kernel: &str) -> String {
+    let (magic, body) = cell_magic(source);
+    let info = info.or(magic).unwrap_or_else(|| kernel.to_string());
+    let mut fence = String::from("```");
+    while body.contains(&fence) {
+        fence.push('`');
+    }
+    format!("{}{}\n{}\n{}", fence, info, body.trim_end_matches('\n'), fence)
+}
+
+fn cell_magic(source: &str) -> (Option<String>, &str) {
+    let (first, rest) = source.split_once('\n').unwrap_or((source, ""));
+    let mut words = first.trim().strip_prefix("%%").unwrap_or("").split_whitespace();
+    let language = match words.next() {
+        Some("script") => words.next(),
+        Some(magic) if LANGUAGE_MAGICS.contains(&magic) => Some(magic),
+        _ => None,
+    };
+    match language {
+        Some(language) => (Some(normalize(language)), rest),
+        None => (None, source),
+    }
+}
+
+// Writes the document as a notebook: front matter as a raw cell, the markdown between blocks as
+// markdown cells and each block of `file` that `commands` holds as a code cell, with what it
+// printed when `transcript` has it. Blocks in another language than the notebook's get a cell magic.
+pub fn from_markdown(text: &str, file: &str, commands: &[Command], transcript: &Transcript) -> Json {
+    let kernel = notebook_language(commands);
+    let (_, body_start) = split_front_matter(text);
+    let mut cells = vec![];
+    if body_start > 0 {
+        cells.push(cell("raw", &text[..body_start]));
+    }
+
+    let mut markdown_start = body_start;
+    let mut count = 0;
+    for (event, range) in Parser::new(&text[body_start..]).into_offset_iter() {
+        let info = match event {
+            Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(info))) => info,
+            _ => continue,
+        };
+        let line = line_of(text, body_start + range.start) + 1;
+        let cmd = match commands.iter().find(|cmd| cmd.script.file == file && cmd.script.line == line) {
+            Some(cmd) => cmd,
+            None => continue,
+        };
+        cells.push(cell("markdown", &text[markdown_start..body_start + range.start]));
+        markdown_start = body_start + range.end;
+
+        let lang = normalize(&cmd.script.executor);
+        let source = match (lang == kernel, kernel.as_str(), lang.as_str()) {
+            (true, _, _) | (false, _, "") => cmd.script.source.clone(),
+            (false, "python", "sh") | (false, "python", "bash") => format!("%%bash\n{}", cmd.script.source),
+            (false, "python", program) => format!("%%script {}\n{}", program, cmd.script.source),
+            (false, _, _) => cmd.script.source.clone(),
+        };
+        let run = transcript.get(&(cmd.script.file.clone(), cmd.script.line)).map(|outputs| {
+            count += 1;
+            (count, outputs)
+        });
+        // without a magic, only the info string says what a block in another language is in
+        let info = Some(info.trim()).filter(|info| *info != cmd.script.executor || (lang != kernel && source == cmd.script.source));
+        cells.push(code_cell(&source, info, run));
+    }
+    cells.push(cell("markdown", &text[markdown_start..]));
+    cells.retain(|cell| cell.get("source").and_then(Json::as_array).is_some_and(|lines| !lines.is_empty()));
+
+    let (name, display_name) = match kernel.as_str() {
+        "python" => (String::from("python3"), String::from("Python 3")),
+        language => (language.to_string(), language.to_string()),
+    };
+    Json::Object(vec![
+        (String::from("cells"), Json::Array(cells)),
+        (String::from("metadata"), Json::Object(vec![
+            (String::from("kernelspec"), Json::Object(vec![
+                (String::from("display_name"), Json::String(display_name)),
+                (String::from("language"), Json::String(kernel.clone())),
+                (String::from("name"), Json::String(name)),
+            ])),
+            (String::from("language_info"), Json::Object(vec![(String::from("name"), Json::String(kernel))])),
+        ])),
+        (String::from("nbformat"), Json::Number(4.0)),
+        (String::from("nbformat_minor"), Json::Number(4.0)),
+    ])
+}
+
+// the language most of the blocks are in, Python for a document without any
+fn notebook_language(commands: &[Command]) -> String {
+    let mut counts: Vec<(String, usize)> = vec![];
+    for cmd in commands {
+        let lang = normalize(&cmd.script.executor);
+        match counts.iter_mut().find(|(name, _)| *name == lang) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((lang, 1)),
+        }
+    }
+    // the first of equally common languages wins
+    counts.iter().rev().max_by_key(|(_, count)| *count).map(|(lang, _)| lang.clone()).unwrap_or_else(|| String::from("python"))
+}
+
+fn cell(cell_type: &str, source: &str) -> Json {
+    Json::Object(vec![
+        (String::from("cell_type"), Json::String(cell_type.to_string())),
+        (String::from("metadata"), Json::Object(vec![])),
+        (String::from("source"), lines(source)),
+    ])
+}
+
+// `run` is what the block printed and when, among the blocks that ran, it ran
+fn code_cell(source: &str, info: Option<&str>, run: Option<(usize, &BlockOutputs)>) -> Json {
+    let metadata = match info {
+        Some(info) => vec![(String::from("rinput"), Json::Object(vec![(String::from("info"), Json::String(info.to_string()))]))],
+        None => vec![],
+    };
+    let (count, outputs) = match run {
+        Some((count, outputs)) => (Json::Number(count as f64), streams(outputs)),
+        None => (Json::Null, vec![]),
+    };
+    Json::Object(vec![
+        (String::from("cell_type"), Json::String(String::from("code"))),
+        (String::from("execution_count"), count),
+        (String::from("metadata"), Json::Object(metadata)),
+        (String::from("outputs"), Json::Array(outputs)),
+        (String::from("source"), lines(source)),
+    ])
+}
+
+// Jupyter keeps text as a list of lines, each with its newline but the last
+fn lines(text: &str) -> Json {
+    Json::Array(text.trim_matches('\n').split_inclusive('\n').map(|line| Json::String(line.to_string())).collect())
+}
+
+fn streams(outputs: &BlockOutputs) -> Vec<Json> {
+    [("stdout", &outputs.stdout), ("stderr", &outputs.stderr)].iter()
+        .filter(|(_, text)| !text.is_empty())
+        .map(|(name, text)| Json::Object(vec![
+            (String::from("name"), Json::String(name.to_string())),
+            (String::from("output_type"), Json::String(String::from("stream"))),
+            (String::from("text"), Json::Array(text.split_inclusive('\n').map(|line| Json::String(line.to_string())).collect())),
+        ]))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::process;
+
+    use crate::rmd::executor::Transcript;
+    use crate::rmd::interpolate::BlockOutputs;
+    use crate::rmd::json::Json;
+    use crate::rmd::notebook::{from_markdown, to_markdown};
+    use crate::rmd::Rmd;
+
+    const NOTEBOOK: &str = r###"{
+ "cells": [
+  {"cell_type": "markdown", "metadata": {}, "source": ["# Report\n", "\n", "Loads the data."]},
+  {"cell_type": "code", "execution_count": 1, "metadata": {}, "outputs": [], "source": ["rows = [1, 2]\n", "print(len(rows))"]},
+  {"cell_type": "code", "execution_count": null, "metadata": {}, "outputs": [], "source": "%%bash\necho done"},
+  {"cell_type": "code", "execution_count": null, "metadata": {}, "outputs": [], "source": []}
+ ],
+ "metadata": {"kernelspec": {"display_name": "Python 3", "language": "python", "name": "python3"}},
+ "nbformat": 4,
+ "nbformat_minor": 5
+}"###;
+
+    #[test]
+    fn should_read_cells_as_blocks() {
+        let markdown = to_markdown(NOTEBOOK).unwrap();
+        assert_eq!("# Report\n\nLoads the data.\n\n```python\nrows = [1, 2]\nprint(len(rows))\n```\n\n```bash\necho done\n```\n\n", markdown);
+
+        let commands = Rmd::new(markdown).parse();
+        assert_eq!(2, commands.len());
+        assert_eq!("python", commands[0].script.executor);
+        assert_eq!(vec!["Report"], commands[1].path);
+        assert!(to_markdown("{\"metadata\": {}}").is_err());
+    }
+
+    #[test]
+    fn should_not_run_the_code_of_markdown_cells() {
+        let notebook = r#"{"cells": [
+  {"cell_type": "markdown", "metadata": {}, "source": ["Clean up with\n", "\n", "```bash\n", "rm -rf data\n", "```\n", "\n", "~~~json\n", "{}\n", "~~~"]},
+  {"cell_type": "code", "metadata": {}, "source": "print(1)", "outputs": []}
+], "metadata": {"kernelspec": {"language": "python"}}}"#;
+        let markdown = to_markdown(notebook).unwrap();
+        assert_eq!("Clean up with\n\n```text\nrm -rf data\n```\n\n~~~json\n{}\n~~~\n\n```python\nprint(1)\n```\n\n", markdown);
+
+        let commands = Rmd::new(markdown).parse();
+        assert_eq!(1, commands.len());
+        assert_eq!("print(1)", commands[0].script.source.trim());
+    }
+
+    #[test]
+    fn should_run_consecutive_kernel_cells_as_one_block() {
+        let notebook = r###"{
+ "cells": [
+  {"cell_type": "code", "metadata": {}, "outputs": [], "source": ["%matplotlib inline\n", "total = 2"]},
+  {"cell_type": "code", "metadata": {}, "outputs": [], "source": ["%time total += 1\n", "!echo \"shell\"\n", "print(total)"]},
+  {"cell_type": "markdown", "metadata": {}, "source": "Done."}
+ ],
+ "metadata": {"kernelspec": {"language": "python"}}
+}"###;
+        let markdown = to_markdown(notebook).unwrap();
+        assert_eq!("```python\n# %matplotlib inline\ntotal = 2\ntotal += 1\n__import__(\"subprocess\").run(\"echo \\\"shell\\\"\", shell=True, check=True)\nprint(total)\n```\n\nDone.\n\n", markdown);
+
+        let commands = Rmd::new(markdown).parse();
+        assert_eq!(1, commands.len());
+        if let Ok(output) = process::Command::new("python3").arg("-c").arg(&commands[0].script.source).output() {
+            assert_eq!("shell\n3\n", String::from_utf8_lossy(&output.stdout));
+        }
+    }
+
+    #[test]
+    fn should_write_blocks_with_outputs() {
+        let text = "---\nshell: bash\n---\n# Report\n\n```python\nprint(2)\n```\n\n```sh id=done\necho done\n```\n\nThe end.\n";
+        let commands = Rmd::new(String::from(text)).parse();
+        let mut transcript = Transcript::new();
+        transcript.insert((String::new(), commands[0].script.line), BlockOutputs { stdout: String::from("2\n"), ..BlockOutputs::default() });
+
+        let notebook = Json::parse(&from_markdown(text, "", &commands, &transcript).pretty()).unwrap();
+        let cells = notebook.get("cells").unwrap().as_array().unwrap();
+        let sources: Vec<String> = cells.iter().map(|cell| cell.get("source").unwrap().as_array().unwrap()
+            .iter().filter_map(Json::as_str).collect()).collect();
+        assert_eq!(vec!["---\nshell: bash\n---", "# Report", "print(2)", "%%bash\necho done", "The end."], sources);
+        assert_eq!(Some(1), cells[2].get("execution_count").unwrap().as_u64());
+        assert_eq!("2\n", cells[2].get("outputs").unwrap().as_array().unwrap()[0].get("text").unwrap().as_array().unwrap()[0].as_str().unwrap());
+        assert_eq!(&Json::Null, cells[3].get("execution_count").unwrap());
+
+        // the info string rides along in the cell's metadata, so the document reads back the same
+        let markdown = to_markdown(&notebook.to_string()).unwrap();
+        assert_eq!("---\nshell: bash\n---\n\n# Report\n\n```python\nprint(2)\n```\n\n```sh id=done\necho done\n```\n\nThe end.\n\n", markdown);
+    }
+}
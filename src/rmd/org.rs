@@ -0,0 +1,173 @@
+// Reads an org-mode document as markdown, line for line so blocks keep their line numbers:
+// `** deploy (env)` becomes `## deploy (env)`, `#+BEGIN_SRC sh :dir build` a ```sh cwd=build
+// fence, and a `#+NAME:` above a block its `id=`. Other keywords and comments are dropped.
+pub fn to_markdown(text: &str) -> String {
+    let lines: Vec<&str> = text.lines().collect();
+    let mut out = vec![];
+    let mut name: Option<String> = None;
+    // the fence that closes the block the line is in
+    let mut open: Option<String> = None;
+    for (index, line) in lines.iter().enumerate() {
+        let trimmed = line.trim_start();
+        if let Some(fence) = &open {
+            if keyword(trimmed, "#+end_").is_some() {
+                out.push(fence.clone());
+                open = None;
+            } else {
+                out.push(unescape(line));
+            }
+            continue;
+        }
+
+        if let Some(header) = keyword(trimmed, "#+begin_src") {
+            let fence = fence(&lines[index + 1..]);
+            out.push(format!("{}{}", fence, info(header, name.take())));
+            open = Some(fence);
+        } else if keyword(trimmed, "#+begin_example").is_some() {
+            let fence = fence(&lines[index + 1..]);
+            out.push(format!("{}text", fence));
+            open = Some(fence);
+        } else if let Some(value) = keyword(trimmed, "#+name:") {
+            name = Some(value.trim().to_string());
+            out.push(String::new());
+        } else if trimmed.starts_with("#+") || trimmed == "#" || trimmed.starts_with("# ") {
+            out.push(String::new());
+        } else {
+            if !trimmed.is_empty() {
+                name = None;
+            }
+            out.push(heading(line).unwrap_or_else(|| line.to_string()));
+        }
+    }
+    // a block left open runs to the end of the document
+    if let Some(fence) = open {
+        out.push(fence);
+    }
+    out.join("\n") + "\n"
+}
+
+// what follows `key` at the start of `line`, whatever the case of either
+fn keyword<'a>(line: &'a str, key: &str) -> Option<&'a str> {
+    match line.get(..key.len()) {
+        Some(start) if start.eq_ignore_ascii_case(key) => Some(&line[key.len()..]),
+        _ => None,
+    }
+}
+
+// longer than any run of backticks in the block that starts `lines`, so none of them closes it
+fn fence(lines: &[&str]) -> String {
+    let body: Vec<String> = lines.iter()
+        .take_while(|line| keyword(line.trim_start(), "#+end_").is_none())
+        .map(|line| unescape(line))
+        .collect();
+    let body = body.join("\n");
+    let mut fence = String::from("```");
+    while body.contains(&fence) {
+        fence.push('`');
+    }
+    fence
+}
+
+// `sh :dir build :results output` -> sh cwd=build results=output
+fn info(header: &str, name: Option<String>) -> String {
+    let mut words = header.split_whitespace().peekable();
+    let mut info = vec![words.next().unwrap_or("").to_string()];
+    while let Some(word) = words.next() {
+        let key = match word.strip_prefix(':') {
+            Some("dir") => "cwd",
+            Some(key) => key,
+            None => continue,
+        };
+        let value = match words.peek() {
+            Some(value) if !value.starts_with(':') => words.next().unwrap(),
+            _ => "true",
+        };
+        info.push(format!("{}={}", key, value));
+    }
+    if let Some(name) = name {
+        info.push(format!("id={}", name));
+    }
+    info.join(" ")
+}
+
+// org escapes block lines that would read as a heading or a keyword with a comma
+fn unescape(line: &str) -> String {
+    let trimmed = line.trim_start();
+    match trimmed.strip_prefix(',') {
+        Some(rest) if rest.starts_with('*') || rest.starts_with("#+") => format!("{}{}", &line[..line.len() - trimmed.len()], rest),
+        _ => line.to_string(),
+    }
+}
+
+// `** TODO deploy :ops:` -> `## deploy`
+fn heading(line: &str) -> Option<String> {
+    let level = line.chars().take_while(|c| *c == '*').count();
+    let title = line[level..].strip_prefix(' ').filter(|_| level > 0)?.trim();
+    let title = title.strip_prefix("TODO ").or_else(|| title.strip_prefix("DONE ")).unwrap_or(title).trim_start();
+    let title = match title.rfind(char::is_whitespace) {
+        Some(index) if is_tags(&title[index + 1..]) => title[..index].trim_end(),
+        _ => title,
+    };
+    Some(format!("{} {}", "#".repeat(level.min(6)), title))
+}
+
+fn is_tags(word: &str) -> bool {
+    word.len() > 2 && word.starts_with(':') && word.ends_with(':')
+        && word.chars().all(|c| c == ':' || c == '@' || c == '_' || c.is_alphanumeric())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::rmd::org::to_markdown;
+    use crate::rmd::Rmd;
+
+    const DOC: &str = "#+TITLE: Ops
+# a comment
+
+* Ops
+** TODO deploy (env)                                              :ops:
+Deploys the current build.
+
+#+NAME: build
+#+BEGIN_SRC sh :dir build :results output
+echo deploying to $env
+,* not a heading
+#+END_SRC
+
+** status
+#+begin_src python
+print('ok')
+#+end_src
+
+#+BEGIN_EXAMPLE
+echo not run
+#+END_EXAMPLE
+";
+
+    #[test]
+    fn should_read_source_blocks_in_place() {
+        let commands = Rmd::new(to_markdown(DOC)).parse();
+
+        assert_eq!(2, commands.len());
+        assert_eq!(vec!["Ops", "deploy"], commands[0].path);
+        assert_eq!("env", commands[0].required_args[0].name);
+        assert_eq!("Deploys the current build.", commands[0].desc);
+        assert_eq!(10, commands[0].script.line);
+        assert_eq!("echo deploying to $env\n* not a heading\n", commands[0].script.source);
+        assert_eq!(Some("build"), commands[0].script.attr("id"));
+        assert_eq!(Some("build"), commands[0].script.attr("cwd"));
+        assert_eq!(Some("output"), commands[0].script.attr("results"));
+        assert_eq!("python", commands[1].script.executor);
+        assert_eq!(16, commands[1].script.line);
+    }
+
+    #[test]
+    fn should_fence_blocks_holding_backticks() {
+        let doc = "#+begin_src sh\ncat <<'EOF'\n```\nEOF\n#+end_src\n";
+        let markdown = to_markdown(doc);
+        assert_eq!("````sh\ncat <<'EOF'\n```\nEOF\n````\n", markdown);
+
+        let commands = Rmd::new(markdown).parse();
+        assert_eq!("cat <<'EOF'\n```\nEOF\n", commands[0].script.source);
+    }
+}
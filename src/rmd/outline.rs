@@ -0,0 +1,320 @@
+use colored::*;
+
+use crate::rmd::command::{Command, OptionFlag};
+use crate::rmd::json::Json;
+
+// Nests the parsed commands by their heading path. Headings without a block of their own become
+// empty commands that only group their subcommands.
+pub fn command_tree(commands: &[Command]) -> Vec<Command> {
+    let mut tree = vec![];
+    for cmd in commands {
+        let mut nodes = &mut tree;
+        let depth = cmd.path.len().saturating_sub(1);
+        for (level, name) in cmd.path[..depth].iter().enumerate() {
+            let found = nodes.iter().rposition(|node: &Command| &node.name == name);
+            let index = match found {
+                Some(index) => index,
+                None => {
+                    let mut group = Command::new(level as u8 + 1);
+                    group.name = name.clone();
+                    group.path = cmd.path[..=level].to_vec();
+                    nodes.push(group);
+                    nodes.len() - 1
+                }
+            };
+            nodes = &mut nodes[index].subcommands;
+        }
+        nodes.push(cmd.clone());
+    }
+    tree
+}
+
+// A lone `# Title` over the whole document names the document, not a command.
+pub fn has_title(tree: &[Command]) -> bool {
+    matches!(tree, [title] if title.cmd_level == 1 && title.script.source.is_empty())
+}
+
+pub fn commands_root(tree: &[Command]) -> &[Command] {
+    if has_title(tree) {
+        &tree[0].subcommands
+    } else {
+        tree
+    }
+}
+
+// a heading as typed on the command line: `Deploy to Staging` -> `deploy-to-staging`
+pub fn command_word(name: &str) -> String {
+    name.split_whitespace()
+        .map(|part| part.chars().filter(|c| c.is_alphanumeric() || "-_.".contains(*c)).collect::<String>())
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<String>>()
+        .join("-")
+        .to_lowercase()
+}
+
+// the commands whose heading path ends with `query`, given as `deploy staging` or `deploy/staging`
+pub fn find<'a>(commands: &'a [Command], query: &str) -> Vec<&'a Command> {
+    let query = query.trim().to_lowercase();
+    commands.iter()
+        .filter(|cmd| {
+            (0..cmd.path.len()).any(|start| {
+                let suffix = &cmd.path[start..];
+                let words: Vec<String> = suffix.iter().map(|name| command_word(name)).collect();
+                suffix.join(" ").to_lowercase() == query || suffix.join("/").to_lowercase() == query
+                    || words.join(" ") == query || words.join("/") == query
+            })
+        })
+        .collect()
+}
+
+pub fn render_tree(tree: &[Command]) -> String {
+    let mut out = String::new();
+    render_nodes(tree, 0, &mut out);
+    out
+}
+
+// blocks under the same heading share its description and options, which are shown once
+fn render_nodes(nodes: &[Command], depth: usize, out: &mut String) {
+    for (index, cmd) in nodes.iter().enumerate() {
+        let repeated = index > 0 && nodes[index - 1].path == cmd.path;
+        render_node(cmd, depth, !repeated, out);
+    }
+}
+
+fn render_node(cmd: &Command, depth: usize, details: bool, out: &mut String) {
+    let indent = "  ".repeat(depth);
+    let name = if cmd.name.is_empty() { String::from("(untitled)") } else { cmd.name.clone() };
+    let args: Vec<String> = cmd.required_args.iter().map(|arg| format!("<{}>", arg.name)).collect();
+    let mut line = format!("{}{}", indent, name.bold());
+    if !args.is_empty() {
+        line.push_str(&format!(" {}", args.join(" ").cyan()));
+    }
+    if !cmd.script.executor.is_empty() {
+        line.push_str(&format!("  {} {}", cmd.script.executor.green(), location(cmd).dimmed()));
+    }
+    out.push_str(&line);
+    out.push('\n');
+
+    if !details {
+        return render_nodes(&cmd.subcommands, depth + 1, out);
+    }
+    if !cmd.desc.is_empty() {
+        out.push_str(&format!("{}    {}\n", indent, cmd.desc));
+    }
+    for flag in cmd.option_flags.iter().filter(|flag| flag.name != "verbose") {
+        out.push_str(&format!("{}    {:<24} {}\n", indent, flag_usage(flag), flag.desc.dimmed()));
+    }
+    render_nodes(&cmd.subcommands, depth + 1, out);
+}
+
+fn flag_usage(flag: &OptionFlag) -> String {
+    let mut usage = match (flag.short.is_empty(), flag.long.is_empty()) {
+        (false, false) => format!("-{}, --{}", flag.short, flag.long),
+        (false, true) => format!("-{}", flag.short),
+        _ => format!("    --{}", flag.long),
+    };
+    if flag.takes_value {
+        usage.push_str(&format!(" <{}>", flag.name));
+    }
+    usage
+}
+
+fn location(cmd: &Command) -> String {
+    if cmd.script.file.is_empty() {
+        format!("line {}", cmd.script.line)
+    } else {
+        format!("{}:{}", cmd.script.file, cmd.script.line)
+    }
+}
+
+pub fn tree_to_json(tree: &[Command]) -> Json {
+    Json::Array(tree.iter().map(command_to_json).collect())
+}
+
+fn command_to_json(cmd: &Command) -> Json {
+    let string = |value: &str| Json::String(value.to_string());
+    let optional = |value: &str| if value.is_empty() { Json::Null } else { string(value) };
+    let flags = cmd.option_flags.iter().map(|flag| Json::Object(vec![
+        (String::from("name"), string(&flag.name)),
+        (String::from("short"), optional(&flag.short)),
+        (String::from("long"), optional(&flag.long)),
+        (String::from("description"), string(&flag.desc)),
+        (String::from("takes_value"), Json::Bool(flag.takes_value)),
+    ])).collect();
+    let has_script = !cmd.script.source.is_empty();
+
+    Json::Object(vec![
+        (String::from("name"), string(&cmd.name)),
+        (String::from("path"), Json::Array(cmd.path.iter().map(|name| string(name)).collect())),
+        (String::from("description"), string(&cmd.desc)),
+        (String::from("language"), optional(&cmd.script.executor)),
+        (String::from("file"), if has_script { string(&cmd.script.file) } else { Json::Null }),
+        (String::from("line"), if has_script { Json::Number(cmd.script.line as f64) } else { Json::Null }),
+        (String::from("args"), Json::Array(cmd.required_args.iter().map(|arg| string(&arg.name)).collect())),
+        (String::from("flags"), Json::Array(flags)),
+        (String::from("subcommands"), Json::Array(cmd.subcommands.iter().map(command_to_json).collect())),
+    ])
+}
+
+pub fn render_command(cmd: &Command) -> String {
+    let mut out = format!("{}  {} {}\n", cmd.path.join(" / ").bold(), cmd.script.executor.green(), location(cmd).dimmed());
+    if !cmd.desc.is_empty() {
+        out.push_str(&format!("{}\n", cmd.desc));
+    }
+    for arg in &cmd.required_args {
+        out.push_str(&format!("    {:<24} {}\n", format!("<{}>", arg.name).cyan(), "required".dimmed()));
+    }
+    for flag in cmd.option_flags.iter().filter(|flag| flag.name != "verbose") {
+        out.push_str(&format!("    {:<24} {}\n", flag_usage(flag), flag.desc.dimmed()));
+    }
+    out.push('\n');
+
+    let width = (cmd.script.line + cmd.script.source.lines().count()).to_string().len();
+    for (offset, line) in cmd.script.source.lines().enumerate() {
+        let number = format!("{:>width$}", cmd.script.line + offset, width = width);
+        out.push_str(&format!("{} {} {}\n", number.blue(), "|".blue(), highlight_line(&cmd.script.executor, line)));
+    }
+    out
+}
+
+const SHELL_KEYWORDS: &[&str] = &["if", "then", "else", "elif", "fi", "for", "while", "until", "do", "done", "case",
+    "esac", "function", "in", "export", "local", "return", "set"];
+const PYTHON_KEYWORDS: &[&str] = &["def", "class", "if", "elif", "else", "for", "while", "in", "import", "from",
+    "return", "with", "as", "try", "except", "finally", "raise", "lambda", "pass", "None", "True", "False", "and",
+    "or", "not", "print"];
+const RUST_KEYWORDS: &[&str] = &["fn", "let", "mut", "pub", "struct", "enum", "impl", "trait", "use", "mod", "match",
+    "if", "else", "for", "while", "loop", "return", "in", "as", "where", "self", "Self", "crate", "const", "static"];
+const JS_KEYWORDS: &[&str] = &["function", "const", "let", "var", "if", "else", "for", "while", "return", "import",
+    "export", "from", "class", "new", "async", "await", "of", "in", "interface", "type"];
+const RUBY_KEYWORDS: &[&str] = &["def", "end", "class", "module", "if", "elsif", "else", "unless", "do", "while",
+    "return", "require", "puts", "nil", "true", "false"];
+
+// (line comment, quote characters, keywords); unknown languages are shown as they are
+fn syntax(lang: &str) -> Option<(&'static str, &'static str, &'static [&'static str])> {
+    match lang {
+        "sh" | "bash" | "zsh" | "shell" => Some(("#", "\"'", SHELL_KEYWORDS)),
+        "py" | "python" => Some(("#", "\"'", PYTHON_KEYWORDS)),
+        "rust" => Some(("//", "\"", RUST_KEYWORDS)),
+        "js" | "javascript" | "mjs" | "ts" | "typescript" | "php" => Some(("//", "\"'`", JS_KEYWORDS)),
+        "rb" | "ruby" => Some(("#", "\"'", RUBY_KEYWORDS)),
+        _ => None,
+    }
+}
+
+pub fn highlight_line(lang: &str, line: &str) -> String {
+    let (comment, quotes, keywords) = match syntax(lang) {
+        Some(syntax) => syntax,
+        None => return line.to_string(),
+    };
+
+    let mut out = String::new();
+    let mut index = 0;
+    while index < line.len() {
+        let rest = &line[index..];
+        let c = rest.chars().next().unwrap();
+        if rest.starts_with(comment) {
+            out.push_str(&rest.dimmed().to_string());
+            break;
+        }
+        if quotes.contains(c) {
+            let end = rest[1..].find(c).map(|end| end + 2).unwrap_or(rest.len());
+            out.push_str(&rest[..end].green().to_string());
+            index += end;
+            continue;
+        }
+        if c.is_alphanumeric() || c == '_' {
+            let end = rest.find(|c: char| !(c.is_alphanumeric() || c == '_')).unwrap_or(rest.len());
+            let word = &rest[..end];
+            if keywords.contains(&word) {
+                out.push_str(&word.blue().bold().to_string());
+            } else if word.chars().all(|c| c.is_ascii_digit()) {
+                out.push_str(&word.yellow().to_string());
+            } else {
+                out.push_str(word);
+            }
+            index += end;
+            continue;
+        }
+        out.push(c);
+        index += c.len_utf8();
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use crate::rmd::outline::{command_tree, find, tree_to_json};
+    use crate::rmd::Rmd;
+
+    const DOC: &str = "# Ops
+
+## deploy (env)
+
+> Deploys the current build
+
+**OPTIONS**
+* dry-run
+    * flags: -n --dry-run
+    * desc: Only print what would change
+
+```sh
+echo deploying to $env
+```
+
+### rollback
+
+```sh
+echo rolling back
+```
+
+## status
+
+```python
+print('ok')
+```
+";
+
+    #[test]
+    fn should_nest_commands_by_heading() {
+        let commands = Rmd::new(String::from(DOC)).parse();
+        let tree = command_tree(&commands);
+
+        assert_eq!(1, tree.len());
+        let ops = &tree[0];
+        assert_eq!("Ops", ops.name);
+        assert!(ops.script.source.is_empty());
+        assert_eq!(vec!["deploy", "status"], ops.subcommands.iter().map(|cmd| cmd.name.as_str()).collect::<Vec<_>>());
+
+        let deploy = &ops.subcommands[0];
+        assert_eq!("Deploys the current build", deploy.desc);
+        assert_eq!("env", deploy.required_args[0].name);
+        assert_eq!(vec!["dry-run", "verbose"], deploy.option_flags.iter().map(|flag| flag.name.as_str()).collect::<Vec<_>>());
+        assert_eq!("n", deploy.option_flags[0].short);
+        assert_eq!("dry-run", deploy.option_flags[0].long);
+        assert_eq!("Only print what would change", deploy.option_flags[0].desc);
+        assert_eq!("rollback", deploy.subcommands[0].name);
+        assert!(deploy.subcommands[0].option_flags.iter().all(|flag| flag.name != "dry-run"));
+    }
+
+    #[test]
+    fn should_find_commands_by_path_suffix() {
+        let commands = Rmd::new(String::from(DOC)).parse();
+
+        assert_eq!(13, find(&commands, "deploy").first().unwrap().script.line);
+        assert_eq!(19, find(&commands, "Deploy Rollback").first().unwrap().script.line);
+        assert_eq!(19, find(&commands, "ops/deploy/rollback").first().unwrap().script.line);
+        assert!(find(&commands, "rollback deploy").is_empty());
+    }
+
+    #[test]
+    fn should_describe_tree_as_json() {
+        let commands = Rmd::new(String::from(DOC)).parse();
+        let json = tree_to_json(&command_tree(&commands));
+        let status = &json.as_array().unwrap()[0].get("subcommands").unwrap().as_array().unwrap()[1];
+
+        assert_eq!("status", status.str_or_empty("name"));
+        assert_eq!("python", status.str_or_empty("language"));
+        assert_eq!(Some(25), status.get("line").unwrap().as_u64());
+        assert_eq!("[\"Ops\",\"status\"]", status.get("path").unwrap().to_string());
+    }
+}
@@ -1,60 +1,164 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+
 use pulldown_cmark::{Event::{Code, End, Html, Start, Text}, Options, Parser, Tag, CodeBlockKind};
-use crate::rmd::command::Command;
+use crate::rmd::command::{Command, Fixture, OptionFlag, RequiredArg};
+use crate::rmd::document;
+use crate::rmd::outline::{command_tree, command_word, has_title};
 
 pub struct Rmd {
-    text: String
+    text: String,
+    path: String,
+    fixtures: Vec<Fixture>,
+    // the documents that include this one, outermost first
+    including: Vec<PathBuf>,
+    errors: Vec<String>,
 }
 
 impl Rmd {
     pub fn new(text: String) -> Rmd {
         Rmd {
-            text
+            text,
+            path: "".to_string(),
+            fixtures: vec![],
+            including: vec![],
+            errors: vec![],
         }
     }
 
+    pub fn with_path(text: String, path: String) -> Rmd {
+        let mut rmd = Rmd::new(text);
+        rmd.path = path;
+        rmd
+    }
+
+    // One command per executable block. Each takes the name, args, description and options of
+    // the heading it sits under, mask style:
+    //
+    //     ## deploy (env)
+    //     > Deploys the current build
+    //     **OPTIONS**
+    //     * dry-run
+    //         * flags: -n --dry-run
+    //         * desc: Only print what would change
+    //
+    // `<!-- rinput:include setup.md#install -->` splices in the commands of another document, or
+    // of one section of it, under the heading the include sits under. Other `<!-- rinput: ... -->`
+    // comments set attributes, `skip` or `only-on=linux`, or with `env FOO=bar` variables, for
+    // the next block, without showing in the rendered document.
     pub fn parse(&mut self) -> Vec<Command> {
-        let parser = create_markdown_parser(&self.text);
+        let (doc_attrs, body_start) = split_front_matter(&self.text);
+        // a copy, as following an include needs the parser itself
+        let text = self.text.clone();
+        let parser = create_markdown_parser(&text[body_start..]);
         let mut commands = vec![];
-        let mut current_command = Command::new(1);
+        let mut sections: Vec<Command> = vec![];
+        let mut section_has_block = false;
+        let mut in_options = false;
+        let mut option_started = false;
+        let mut list_depth = 0;
+        let mut current_command: Option<Command> = None;
+        let mut current_fixture: Option<Fixture> = None;
+        // what the directives since the last block set for the next one
+        let mut directive_attrs: HashMap<String, String> = HashMap::new();
+        let mut directive_env: Vec<(String, String)> = vec![];
         let mut text = "".to_string();
+        self.fixtures = vec![];
+        self.errors = vec![];
 
-        for event in parser {
+        for (event, range) in parser.into_offset_iter() {
             match event {
                 Start(tag) => {
+                    // inline tags such as emphasis or links are part of the surrounding text
+                    let starts_block = is_block_tag(&tag);
                     match tag {
                         #[cfg(not(windows))]
                         Tag::CodeBlock(info) => {
                             match info {
-                                CodeBlockKind::Fenced(lang_code) => {
-                                    if lang_code.to_string() != String::from("powershell")
-                                        && lang_code.to_string() != String::from("batch")
-                                        && lang_code.to_string() != String::from("cmd")
-                                    {
-                                        current_command.script.executor = lang_code.to_string();
+                                CodeBlockKind::Fenced(info) => {
+                                    let (lang_code, attrs) = parse_info_string(&info);
+                                    // the source starts on the line after the opening fence
+                                    let line = line_of(&self.text, body_start + range.start) + 1;
+                                    let directed = std::mem::take(&mut directive_attrs);
+                                    let env = std::mem::take(&mut directive_env);
+                                    if lang_code == "file" {
+                                        let path = attrs.get("path").cloned().unwrap_or_default();
+                                        current_fixture = Some(Fixture::new(path, line));
+                                    } else if is_supported_lang(&lang_code) {
+                                        let mut command = sections.last().cloned().unwrap_or_else(|| Command::new(0));
+                                        command.path = sections.iter().map(|section| section.name.clone()).collect();
+                                        command.script.executor = lang_code;
+                                        command.script.attrs = doc_attrs.clone();
+                                        command.script.attrs.extend(directed);
+                                        command.script.attrs.extend(attrs);
+                                        command.script.env = env;
+                                        command.script.file = self.path.clone();
+                                        command.script.line = line;
+                                        current_command = Some(command);
+                                        section_has_block = true;
                                     }
                                 }
                                 CodeBlockKind::Indented => {}
                             }
                         }
+                        Tag::List(_) => {
+                            // a nested list under an option holds its settings
+                            if in_options && list_depth == 1 {
+                                start_option(&mut sections, &text);
+                                option_started = true;
+                            }
+                            list_depth += 1;
+                        }
+                        Tag::Item if list_depth == 1 => option_started = false,
+                        // directives do not carry over into another section
+                        Tag::Heading(_) => {
+                            directive_attrs.clear();
+                            directive_env.clear();
+                        }
                         _ => (),
                     }
 
-                    text = "".to_string();
+                    if starts_block {
+                        text = "".to_string();
+                    }
                 }
                 End(tag) => {
                     match tag {
                         #[cfg(not(windows))]
-                        Tag::CodeBlock(info) => {
-                            match info {
-                                CodeBlockKind::Fenced(lang_code) => {
-                                    if lang_code.to_string() != String::from("powershell")
-                                        && lang_code.to_string() != String::from("batch")
-                                        && lang_code.to_string() != String::from("cmd")
-                                    {
-                                        current_command.script.source = text.to_string();
-                                    }
-                                }
-                                CodeBlockKind::Indented => {}
+                        Tag::CodeBlock(_) => {
+                            if let Some(mut fixture) = current_fixture.take() {
+                                fixture.content = text.to_string();
+                                self.fixtures.push(fixture);
+                            }
+                            if let Some(mut command) = current_command.take() {
+                                command.script.source = text.to_string();
+                                commands.push(command.build());
+                            }
+                        }
+                        Tag::Heading(level) => {
+                            while sections.last().is_some_and(|section| section.cmd_level as u32 >= level) {
+                                sections.pop();
+                            }
+                            sections.push(parse_heading(level as u8, &text));
+                            section_has_block = false;
+                            in_options = false;
+                        }
+                        Tag::Paragraph if text.trim() == "OPTIONS" => in_options = true,
+                        Tag::Paragraph if list_depth == 0 && !section_has_block => {
+                            if let Some(section) = sections.last_mut().filter(|section| section.desc.is_empty()) {
+                                section.desc = text.trim().to_string();
+                            }
+                        }
+                        Tag::Item if in_options && list_depth == 1 && !option_started && !text.trim().is_empty() => {
+                            start_option(&mut sections, &text);
+                        }
+                        Tag::Item if in_options && list_depth > 1 => set_option(&mut sections, &text),
+                        Tag::List(_) => {
+                            list_depth -= 1;
+                            if list_depth == 0 {
+                                in_options = false;
                             }
                         }
                         _ => (),
@@ -63,7 +167,33 @@ impl Rmd {
                 Text(body) => {
                     text += &body.to_string();
                 }
-                Html(html) => {}
+                Html(html) => {
+                    let line = line_of(&self.text, body_start + range.start);
+                    if let Some(words) = directive(&html).and_then(|body| body.strip_prefix("env"))
+                        .filter(|words| words.is_empty() || words.starts_with(char::is_whitespace)) {
+                        for word in split_words(words) {
+                            match word.split_once('=') {
+                                Some((name, value)) if !name.is_empty() => directive_env.push((name.to_string(), value.to_string())),
+                                _ => self.errors.push(format!("{}:{}: `{}` in an env directive is not NAME=value", self.path, line, word)),
+                            }
+                        }
+                    } else if let Some(target) = include_target(&html) {
+                        let parent: Vec<String> = sections.iter().map(|section| section.name.clone()).collect();
+                        match self.include(target, &parent) {
+                            Ok(included) => commands.extend(included),
+                            Err(err) => self.errors.push(format!("{}:{}: {}", self.path, line, err)),
+                        }
+                    } else if let Some(body) = directive(&html) {
+                        for word in split_words(body) {
+                            match attr_pair(&word) {
+                                (key, value) if key == "skip" || key == "only-on" => {
+                                    directive_attrs.insert(key, value);
+                                }
+                                _ => self.errors.push(format!("{}:{}: unknown directive `{}`, expected skip, only-on= or env", self.path, line, word)),
+                            }
+                        }
+                    }
+                }
                 Code(inline_code) => {
                     text += &format!("`{}`", inline_code);
                 }
@@ -71,15 +201,402 @@ impl Rmd {
             }
         }
 
-        commands.push(current_command.build());
         commands
     }
+
+    // the ```file blocks found by the last `parse`
+    pub fn fixtures(&self) -> &[Fixture] {
+        &self.fixtures
+    }
+
+    // the includes the last `parse` could not follow
+    pub fn errors(&self) -> &[String] {
+        &self.errors
+    }
+
+    // Parses the document `target` names, relative to this one, and moves its commands, or those
+    // of its `#section`, under `parent`. They keep the file and lines they come from.
+    fn include(&mut self, target: &str, parent: &[String]) -> Result<Vec<Command>> {
+        let (file, section) = match target.split_once('#') {
+            Some((file, section)) => (file, Some(section)),
+            None => (target, None),
+        };
+        let path = Path::new(&self.path).parent().unwrap_or_else(|| Path::new("")).join(file);
+        let canonical = fs::canonicalize(&path)
+            .map_err(|err| Error::new(err.kind(), format!("cannot include {}: {}", path.display(), err)))?;
+        let mut including = self.including.clone();
+        including.extend(fs::canonicalize(&self.path).ok());
+        if including.contains(&canonical) {
+            let chain: Vec<String> = including.iter().chain(Some(&canonical)).map(|path| path.display().to_string()).collect();
+            return Err(Error::new(ErrorKind::InvalidInput, format!("include cycle: {}", chain.join(" -> "))));
+        }
+
+        let mut included = Rmd::with_path(document::read(&path.display().to_string())?, path.display().to_string());
+        included.including = including;
+        let commands = included.parse();
+        self.errors.append(&mut included.errors);
+        // the blocks of one section may still need the files the whole document sets up
+        self.fixtures.append(&mut included.fixtures);
+
+        let title = has_title(&command_tree(&commands));
+        let mut spliced = vec![];
+        for mut cmd in commands {
+            let start = match section {
+                Some(section) => match cmd.path.iter().position(|name| names_section(name, section)) {
+                    Some(start) => start,
+                    None => continue,
+                },
+                None if title => 1.min(cmd.path.len()),
+                None => 0,
+            };
+            cmd.path = parent.iter().chain(&cmd.path[start..]).cloned().collect();
+            cmd.cmd_level = cmd.path.len() as u8;
+            spliced.push(cmd);
+        }
+        match section {
+            Some(section) if spliced.is_empty() => {
+                Err(Error::new(ErrorKind::NotFound, format!("{} has no section `{}` with blocks", path.display(), section)))
+            }
+            _ => Ok(spliced),
+        }
+    }
+}
+
+// `<!-- rinput: only-on=linux -->` -> `only-on=linux`
+fn directive(html: &str) -> Option<&str> {
+    let comment = html.trim().strip_prefix("<!--")?.strip_suffix("-->")?.trim();
+    comment.strip_prefix("rinput:").map(str::trim)
+}
+
+// `<!-- rinput:include setup.md#install -->` -> `setup.md#install`
+pub fn include_target(html: &str) -> Option<&str> {
+    directive(html)?.strip_prefix("include").map(str::trim).filter(|target| !target.is_empty())
+}
+
+fn names_section(name: &str, section: &str) -> bool {
+    name.eq_ignore_ascii_case(section) || command_word(name) == command_word(section)
+}
+
+fn is_block_tag(tag: &Tag) -> bool {
+    matches!(tag, Tag::Paragraph | Tag::Heading(_) | Tag::BlockQuote | Tag::CodeBlock(_) | Tag::List(_) | Tag::Item)
+}
+
+// `deploy (env) (region)` -> a command named `deploy` with the required args `env` and `region`
+fn parse_heading(level: u8, text: &str) -> Command {
+    let mut command = Command::new(level);
+    let mut parts = text.split('(');
+    command.name = parts.next().unwrap_or("").trim().to_string();
+    for part in parts {
+        let arg = part.split(')').next().unwrap_or("").trim();
+        if !arg.is_empty() {
+            command.required_args.push(RequiredArg::new(arg.to_string()));
+        }
+    }
+    command
+}
+
+// the item text read so far is the option's name
+fn start_option(sections: &mut [Command], text: &str) {
+    if let Some(section) = sections.last_mut() {
+        let mut flag = OptionFlag::new();
+        flag.name = text.trim().to_string();
+        flag.long = flag.name.clone();
+        section.option_flags.push(flag);
+    }
+}
+
+// `* key: value` under an option sets one of its settings
+fn set_option(sections: &mut [Command], text: &str) {
+    let flag = match sections.last_mut().and_then(|section| section.option_flags.last_mut()) {
+        Some(flag) => flag,
+        None => return,
+    };
+    let mut pair = text.trim().splitn(2, ':');
+    let key = pair.next().unwrap_or("").trim().to_lowercase();
+    let value = pair.next().unwrap_or("").trim().trim_matches('`');
+    match key.as_ref() {
+        "flags" => {
+            for flag_name in value.split_whitespace() {
+                let flag_name = flag_name.trim_matches('`');
+                if let Some(long) = flag_name.strip_prefix("--") {
+                    flag.long = long.to_string();
+                } else if let Some(short) = flag_name.strip_prefix('-') {
+                    flag.short = short.to_string();
+                }
+            }
+        }
+        "type" => {
+            flag.takes_value = value != "bool" && value != "boolean";
+            flag.validate_as_number = value == "number";
+        }
+        "desc" | "description" => flag.desc = value.to_string(),
+        "multiple" => flag.multiple = value == "true",
+        _ => {}
+    }
+}
+
+// data, config and transcript blocks are only shown, never run
+const DISPLAY_ONLY_LANGS: [&str; 17] = [
+    "text", "txt", "plain", "output", "console", "json", "toml", "yaml", "yml", "csv", "xml",
+    "html", "css", "diff", "ini", "markdown", "md",
+];
+
+pub fn is_supported_lang(lang_code: &str) -> bool {
+    !lang_code.is_empty()
+        && lang_code != "powershell" && lang_code != "batch" && lang_code != "cmd"
+        && !DISPLAY_ONLY_LANGS.contains(&lang_code)
+}
+
+// ```python sandbox=strict name="a b" -> ("python", {sandbox: strict, name: a b}); a bare key means `true`
+pub fn parse_info_string(info: &str) -> (String, HashMap<String, String>) {
+    let mut tokens = split_words(info).into_iter();
+    let lang_code = tokens.next().unwrap_or_default();
+    let attrs = tokens.map(|token| attr_pair(&token)).collect();
+    (lang_code, attrs)
+}
+
+// whitespace separated, but a double-quoted value may hold spaces
+fn split_words(info: &str) -> Vec<String> {
+    let mut tokens = vec![];
+    let mut token = String::new();
+    let mut quoted = false;
+    for c in info.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                if !token.is_empty() {
+                    tokens.push(token.clone());
+                    token.clear();
+                }
+            }
+            c => token.push(c),
+        }
+    }
+    if !token.is_empty() {
+        tokens.push(token);
+    }
+    tokens
 }
 
+fn attr_pair(token: &str) -> (String, String) {
+    let mut pair = token.splitn(2, '=');
+    let key = pair.next().unwrap_or("").to_string();
+    let value = pair.next().unwrap_or("true").to_string();
+    (key, value)
+}
+
+// A document may start with `---` front matter of `key: value` lines, which become the
+// default attributes of every block. Returns them with the offset where the markdown starts.
+pub fn split_front_matter(text: &str) -> (HashMap<String, String>, usize) {
+    let mut attrs = HashMap::new();
+    if !text.starts_with("---\n") && !text.starts_with("---\r\n") {
+        return (attrs, 0);
+    }
+
+    let mut offset = text.find('\n').unwrap() + 1;
+    for line in text[offset..].split_inclusive('\n') {
+        offset += line.len();
+        let line = line.trim();
+        if line == "---" {
+            return (attrs, offset);
+        }
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let mut pair = line.splitn(2, ':');
+        let key = pair.next().unwrap_or("").trim();
+        let value = pair.next().unwrap_or("").trim().trim_matches('"');
+        attrs.insert(key.to_string(), value.to_string());
+    }
 
-fn create_markdown_parser(content: &String) -> Parser {
+    // no closing `---`, so this was a thematic break rather than front matter
+    (HashMap::new(), 0)
+}
+
+pub fn line_of(text: &str, offset: usize) -> usize {
+    text[..offset].matches('\n').count() + 1
+}
+
+fn create_markdown_parser(content: &str) -> Parser {
     let mut options = Options::empty();
     options.insert(Options::ENABLE_STRIKETHROUGH);
     let parser = Parser::new_ext(&content, options);
     parser
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use tempfile::tempdir;
+
+    use crate::rmd::Rmd;
+
+    #[test]
+    fn should_record_block_location() {
+        let mut rmd = Rmd::with_path(String::from("# Hello
+
+```rust
+fn main() {}
+```
+"), String::from("doc.md"));
+        let commands = rmd.parse();
+
+        assert_eq!("doc.md", commands[0].script.file);
+        assert_eq!(4, commands[0].script.line);
+    }
+
+    #[test]
+    fn should_parse_block_attributes_over_front_matter() {
+        let mut rmd = Rmd::new(String::from("---
+sandbox: strict
+title: \"Demo\"
+---
+
+```python retries=3 sandbox=off name=\"two words\" quiet
+print(1)
+```
+"));
+        let commands = rmd.parse();
+        let script = &commands[0].script;
+
+        assert_eq!("python", script.executor);
+        assert_eq!(Some("off"), script.attr("sandbox"));
+        assert_eq!(Some("Demo"), script.attr("title"));
+        assert_eq!(Some("two words"), script.attr("name"));
+        assert_eq!(Some("true"), script.attr("quiet"));
+        assert_eq!(7, script.line);
+    }
+
+    #[test]
+    fn should_apply_directives_to_the_next_block() {
+        let mut rmd = Rmd::new(String::from("# Ops
+
+<!-- rinput: skip only-on=linux -->
+<!-- rinput: env STAGE=prod NAME=\"a b\" -->
+
+```sh retries=2
+echo $STAGE
+```
+
+<!-- rinput: skip -->
+
+## status
+
+```sh
+echo ok
+```
+"));
+        let commands = rmd.parse();
+
+        assert_eq!(Some("true"), commands[0].script.attr("skip"));
+        assert_eq!(Some("linux"), commands[0].script.attr("only-on"));
+        assert_eq!(Some("2"), commands[0].script.attr("retries"));
+        assert_eq!(vec![(String::from("STAGE"), String::from("prod")), (String::from("NAME"), String::from("a b"))], commands[0].script.env);
+        // a directive does not reach past the next heading
+        assert_eq!(None, commands[1].script.attr("skip"));
+        assert!(commands[1].script.env.is_empty());
+        assert!(rmd.errors().is_empty());
+    }
+
+    #[test]
+    fn should_report_unknown_directives() {
+        let mut rmd = Rmd::with_path(String::from("# Ops
+
+<!-- rinput: retries=2 skip -->
+<!-- rinput: env STAGE -->
+
+```sh
+echo $STAGE
+```
+"), String::from("ops.md"));
+        let commands = rmd.parse();
+
+        assert_eq!(Some("true"), commands[0].script.attr("skip"));
+        assert_eq!(None, commands[0].script.attr("retries"));
+        assert_eq!(vec![
+            "ops.md:3: unknown directive `retries=2`, expected skip, only-on= or env",
+            "ops.md:4: `STAGE` in an env directive is not NAME=value",
+        ], rmd.errors());
+    }
+
+    #[test]
+    fn should_split_blocks_and_fixtures() {
+        let mut rmd = Rmd::new(String::from("```file path=data/input.csv
+a,b
+```
+
+```toml
+[not] = \"run\"
+```
+
+```sh
+cat data/input.csv
+```
+
+```python
+print(1)
+```
+"));
+        let commands = rmd.parse();
+
+        assert_eq!(2, commands.len());
+        assert_eq!("sh", commands[0].script.executor);
+        assert_eq!("cat data/input.csv\n", commands[0].script.source);
+        assert_eq!("python", commands[1].script.executor);
+
+        let fixtures = rmd.fixtures();
+        assert_eq!(1, fixtures.len());
+        assert_eq!("data/input.csv", fixtures[0].path);
+        assert_eq!("a,b\n", fixtures[0].content);
+        assert_eq!(2, fixtures[0].line);
+    }
+
+    #[test]
+    fn should_splice_included_blocks() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("setup.md"), "# Setup\n\n## install\n\n```sh\nmake install\n```\n\n## clean\n\n```sh\nmake clean\n```\n").unwrap();
+        let root = dir.path().join("ops.md");
+        let mut rmd = Rmd::with_path(String::from("# Ops
+
+## prepare
+
+<!-- rinput:include setup.md#install -->
+
+## setup
+
+<!-- rinput:include setup.md -->
+
+```sh
+echo done
+```
+"), root.display().to_string());
+        let commands = rmd.parse();
+
+        assert!(rmd.errors().is_empty());
+        let paths: Vec<Vec<&str>> = commands.iter().map(|cmd| cmd.path.iter().map(String::as_str).collect()).collect();
+        assert_eq!(vec![vec!["Ops", "prepare", "install"], vec!["Ops", "setup", "install"], vec!["Ops", "setup", "clean"], vec!["Ops", "setup"]], paths);
+        assert_eq!(dir.path().join("setup.md").display().to_string(), commands[0].script.file);
+        assert_eq!(6, commands[0].script.line);
+        assert_eq!("make clean\n", commands[2].script.source);
+        assert_eq!(root.display().to_string(), commands[3].script.file);
+    }
+
+    #[test]
+    fn should_report_include_cycles() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("a.md"), "<!-- rinput:include b.md -->\n").unwrap();
+        fs::write(dir.path().join("b.md"), "<!-- rinput:include a.md -->\n\n```sh\necho b\n```\n").unwrap();
+        let mut rmd = Rmd::with_path(String::from("<!-- rinput:include missing.md#nothing -->\n"), dir.path().join("ops.md").display().to_string());
+        rmd.parse();
+        assert!(rmd.errors()[0].contains("cannot include"));
+
+        let path = dir.path().join("a.md");
+        let mut rmd = Rmd::with_path(fs::read_to_string(&path).unwrap(), path.display().to_string());
+        let commands = rmd.parse();
+        assert_eq!(1, commands.len());
+        assert_eq!(1, rmd.errors().len());
+        assert!(rmd.errors()[0].contains("include cycle:"));
+    }
+}
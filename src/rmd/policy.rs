@@ -0,0 +1,109 @@
+use std::io::{Error, ErrorKind, Result};
+use std::time::Duration;
+
+use crate::rmd::command::Script;
+use crate::rmd::limits::parse_duration;
+
+// How a block's result is judged, from `retries`, `backoff`, `allow-failure`, `expect-exit` and
+// `on-failure` attributes. Front matter sets them for every block like any other attribute.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Policy {
+    pub retries: u32,
+    // the wait before the first retry, doubled after each further attempt
+    pub backoff: Duration,
+    pub allow_failure: bool,
+    pub expect_exit: i32,
+    // the id of the block to run when this one fails for good
+    pub on_failure: Option<String>,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self {
+            retries: 0,
+            backoff: Duration::from_secs(1),
+            allow_failure: false,
+            expect_exit: 0,
+            on_failure: None,
+        }
+    }
+}
+
+impl Policy {
+    pub fn from_script(script: &Script) -> Result<Policy> {
+        let mut policy = Policy::default();
+        if let Some(value) = script.attr("retries") {
+            policy.retries = value.parse().map_err(|_| invalid("retries", value))?;
+        }
+        if let Some(value) = script.attr("backoff") {
+            policy.backoff = parse_duration(value)?;
+        }
+        if let Some(value) = script.attr("allow-failure") {
+            policy.allow_failure = value.parse().map_err(|_| invalid("allow-failure", value))?;
+        }
+        if let Some(value) = script.attr("expect-exit") {
+            policy.expect_exit = value.parse().map_err(|_| invalid("expect-exit", value))?;
+        }
+        policy.on_failure = script.attr("on-failure").map(String::from);
+
+        Ok(policy)
+    }
+
+    pub fn delay_before(&self, retry: u32) -> Duration {
+        self.backoff * 2u32.saturating_pow(retry.saturating_sub(1))
+    }
+
+    // `retries=3 backoff=2s expect-exit=2`, only naming what differs from the default
+    pub fn describe(&self) -> String {
+        let default = Policy::default();
+        let mut parts = vec![];
+        if self.retries != default.retries {
+            parts.push(format!("retries={} backoff={:?}", self.retries, self.backoff));
+        }
+        if self.expect_exit != default.expect_exit {
+            parts.push(format!("expect-exit={}", self.expect_exit));
+        }
+        if self.allow_failure {
+            parts.push(String::from("allow-failure"));
+        }
+        if let Some(handler) = &self.on_failure {
+            parts.push(format!("on-failure={}", handler));
+        }
+
+        if parts.is_empty() {
+            String::from("default")
+        } else {
+            parts.join(" ")
+        }
+    }
+}
+
+fn invalid(key: &str, value: &str) -> Error {
+    Error::new(ErrorKind::InvalidInput, format!("invalid {}=`{}`", key, value))
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use crate::rmd::command::Script;
+    use crate::rmd::policy::Policy;
+
+    #[test]
+    fn should_read_policy_from_attributes() {
+        let mut script = Script::new();
+        script.attrs.insert(String::from("retries"), String::from("3"));
+        script.attrs.insert(String::from("backoff"), String::from("2s"));
+        script.attrs.insert(String::from("on-failure"), String::from("cleanup"));
+        let policy = Policy::from_script(&script).unwrap();
+
+        assert_eq!(3, policy.retries);
+        assert_eq!(Duration::from_secs(2), policy.delay_before(1));
+        assert_eq!(Duration::from_secs(8), policy.delay_before(3));
+        assert_eq!("retries=3 backoff=2s on-failure=cleanup", policy.describe());
+        assert_eq!("default", Policy::default().describe());
+
+        script.attrs.insert(String::from("allow-failure"), String::from("maybe"));
+        assert!(Policy::from_script(&script).is_err());
+    }
+}
@@ -0,0 +1,174 @@
+// the characters rST underlines and overlines titles with
+const ADORNMENTS: &str = "=-`:'\"~^_*+#<>.";
+
+const CODE_DIRECTIVES: [&str; 3] = [".. code-block::", ".. code::", ".. sourcecode::"];
+
+// Reads a reStructuredText document as markdown, line for line so blocks keep their line
+// numbers. Title styles become heading levels in the order they first appear, and a
+// `.. code-block:: sh` directive a fenced block, its `:name:` option the block's `id=`.
+pub fn to_markdown(text: &str) -> String {
+    let lines: Vec<&str> = text.lines().collect();
+    let mut out: Vec<String> = lines.iter().map(|line| line.to_string()).collect();
+    let mut styles: Vec<(char, bool)> = vec![];
+    let mut index = 0;
+    while index < lines.len() {
+        let line = lines[index];
+        if CODE_DIRECTIVES.iter().any(|directive| line.trim_start().starts_with(directive)) {
+            index = code_block(&lines, index, &mut out);
+            continue;
+        }
+
+        if let Some(style) = lines.get(index + 1).and_then(|next| adornment(next, line)) {
+            let overline = index > 0 && adornment(lines[index - 1], line) == Some(style);
+            let level = match styles.iter().position(|known| *known == (style, overline)) {
+                Some(level) => level + 1,
+                None => {
+                    styles.push((style, overline));
+                    styles.len()
+                }
+            };
+            out[index] = format!("{} {}", "#".repeat(level.min(6)), line.trim());
+            out[index + 1] = String::new();
+            if overline {
+                out[index - 1] = String::new();
+            }
+            index += 2;
+            continue;
+        }
+        // comments and the directives rinput has no use for
+        if line.trim_start().starts_with("..") {
+            out[index] = String::new();
+        }
+        index += 1;
+    }
+    out.join("\n") + "\n"
+}
+
+// the character `line` underlines `title` with, when it does
+fn adornment(line: &str, title: &str) -> Option<char> {
+    let line = line.trim_end();
+    let title = title.trim();
+    let c = line.chars().next()?;
+    let is_title = !title.is_empty() && !title.chars().all(|t| ADORNMENTS.contains(t)) && !title.starts_with("..");
+    let underlines = ADORNMENTS.contains(c) && line.chars().all(|l| l == c) && line.chars().count() >= title.chars().count();
+    Some(c).filter(|_| is_title && underlines)
+}
+
+fn indent(line: &str) -> usize {
+    line.len() - line.trim_start().len()
+}
+
+// Turns the directive at `start` into a fence: the opening one on the blank line before the
+// code, the closing one on the first blank line after it. Returns the line after the block.
+fn code_block(lines: &[&str], start: usize, out: &mut [String]) -> usize {
+    let base = indent(lines[start]);
+    let directive = lines[start].trim();
+    let lang = directive[directive.find("::").unwrap() + 2..].trim();
+    let mut info = vec![lang.to_string()];
+    out[start] = String::new();
+
+    let mut index = start + 1;
+    while index < lines.len() && indent(lines[index]) > base && lines[index].trim_start().starts_with(':') {
+        let option = lines[index].trim()[1..].splitn(2, ':').collect::<Vec<&str>>();
+        let key = if option[0] == "name" { "id" } else { option[0] };
+        let value = option.get(1).map(|value| value.trim()).filter(|value| !value.is_empty()).unwrap_or("true");
+        info.push(format!("{}={}", key, value));
+        out[index] = String::new();
+        index += 1;
+    }
+
+    let mut content_indent = None;
+    let mut first = None;
+    let mut last = None;
+    while index < lines.len() {
+        let line = lines[index];
+        if line.trim().is_empty() {
+            out[index] = String::new();
+        } else if indent(line) <= base {
+            break;
+        } else {
+            let dedent = *content_indent.get_or_insert(indent(line));
+            out[index] = format!("{}{}", " ".repeat(base), &line[dedent.min(indent(line))..]);
+            first = first.or(Some(index));
+            last = Some(index);
+        }
+        index += 1;
+    }
+
+    let (first, last) = match (first, last) {
+        (Some(first), Some(last)) => (first, last),
+        _ => return index,
+    };
+    // longer than any run of backticks in the code, so none of them closes it
+    let body = out[first..=last].join("\n");
+    let mut fence = " ".repeat(base) + "```";
+    while body.contains(fence.trim_start()) {
+        fence.push('`');
+    }
+    let opening = format!("{}{}", fence, info.join(" ").trim_end());
+    // only blank lines and options, blanked above, are left between the directive and the code
+    out[if first - 1 > start { first - 1 } else { start }] = opening;
+    match (last + 1..index).next() {
+        Some(blank) => out[blank] = fence,
+        None => out[last].push_str(&format!("\n{}", fence)),
+    }
+    index
+}
+
+#[cfg(test)]
+mod test {
+    use crate::rmd::rst::to_markdown;
+    use crate::rmd::Rmd;
+
+    const DOC: &str = "===
+Ops
+===
+
+.. A comment.
+
+deploy (env)
+------------
+
+Deploys the current build.
+
+.. code-block:: sh
+   :name: build
+   :cwd: build
+
+   echo deploying to $env
+
+   echo done
+
+status
+------
+
+.. code:: python
+
+    print('ok')";
+
+    #[test]
+    fn should_read_code_blocks_in_place() {
+        let commands = Rmd::new(to_markdown(DOC)).parse();
+
+        assert_eq!(2, commands.len());
+        assert_eq!(vec!["Ops", "deploy"], commands[0].path);
+        assert_eq!("env", commands[0].required_args[0].name);
+        assert_eq!("Deploys the current build.", commands[0].desc);
+        assert_eq!(16, commands[0].script.line);
+        assert_eq!("echo deploying to $env\n\necho done\n", commands[0].script.source);
+        assert_eq!(Some("build"), commands[0].script.attr("id"));
+        assert_eq!(Some("build"), commands[0].script.attr("cwd"));
+        assert_eq!(vec!["Ops", "status"], commands[1].path);
+        assert_eq!(25, commands[1].script.line);
+        assert_eq!("print('ok')\n", commands[1].script.source);
+    }
+
+    #[test]
+    fn should_fence_blocks_holding_backticks() {
+        let doc = ".. code-block:: sh\n\n   cat <<'EOF'\n   ```\n   EOF\n";
+        let commands = Rmd::new(to_markdown(doc)).parse();
+
+        assert_eq!(1, commands.len());
+        assert_eq!("cat <<'EOF'\n```\nEOF\n", commands[0].script.source);
+    }
+}
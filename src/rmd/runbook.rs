@@ -0,0 +1,49 @@
+use std::path::{Path, PathBuf};
+
+// the documents `rinput` picks up without --file, in order of preference
+pub const RUNBOOK_NAMES: [&str; 2] = ["rinput.md", "maskfile.md"];
+// only taken from the directory rinput runs in, and only when no runbook is found
+const FALLBACK_NAME: &str = "README.md";
+
+// Like make with its Makefile: the closest directory from `start` upwards holding one of `names`
+// wins, so each package of a monorepo can keep a runbook of its own.
+pub fn discover(start: &Path, names: &[&str]) -> Option<PathBuf> {
+    start.ancestors()
+        .find_map(|dir| names.iter().map(|name| dir.join(name)).find(|path| path.is_file()))
+        .or_else(|| Some(start.join(FALLBACK_NAME)).filter(|path| path.is_file()))
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use tempfile::tempdir;
+
+    use crate::rmd::runbook::{discover, RUNBOOK_NAMES};
+
+    #[test]
+    fn should_find_the_closest_runbook() {
+        let dir = tempdir().unwrap();
+        let package = dir.path().join("packages/api");
+        let src = package.join("src");
+        fs::create_dir_all(&src).unwrap();
+        fs::write(dir.path().join("rinput.md"), "").unwrap();
+
+        assert_eq!(Some(dir.path().join("rinput.md")), discover(&src, &RUNBOOK_NAMES));
+
+        fs::write(package.join("maskfile.md"), "").unwrap();
+        assert_eq!(Some(package.join("maskfile.md")), discover(&src, &RUNBOOK_NAMES));
+        assert_eq!(Some(dir.path().join("rinput.md")), discover(dir.path(), &RUNBOOK_NAMES));
+    }
+
+    #[test]
+    fn should_fall_back_to_readme_in_place() {
+        let dir = tempdir().unwrap();
+        let nested = dir.path().join("docs");
+        fs::create_dir_all(&nested).unwrap();
+        fs::write(dir.path().join("README.md"), "").unwrap();
+
+        assert_eq!(Some(dir.path().join("README.md")), discover(dir.path(), &RUNBOOK_NAMES));
+        assert_eq!(None, discover(&nested, &RUNBOOK_NAMES));
+    }
+}
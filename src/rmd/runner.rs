@@ -0,0 +1,396 @@
+use std::collections::HashSet;
+use std::io::{Error, ErrorKind, Result};
+use std::os::unix::process::ExitStatusExt;
+use std::process::ExitStatus;
+use std::thread;
+use std::time::Instant;
+
+use colored::*;
+
+use crate::rmd::command::Command;
+use crate::rmd::executor::{execute_command, RunContext};
+use crate::rmd::json::Json;
+use crate::rmd::log;
+use crate::rmd::policy::Policy;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    Passed,
+    Failed { reason: String, exit_code: i32 },
+    // failed, but `allow-failure` let the run carry on
+    Allowed { reason: String },
+    // not run because an earlier block failed
+    Skipped,
+    // not run because the block says so, with `skip` or an `only-on` for another system
+    Excluded { reason: String },
+    // not run again; what it left from an earlier run is still in the context
+    Unchanged,
+}
+
+impl Outcome {
+    // whether a later run has to run the block again
+    pub fn is_settled(&self) -> bool {
+        matches!(self, Outcome::Passed | Outcome::Allowed { .. } | Outcome::Excluded { .. } | Outcome::Unchanged)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Outcome::Passed => "passed",
+            Outcome::Failed { .. } => "failed",
+            Outcome::Allowed { .. } => "allowed",
+            Outcome::Skipped => "skipped",
+            Outcome::Excluded { .. } => "excluded",
+            Outcome::Unchanged => "unchanged",
+        }
+    }
+
+    fn reason(&self) -> Option<&str> {
+        match self {
+            Outcome::Failed { reason, .. } | Outcome::Allowed { reason } | Outcome::Excluded { reason } => Some(reason),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BlockReport {
+    // the block's position among the document's commands
+    pub index: usize,
+    pub label: String,
+    pub policy: String,
+    pub attempts: u32,
+    pub outcome: Outcome,
+}
+
+#[derive(Debug, Default)]
+pub struct RunReport {
+    pub blocks: Vec<BlockReport>,
+    // the status of the first block that failed for good, 0 when none did
+    pub exit_code: i32,
+}
+
+impl RunReport {
+    pub fn print_summary(&self) {
+        if log::is_json() {
+            return self.emit_summary();
+        }
+        eprintln!("{}", "summary:".bold());
+        for block in &self.blocks {
+            // padded before colouring, the escape codes would count towards the width otherwise
+            let (status, detail) = match &block.outcome {
+                Outcome::Passed => (format!("{:<8}", "ok").green(), String::new()),
+                Outcome::Failed { reason, .. } => (format!("{:<8}", "failed").red().bold(), format!("{}, ", reason)),
+                Outcome::Allowed { reason } => (format!("{:<8}", "allowed").yellow(), format!("{}, ", reason)),
+                Outcome::Skipped => (format!("{:<8}", "skipped").dimmed(), String::new()),
+                Outcome::Excluded { reason } => (format!("{:<8}", "skipped").dimmed(), format!("{}, ", reason)),
+                Outcome::Unchanged => (format!("{:<8}", "kept").dimmed(), String::new()),
+            };
+            let attempts = if block.attempts > 1 { format!(", {} attempts", block.attempts) } else { String::new() };
+            eprintln!("  {} {:<32} {}{}", status, block.label, detail, format!("{}{}", block.policy, attempts).dimmed());
+        }
+    }
+
+    // one line of counts, plus a line per block that failed for good
+    pub fn print_compact(&self) {
+        if log::is_json() {
+            return self.emit_summary();
+        }
+        let count = |matches: fn(&Outcome) -> bool| self.blocks.iter().filter(|block| matches(&block.outcome)).count();
+        let passed = count(|outcome| matches!(outcome, Outcome::Passed | Outcome::Allowed { .. }));
+        let failed = count(|outcome| matches!(outcome, Outcome::Failed { .. }));
+        let skipped = count(|outcome| matches!(outcome, Outcome::Skipped | Outcome::Excluded { .. }));
+        let unchanged = count(|outcome| matches!(outcome, Outcome::Unchanged));
+
+        let failed = format!("{} failed", failed);
+        let failed = if self.exit_code == 0 { failed.normal() } else { failed.red().bold() };
+        eprintln!("{}, {}, {} skipped, {} unchanged", format!("{} passed", passed).green(), failed, skipped, unchanged);
+        for block in &self.blocks {
+            if let Outcome::Failed { reason, .. } = &block.outcome {
+                eprintln!("  {} {}: {}", "failed".red().bold(), block.label, reason);
+            }
+        }
+    }
+
+    // an event per block, then one with the counts
+    fn emit_summary(&self) {
+        let text = |value: &str| Json::String(value.to_string());
+        for block in &self.blocks {
+            let mut fields = vec![
+                (String::from("block"), text(&block.label)),
+                (String::from("outcome"), text(block.outcome.name())),
+                (String::from("policy"), text(&block.policy)),
+                (String::from("attempts"), Json::Number(f64::from(block.attempts))),
+            ];
+            if let Some(reason) = block.outcome.reason() {
+                fields.push((String::from("reason"), text(reason)));
+            }
+            if let Outcome::Failed { exit_code, .. } = &block.outcome {
+                fields.push((String::from("exit_code"), Json::Number(f64::from(*exit_code))));
+            }
+            log::emit("normal", "summary", &format!("{} {}", block.label, block.outcome.name()), fields);
+        }
+
+        let mut counts = vec![];
+        for name in ["passed", "failed", "allowed", "skipped", "excluded", "unchanged"] {
+            let count = self.blocks.iter().filter(|block| block.outcome.name() == name).count();
+            counts.push((String::from(name), Json::Number(count as f64)));
+        }
+        counts.push((String::from("exit_code"), Json::Number(f64::from(self.exit_code))));
+        log::emit("normal", "summary", "run finished", counts);
+    }
+}
+
+// Runs the blocks in document order. A block that fails for good runs its `on-failure` block and
+// stops the run; blocks named by an `on-failure` only ever run that way.
+pub fn run_commands(commands: Vec<Command>, ctx: &mut RunContext) -> Result<RunReport> {
+    run_commands_from(commands, 0, ctx)
+}
+
+// The same, but the blocks before `first` are taken as done: `ctx` still holds what they left.
+pub fn run_commands_from(commands: Vec<Command>, first: usize, ctx: &mut RunContext) -> Result<RunReport> {
+    let mut policies = vec![];
+    let mut exclusions = vec![];
+    for cmd in &commands {
+        exclusions.push(excluded(cmd)?);
+        let policy = Policy::from_script(&cmd.script)?;
+        if let Some(handler) = &policy.on_failure {
+            if find_block(&commands, handler).is_none() {
+                let msg = format!("{}: on-failure={} does not name a block with id={}", label(cmd), handler, handler);
+                return Err(Error::new(ErrorKind::InvalidInput, msg));
+            }
+        }
+        policies.push(policy);
+    }
+    let handlers: HashSet<&str> = policies.iter().filter_map(|policy| policy.on_failure.as_deref()).collect();
+    let is_handler = |cmd: &Command| cmd.script.attr("id").is_some_and(|id| handlers.contains(id));
+    let planned = (first..commands.len()).filter(|index| exclusions[*index].is_none() && !is_handler(&commands[*index])).count();
+    log::info("plan", &format!("{} of {} block(s) to run, {} failure handler(s)", planned, commands.len(), handlers.len()));
+    for (cmd, (policy, excluded)) in commands.iter().zip(policies.iter().zip(&exclusions)) {
+        let excluded = excluded.as_ref().map(|reason| format!(", not run: {}", reason)).unwrap_or_default();
+        log::debug("plan", &format!("{} in {}, {}{}", label(cmd), cmd.script.file, policy.describe(), excluded));
+    }
+
+    let mut report = RunReport::default();
+    for (index, (cmd, policy)) in commands.iter().zip(&policies).enumerate() {
+        if is_handler(cmd) {
+            continue;
+        }
+        if index < first || report.exit_code != 0 {
+            let outcome = if index < first { Outcome::Unchanged } else { Outcome::Skipped };
+            report.blocks.push(BlockReport { index, label: label(cmd), policy: policy.describe(), attempts: 0, outcome });
+            continue;
+        }
+        if let Some(reason) = exclusions[index].clone() {
+            report.blocks.push(BlockReport { index, label: label(cmd), policy: policy.describe(), attempts: 0, outcome: Outcome::Excluded { reason } });
+            continue;
+        }
+
+        let block = run_block(index, cmd, policy, ctx);
+        if let Outcome::Failed { exit_code, .. } = block.outcome {
+            report.exit_code = exit_code;
+            report.blocks.push(block);
+            if let Some(handler) = policy.on_failure.as_deref().and_then(|handler| find_block(&commands, handler)) {
+                let mut handled = run_block(handler, &commands[handler], &Policy::default(), ctx);
+                handled.policy = format!("on-failure of {}", label(cmd));
+                report.blocks.push(handled);
+            }
+            continue;
+        }
+        report.blocks.push(block);
+    }
+
+    Ok(report)
+}
+
+fn run_block(index: usize, cmd: &Command, policy: &Policy, ctx: &mut RunContext) -> BlockReport {
+    log::debug("run", &format!("starting {}", label(cmd)));
+    let started = Instant::now();
+    let mut attempts = 0;
+    let outcome = loop {
+        attempts += 1;
+        let (reason, exit_code) = match execute_command(cmd.clone(), ctx) {
+            Ok(output) if output.status.code() == Some(policy.expect_exit) => break Outcome::Passed,
+            Ok(output) => (describe_status(output.status, policy.expect_exit), exit_code_of(output.status)),
+            // setup errors such as a bad attribute or a missing toolchain would fail the same way again
+            Err(err) => {
+                log::error("run", &err.to_string());
+                break failure(policy, err.to_string(), 1);
+            }
+        };
+
+        if attempts > policy.retries {
+            break failure(policy, reason, exit_code);
+        }
+        let delay = policy.delay_before(attempts);
+        let retry = format!("{} {}, retrying in {:?} ({}/{})", label(cmd), reason, delay, attempts, policy.retries);
+        log::status("run", &format!("retry: {}", retry), format!("{} {}", "retry:".yellow(), retry));
+        thread::sleep(delay);
+    };
+
+    let status = match outcome.reason() {
+        Some(reason) => format!("{}, {}", outcome.name(), reason),
+        None => outcome.name().to_string(),
+    };
+    log::timed("run", &format!("{} {}", label(cmd), status), started.elapsed());
+    BlockReport { index, label: label(cmd), policy: policy.describe(), attempts, outcome }
+}
+
+// why the block's `skip` or `only-on=linux,macos` keeps it from running here, if they do
+fn excluded(cmd: &Command) -> Result<Option<String>> {
+    match cmd.script.attr("skip") {
+        Some("true") => return Ok(Some(String::from("skip"))),
+        Some("false") | None => (),
+        Some(value) => {
+            let msg = format!("{}: skip={} is not true or false", label(cmd), value);
+            return Err(Error::new(ErrorKind::InvalidInput, msg));
+        }
+    }
+    let systems = match cmd.script.attr("only-on") {
+        Some(systems) => systems,
+        None => return Ok(None),
+    };
+    let os = std::env::consts::OS;
+    let matches = |system: &str| system == os || system == std::env::consts::FAMILY || (system == "darwin" && os == "macos");
+    if systems.split(',').map(str::trim).any(matches) {
+        Ok(None)
+    } else {
+        Ok(Some(format!("only on {}", systems)))
+    }
+}
+
+fn failure(policy: &Policy, reason: String, exit_code: i32) -> Outcome {
+    if policy.allow_failure {
+        Outcome::Allowed { reason }
+    } else {
+        Outcome::Failed { reason, exit_code }
+    }
+}
+
+fn find_block(commands: &[Command], id: &str) -> Option<usize> {
+    commands.iter().position(|cmd| cmd.script.attr("id") == Some(id))
+}
+
+pub fn label(cmd: &Command) -> String {
+    match cmd.script.attr("id") {
+        Some(id) => id.to_string(),
+        None => format!("{} block at line {}", cmd.script.executor, cmd.script.line),
+    }
+}
+
+fn describe_status(status: ExitStatus, expected: i32) -> String {
+    match (status.code(), status.signal()) {
+        (Some(code), _) if expected == 0 => format!("exited with {}", code),
+        (Some(code), _) => format!("exited with {}, expected {}", code, expected),
+        (None, Some(signal)) => format!("killed by {} ({})", signal_name(signal), signal),
+        (None, None) => String::from("stopped without a status"),
+    }
+}
+
+// what the run exits with when this block fails: its own code, or a shell's 128 + signal
+fn exit_code_of(status: ExitStatus) -> i32 {
+    match (status.code(), status.signal()) {
+        (Some(0), _) => 1,
+        (Some(code), _) => code,
+        (None, signal) => 128 + signal.unwrap_or(0),
+    }
+}
+
+fn signal_name(signal: i32) -> &'static str {
+    match signal {
+        libc::SIGHUP => "SIGHUP",
+        libc::SIGINT => "SIGINT",
+        libc::SIGABRT => "SIGABRT",
+        libc::SIGKILL => "SIGKILL",
+        libc::SIGSEGV => "SIGSEGV",
+        libc::SIGPIPE => "SIGPIPE",
+        libc::SIGTERM => "SIGTERM",
+        libc::SIGXCPU => "SIGXCPU",
+        libc::SIGXFSZ => "SIGXFSZ",
+        _ => "a signal",
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tempfile::tempdir;
+
+    use crate::rmd::command::Command;
+    use crate::rmd::executor::RunContext;
+    use crate::rmd::runner::{Outcome, run_commands};
+
+    fn block(attrs: &[(&str, &str)], source: &str) -> Command {
+        let mut cmd = Command::new(1);
+        cmd.script.executor = String::from("sh");
+        cmd.script.source = String::from(source);
+        for (key, value) in attrs {
+            cmd.script.attrs.insert(key.to_string(), value.to_string());
+        }
+        cmd
+    }
+
+    #[test]
+    fn should_retry_until_block_passes() {
+        let dir = tempdir().unwrap();
+        let counter = dir.path().join("attempts");
+        let source = format!("n=$(cat {0} 2>/dev/null || echo 0); n=$((n+1)); echo $n > {0}; test $n -ge 3", counter.display());
+        let report = run_commands(vec![block(&[("retries", "3"), ("backoff", "0s")], &source)], &mut RunContext::default()).unwrap();
+
+        assert_eq!(Outcome::Passed, report.blocks[0].outcome);
+        assert_eq!(3, report.blocks[0].attempts);
+        assert_eq!(0, report.exit_code);
+    }
+
+    #[test]
+    fn should_honour_expected_exit_and_allowed_failures() {
+        let commands = vec![
+            block(&[("expect-exit", "2")], "exit 2"),
+            block(&[("allow-failure", "true")], "exit 1"),
+            block(&[], "true"),
+        ];
+        let report = run_commands(commands, &mut RunContext::default()).unwrap();
+
+        assert_eq!(Outcome::Passed, report.blocks[0].outcome);
+        assert_eq!(Outcome::Allowed { reason: String::from("exited with 1") }, report.blocks[1].outcome);
+        assert_eq!(Outcome::Passed, report.blocks[2].outcome);
+        assert_eq!(0, report.exit_code);
+    }
+
+    #[test]
+    fn should_leave_out_excluded_blocks() {
+        let mut env_block = block(&[("only-on", "unix,windows")], "test \"$GREETING\" = hello");
+        env_block.script.env.push((String::from("GREETING"), String::from("hello")));
+        let commands = vec![
+            block(&[("skip", "true")], "exit 1"),
+            block(&[("only-on", "plan9")], "exit 1"),
+            env_block,
+        ];
+        let report = run_commands(commands, &mut RunContext::default()).unwrap();
+
+        assert_eq!(Outcome::Excluded { reason: String::from("skip") }, report.blocks[0].outcome);
+        assert_eq!(Outcome::Excluded { reason: String::from("only on plan9") }, report.blocks[1].outcome);
+        assert_eq!(Outcome::Passed, report.blocks[2].outcome);
+        assert!(run_commands(vec![block(&[("skip", "maybe")], "true")], &mut RunContext::default()).is_err());
+    }
+
+    #[test]
+    fn should_run_failure_handler_and_skip_the_rest() {
+        let commands = vec![
+            block(&[("on-failure", "cleanup")], "kill -9 $$"),
+            block(&[], "true"),
+            block(&[("id", "cleanup")], "true"),
+        ];
+        let report = run_commands(commands, &mut RunContext::default()).unwrap();
+
+        assert_eq!(3, report.blocks.len());
+        assert_eq!(Outcome::Failed { reason: String::from("killed by SIGKILL (9)"), exit_code: 137 }, report.blocks[0].outcome);
+        assert_eq!("cleanup", report.blocks[1].label);
+        assert_eq!(Outcome::Passed, report.blocks[1].outcome);
+        assert_eq!(Outcome::Skipped, report.blocks[2].outcome);
+        assert_eq!(137, report.exit_code);
+    }
+
+    #[test]
+    fn should_reject_unknown_failure_handler() {
+        assert!(run_commands(vec![block(&[("on-failure", "nope")], "true")], &mut RunContext::default()).is_err());
+    }
+}
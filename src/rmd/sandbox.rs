@@ -0,0 +1,242 @@
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::process;
+
+use tempfile::TempDir;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SandboxMode {
+    Off,
+    Strict,
+}
+
+impl SandboxMode {
+    pub fn from_attr(value: Option<&str>) -> Result<SandboxMode> {
+        match value {
+            None | Some("off") | Some("none") | Some("false") => Ok(SandboxMode::Off),
+            Some("strict") | Some("true") => Ok(SandboxMode::Strict),
+            Some(other) => {
+                let msg = format!("unknown sandbox mode `{}`, expected `strict` or `off`", other);
+                Err(Error::new(ErrorKind::InvalidInput, msg))
+            }
+        }
+    }
+}
+
+pub fn is_available() -> bool {
+    let max_namespaces = fs::read_to_string("/proc/sys/user/max_user_namespaces")
+        .ok()
+        .and_then(|value| value.trim().parse::<u64>().ok())
+        .unwrap_or(0);
+    let unprivileged = fs::read_to_string("/proc/sys/kernel/unprivileged_userns_clone")
+        .map(|value| value.trim() != "0")
+        .unwrap_or(true);
+
+    cfg!(target_os = "linux") && max_namespaces > 0 && unprivileged
+}
+
+// Runs `child` in fresh user, mount, IPC and network namespaces: every mount of the host is
+// remounted read-only, the network only has a downed loopback, and `TMPDIR` points at a private
+// writable directory. The returned directory has to outlive the child. Only the block's own
+// process is isolated; dependency installs and builds happen before it is spawned.
+//
+// There is no fallback: if the namespaces cannot be created, the child fails to spawn.
+pub fn isolate(child: &mut process::Command, mode: SandboxMode) -> Result<Option<TempDir>> {
+    if mode == SandboxMode::Off {
+        return Ok(None);
+    }
+    if !is_available() {
+        let msg = "sandbox=strict requested, but user namespaces are not available on this system";
+        return Err(Error::new(ErrorKind::Other, msg));
+    }
+
+    let scratch = tempfile::Builder::new().prefix("rinput-sandbox").tempdir()?;
+    child.env("TMPDIR", scratch.path());
+    linux::apply(child, scratch.path())?;
+
+    Ok(Some(scratch))
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::ffi::CString;
+    use std::fs;
+    use std::io::{Error, ErrorKind, Result};
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::process::CommandExt;
+    use std::path::Path;
+    use std::process;
+    use std::ptr;
+
+    use libc::c_ulong;
+
+    struct Mount {
+        target: CString,
+        flags: c_ulong,
+    }
+
+    pub fn apply(child: &mut process::Command, scratch: &Path) -> Result<()> {
+        // everything is allocated up front: between fork and exec only raw syscalls are safe
+        let uid_map = format!("{} {} 1", unsafe { libc::getuid() }, unsafe { libc::getuid() });
+        let gid_map = format!("{} {} 1", unsafe { libc::getgid() }, unsafe { libc::getgid() });
+        let setgroups = CString::new("/proc/self/setgroups").unwrap();
+        let uid_map_path = CString::new("/proc/self/uid_map").unwrap();
+        let gid_map_path = CString::new("/proc/self/gid_map").unwrap();
+        let root = CString::new("/").unwrap();
+        let scratch = CString::new(scratch.as_os_str().as_bytes())
+            .map_err(|_| Error::new(ErrorKind::InvalidInput, "sandbox directory contains a NUL byte"))?;
+        let mounts = read_mounts()?;
+
+        unsafe {
+            child.pre_exec(move || {
+                let namespaces = libc::CLONE_NEWUSER | libc::CLONE_NEWNS | libc::CLONE_NEWNET | libc::CLONE_NEWIPC;
+                check(libc::unshare(namespaces))?;
+
+                write_file(&setgroups, b"deny")?;
+                write_file(&uid_map_path, uid_map.as_bytes())?;
+                write_file(&gid_map_path, gid_map.as_bytes())?;
+
+                check(libc::mount(ptr::null(), root.as_ptr(), ptr::null(), libc::MS_REC | libc::MS_PRIVATE, ptr::null()))?;
+                // bound before the read-only pass, which only touches the mounts read beforehand
+                check(libc::mount(scratch.as_ptr(), scratch.as_ptr(), ptr::null(), libc::MS_BIND, ptr::null()))?;
+                for mount in &mounts {
+                    let flags = libc::MS_REMOUNT | libc::MS_BIND | libc::MS_RDONLY | mount.flags;
+                    check(libc::mount(ptr::null(), mount.target.as_ptr(), ptr::null(), flags, ptr::null()))?;
+                }
+                Ok(())
+            });
+        }
+
+        Ok(())
+    }
+
+    fn check(result: libc::c_int) -> Result<()> {
+        if result == -1 {
+            Err(Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+
+    unsafe fn write_file(path: &CString, content: &[u8]) -> Result<()> {
+        let fd = libc::open(path.as_ptr(), libc::O_WRONLY);
+        if fd == -1 {
+            return Err(Error::last_os_error());
+        }
+        let written = libc::write(fd, content.as_ptr() as *const libc::c_void, content.len());
+        libc::close(fd);
+        if written == -1 {
+            return Err(Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    // mounts inherited from the parent namespace keep their nosuid/nodev/noexec/atime flags
+    // locked, so a read-only remount has to repeat them
+    fn read_mounts() -> Result<Vec<Mount>> {
+        let mountinfo = fs::read_to_string("/proc/self/mountinfo")?;
+        let mut mounts = vec![];
+        for line in mountinfo.lines() {
+            let fields: Vec<&str> = line.split(' ').collect();
+            if fields.len() < 6 {
+                continue;
+            }
+
+            let mut flags = 0;
+            for option in fields[5].split(',') {
+                flags |= match option {
+                    "nosuid" => libc::MS_NOSUID,
+                    "nodev" => libc::MS_NODEV,
+                    "noexec" => libc::MS_NOEXEC,
+                    "noatime" => libc::MS_NOATIME,
+                    "nodiratime" => libc::MS_NODIRATIME,
+                    "relatime" => libc::MS_RELATIME,
+                    _ => 0,
+                };
+            }
+
+            let target = CString::new(unescape(fields[4]))
+                .map_err(|_| Error::new(ErrorKind::InvalidData, "mount point contains a NUL byte"))?;
+            mounts.push(Mount { target, flags });
+        }
+
+        Ok(mounts)
+    }
+
+    // mountinfo escapes spaces, tabs, newlines and backslashes as `\ooo`
+    fn unescape(path: &str) -> Vec<u8> {
+        let bytes = path.as_bytes();
+        let mut out = vec![];
+        let mut index = 0;
+        while index < bytes.len() {
+            let escaped = bytes.get(index + 1..index + 4)
+                .filter(|_| bytes[index] == b'\\')
+                .and_then(|octal| u8::from_str_radix(std::str::from_utf8(octal).ok()?, 8).ok());
+            match escaped {
+                Some(value) => {
+                    out.push(value);
+                    index += 4;
+                }
+                None => {
+                    out.push(bytes[index]);
+                    index += 1;
+                }
+            }
+        }
+        out
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod linux {
+    use std::io::{Error, ErrorKind, Result};
+    use std::path::Path;
+    use std::process;
+
+    pub fn apply(_child: &mut process::Command, _scratch: &Path) -> Result<()> {
+        Err(Error::new(ErrorKind::Other, "sandbox=strict is only supported on Linux"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::process;
+
+    use crate::rmd::sandbox::{is_available, isolate, SandboxMode};
+
+    fn run_isolated(script: &str) -> process::Output {
+        let mut child = process::Command::new("sh");
+        child.arg("-c").arg(script);
+        let _scratch = isolate(&mut child, SandboxMode::Strict).unwrap();
+        child.output().unwrap()
+    }
+
+    #[test]
+    fn should_parse_sandbox_mode() {
+        assert_eq!(SandboxMode::Off, SandboxMode::from_attr(None).unwrap());
+        assert_eq!(SandboxMode::Strict, SandboxMode::from_attr(Some("strict")).unwrap());
+        assert!(SandboxMode::from_attr(Some("loose")).is_err());
+    }
+
+    #[test]
+    fn should_make_host_read_only() {
+        if !is_available() {
+            return;
+        }
+
+        let output = run_isolated("touch /rinput-sandbox-probe");
+        assert!(!output.status.success());
+        assert!(String::from_utf8_lossy(&output.stderr).contains("Read-only file system"));
+    }
+
+    #[test]
+    fn should_allow_private_tmp_and_block_network() {
+        if !is_available() {
+            return;
+        }
+
+        let output = run_isolated("echo ok > \"$TMPDIR/probe\" && cat \"$TMPDIR/probe\" && sed -n '3,$p' /proc/net/dev | cut -d: -f1 | tr -d ' '");
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        assert_eq!("ok\nlo\n", String::from_utf8_lossy(&output.stdout));
+    }
+}
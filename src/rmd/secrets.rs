@@ -0,0 +1,198 @@
+use std::collections::HashMap;
+use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, Error, ErrorKind, Result, Write};
+use std::os::unix::io::AsRawFd;
+use std::sync::Mutex;
+
+use crate::rmd::command::{Command, Script};
+
+// what secrets show as in output
+const MASK: &str = "******";
+
+// the terminal and its settings from before a prompt turned echo off, for an interrupt to put back
+static ECHOING: Mutex<Option<(libc::c_int, libc::termios)>> = Mutex::new(None);
+
+extern "C" fn on_interrupt(signal: libc::c_int) {
+    if let Ok(echoing) = ECHOING.try_lock() {
+        if let Some((fd, termios)) = echoing.as_ref() {
+            unsafe {
+                libc::tcsetattr(*fd, libc::TCSANOW, termios);
+            }
+        }
+    }
+    // then die of the interrupt as we would have without the handler
+    unsafe {
+        libc::signal(signal, libc::SIG_DFL);
+        libc::raise(signal);
+    }
+}
+
+// The secrets a block declares, with `secrets: [API_TOKEN, DB_PASSWORD]` in the front matter or
+// `secrets=API_TOKEN,DB_PASSWORD` on the block.
+pub fn declared(script: &Script) -> Vec<String> {
+    script.attr("secrets").unwrap_or("")
+        .trim().trim_start_matches('[').trim_end_matches(']')
+        .split(',')
+        .map(|name| name.trim().trim_matches('"').trim_matches('\'').to_string())
+        .filter(|name| !name.is_empty())
+        .collect()
+}
+
+// The value of every secret the blocks declare: from `known`, then the environment, then asked
+// for on the terminal without echoing it.
+pub fn resolve(commands: &[Command], known: &HashMap<String, String>) -> Result<HashMap<String, String>> {
+    let mut secrets = HashMap::new();
+    for name in commands.iter().flat_map(|cmd| declared(&cmd.script)) {
+        if secrets.contains_key(&name) {
+            continue;
+        }
+        let value = match known.get(&name).cloned().or_else(|| std::env::var(&name).ok()) {
+            Some(value) => value,
+            None => prompt(&name)?,
+        };
+        secrets.insert(name, value);
+    }
+    Ok(secrets)
+}
+
+fn prompt(name: &str) -> Result<String> {
+    let mut tty = OpenOptions::new().read(true).write(true).open("/dev/tty")
+        .map_err(|_| Error::new(ErrorKind::NotFound, format!("secret {} is not set and there is no terminal to ask for it", name)))?;
+    let fd = tty.as_raw_fd();
+    let mut termios: libc::termios = unsafe { std::mem::zeroed() };
+    if unsafe { libc::tcgetattr(fd, &mut termios) } != 0 {
+        return Err(Error::last_os_error());
+    }
+    write!(tty, "{}: ", name)?;
+    tty.flush()?;
+
+    let echoing = termios;
+    termios.c_lflag &= !libc::ECHO;
+    *ECHOING.lock().unwrap() = Some((fd, echoing));
+    let previous = unsafe {
+        let previous = libc::signal(libc::SIGINT, on_interrupt as extern "C" fn(libc::c_int) as libc::sighandler_t);
+        libc::tcsetattr(fd, libc::TCSANOW, &termios);
+        previous
+    };
+    let mut value = String::new();
+    let read = BufReader::new(&tty).read_line(&mut value);
+    unsafe {
+        libc::tcsetattr(fd, libc::TCSANOW, &echoing);
+        libc::signal(libc::SIGINT, previous);
+    }
+    *ECHOING.lock().unwrap() = None;
+    writeln!(tty)?;
+    read?;
+    Ok(value.trim_end_matches(&['\r', '\n'][..]).to_string())
+}
+
+// `text` with every secret value in it replaced by the mask
+pub fn mask(text: &str, values: &[String]) -> String {
+    let mut masked = text.to_string();
+    for value in values.iter().filter(|value| !value.is_empty()) {
+        masked = masked.replace(value.as_str(), MASK);
+    }
+    masked
+}
+
+// Masks what passes through it on the way to `inner`. The end of a write that could be the start
+// of a secret is held back until the next one shows whether it is.
+pub struct MaskingWriter<W: Write> {
+    inner: W,
+    values: Vec<String>,
+    pending: Vec<u8>,
+}
+
+impl<W: Write> MaskingWriter<W> {
+    pub fn new(inner: W, values: &[String]) -> Self {
+        // the longest first, so a secret holding another is masked whole
+        let mut values: Vec<String> = values.iter().filter(|value| !value.is_empty()).cloned().collect();
+        values.sort_by_key(|value| std::cmp::Reverse(value.len()));
+        MaskingWriter { inner, values, pending: vec![] }
+    }
+
+    fn held_back(&self) -> usize {
+        (1..self.pending.len() + 1).rev()
+            .find(|len| {
+                let tail = &self.pending[self.pending.len() - len..];
+                self.values.iter().any(|value| value.len() > *len && value.as_bytes().starts_with(tail))
+            })
+            .unwrap_or(0)
+    }
+
+    fn write_through(&mut self, keep: usize) -> Result<()> {
+        let ready: Vec<u8> = self.pending.drain(..self.pending.len() - keep).collect();
+        let mut masked = ready;
+        for value in &self.values {
+            masked = replace(&masked, value.as_bytes(), MASK.as_bytes());
+        }
+        self.inner.write_all(&masked)
+    }
+}
+
+impl<W: Write> Write for MaskingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.pending.extend_from_slice(buf);
+        let keep = self.held_back();
+        self.write_through(keep)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Drop for MaskingWriter<W> {
+    fn drop(&mut self) {
+        let _ = self.write_through(0);
+        let _ = self.inner.flush();
+    }
+}
+
+fn replace(haystack: &[u8], needle: &[u8], with: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(haystack.len());
+    let mut index = 0;
+    while index < haystack.len() {
+        if haystack[index..].starts_with(needle) {
+            out.extend_from_slice(with);
+            index += needle.len();
+        } else {
+            out.push(haystack[index]);
+            index += 1;
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+
+    use crate::rmd::command::Script;
+    use crate::rmd::secrets::{declared, mask, MaskingWriter};
+
+    #[test]
+    fn should_read_declared_secrets() {
+        let mut script = Script::new();
+        script.attrs.insert(String::from("secrets"), String::from("[API_TOKEN, \"DB_PASSWORD\"]"));
+        assert_eq!(vec!["API_TOKEN", "DB_PASSWORD"], declared(&script));
+
+        script.attrs.insert(String::from("secrets"), String::from("API_TOKEN"));
+        assert_eq!(vec!["API_TOKEN"], declared(&script));
+    }
+
+    #[test]
+    fn should_mask_secrets_split_across_writes() {
+        let values = vec![String::from("s3cr3t"), String::new()];
+        let mut out = vec![];
+        {
+            let mut writer = MaskingWriter::new(&mut out, &values);
+            writer.write_all(b"token: s3").unwrap();
+            writer.write_all(b"cr3t\nnot s3").unwrap();
+            writer.write_all(b"cond\ns").unwrap();
+        }
+        assert_eq!("token: ******\nnot s3cond\ns", String::from_utf8(out).unwrap());
+        assert_eq!("a ****** b", mask("a s3cr3t b", &values));
+    }
+}
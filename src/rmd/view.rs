@@ -0,0 +1,285 @@
+use colored::*;
+use pulldown_cmark::{CodeBlockKind, Event, LinkType, Options, Parser, Tag};
+
+use crate::rmd::command::Command;
+use crate::rmd::outline::highlight_line;
+use crate::rmd::parser::{include_target, line_of, parse_info_string, split_front_matter};
+
+// the inline styles open at the current text, counted so nested ones close cleanly
+#[derive(Default)]
+struct Styles {
+    heading: u32,
+    bold: u32,
+    italic: u32,
+    strike: u32,
+    link: u32,
+}
+
+impl Styles {
+    fn apply(&self, text: &str) -> String {
+        let mut styled = match self.heading {
+            0 => text.normal(),
+            1 => text.magenta().bold().underline(),
+            2 => text.cyan().bold(),
+            _ => text.bold(),
+        };
+        if self.bold > 0 {
+            styled = styled.bold();
+        }
+        if self.italic > 0 {
+            styled = styled.italic();
+        }
+        if self.strike > 0 {
+            styled = styled.strikethrough();
+        }
+        if self.link > 0 {
+            styled = styled.blue().underline();
+        }
+        styled.to_string()
+    }
+}
+
+// A code block being collected: its language and, when `run` would execute it, its number.
+struct Block {
+    lang: String,
+    number: Option<usize>,
+    source: String,
+}
+
+// Renders the document at `file` for the terminal. Every block of it `commands` holds gets a `[n]`
+// marker in its gutter, numbered like `rinput run --block n` counts them.
+pub fn render(text: &str, file: &str, commands: &[Command]) -> String {
+    let (_, body_start) = split_front_matter(text);
+    let mut options = Options::empty();
+    options.insert(Options::ENABLE_STRIKETHROUGH);
+
+    let mut out = String::new();
+    let mut line = String::new();
+    let mut styles = Styles::default();
+    let mut lists: Vec<Option<u64>> = vec![];
+    let mut links: Vec<Option<String>> = vec![];
+    let mut quotes = 0;
+    let mut block: Option<Block> = None;
+
+    for (event, range) in Parser::new_ext(&text[body_start..], options).into_offset_iter() {
+        match event {
+            Event::Start(tag) => match tag {
+                Tag::Heading(level) => {
+                    flush(&mut out, &mut line, quotes);
+                    styles.heading = level;
+                }
+                Tag::BlockQuote => {
+                    flush(&mut out, &mut line, quotes);
+                    quotes += 1;
+                }
+                Tag::List(start) => {
+                    flush(&mut out, &mut line, quotes);
+                    lists.push(start);
+                }
+                Tag::Item => {
+                    flush(&mut out, &mut line, quotes);
+                    let bullet = match lists.last_mut() {
+                        Some(Some(number)) => {
+                            *number += 1;
+                            format!("{}.", *number - 1)
+                        }
+                        _ => String::from("•"),
+                    };
+                    line = format!("{}{} ", "  ".repeat(lists.len().saturating_sub(1)), bullet.cyan());
+                }
+                Tag::CodeBlock(kind) => {
+                    flush(&mut out, &mut line, quotes);
+                    let (lang, number) = match kind {
+                        CodeBlockKind::Fenced(info) => {
+                            // the parser records a block by the line after its opening fence
+                            let source_line = line_of(text, body_start + range.start) + 1;
+                            let number = commands.iter().position(|cmd| cmd.script.file == file && cmd.script.line == source_line);
+                            (parse_info_string(&info).0, number.map(|index| index + 1))
+                        }
+                        CodeBlockKind::Indented => (String::new(), None),
+                    };
+                    block = Some(Block { lang, number, source: String::new() });
+                }
+                Tag::Emphasis => styles.italic += 1,
+                Tag::Strong => styles.bold += 1,
+                Tag::Strikethrough => styles.strike += 1,
+                Tag::Link(kind, url, _) | Tag::Image(kind, url, _) => {
+                    styles.link += 1;
+                    // an autolink already reads as its address
+                    let shown = !matches!(kind, LinkType::Autolink | LinkType::Email);
+                    links.push(Some(url.to_string()).filter(|_| shown));
+                }
+                _ => (),
+            },
+            Event::End(tag) => match tag {
+                Tag::Heading(_) => {
+                    flush(&mut out, &mut line, quotes);
+                    blank(&mut out, quotes);
+                    styles.heading = 0;
+                }
+                Tag::Paragraph => {
+                    flush(&mut out, &mut line, quotes);
+                    if lists.is_empty() {
+                        blank(&mut out, quotes);
+                    }
+                }
+                Tag::BlockQuote => {
+                    flush(&mut out, &mut line, quotes);
+                    quotes -= 1;
+                }
+                Tag::List(_) => {
+                    flush(&mut out, &mut line, quotes);
+                    lists.pop();
+                    if lists.is_empty() {
+                        blank(&mut out, quotes);
+                    }
+                }
+                Tag::Item => flush(&mut out, &mut line, quotes),
+                Tag::CodeBlock(_) => {
+                    if let Some(block) = block.take() {
+                        render_block(&block, quotes, &mut out);
+                        blank(&mut out, quotes);
+                    }
+                }
+                Tag::Emphasis => styles.italic -= 1,
+                Tag::Strong => styles.bold -= 1,
+                Tag::Strikethrough => styles.strike -= 1,
+                Tag::Link(..) | Tag::Image(..) => {
+                    styles.link -= 1;
+                    if let Some(Some(url)) = links.pop() {
+                        line.push_str(&format!(" ({})", url).dimmed().to_string());
+                    }
+                }
+                _ => (),
+            },
+            Event::Text(body) => match block.as_mut() {
+                Some(block) => block.source.push_str(&body),
+                None => line.push_str(&styles.apply(&body)),
+            },
+            Event::Html(html) => {
+                if let Some(target) = include_target(&html) {
+                    flush(&mut out, &mut line, quotes);
+                    out.push_str(&format!("{}{}\n", quote_prefix(quotes), format!("⤷ include {}", target).dimmed()));
+                    blank(&mut out, quotes);
+                }
+            }
+            Event::Code(code) => line.push_str(&code.yellow().to_string()),
+            Event::SoftBreak => line.push(' '),
+            Event::HardBreak => flush(&mut out, &mut line, quotes),
+            Event::Rule => {
+                flush(&mut out, &mut line, quotes);
+                out.push_str(&format!("{}\n", "─".repeat(40).dimmed()));
+                blank(&mut out, quotes);
+            }
+            _ => (),
+        }
+    }
+    flush(&mut out, &mut line, quotes);
+    out.truncate(out.trim_end().len());
+    out.push('\n');
+    out
+}
+
+fn render_block(block: &Block, quotes: usize, out: &mut String) {
+    let gutter = "│".dimmed();
+    if !block.lang.is_empty() {
+        out.push_str(&format!("{}     {}\n", quote_prefix(quotes), block.lang.dimmed()));
+    }
+    for (index, source) in block.source.lines().enumerate() {
+        let marker = match block.number {
+            Some(number) if index == 0 => format!("{:>4}", format!("[{}]", number)).green().bold().to_string(),
+            _ => String::from("    "),
+        };
+        out.push_str(&format!("{}{} {} {}\n", quote_prefix(quotes), marker, gutter, highlight_line(&block.lang, source)));
+    }
+}
+
+fn quote_prefix(quotes: usize) -> String {
+    if quotes == 0 {
+        return String::new();
+    }
+    "│ ".repeat(quotes).dimmed().to_string()
+}
+
+fn flush(out: &mut String, line: &mut String, quotes: usize) {
+    if !line.trim().is_empty() {
+        out.push_str(&format!("{}{}\n", quote_prefix(quotes), line));
+    }
+    line.clear();
+}
+
+// one empty line between blocks, however many of them end at once
+fn blank(out: &mut String, quotes: usize) {
+    let prefix = quote_prefix(quotes);
+    if !out.is_empty() && !out.ends_with(&format!("\n{}\n", prefix)) {
+        out.push_str(&format!("{}\n", prefix));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::rmd::Rmd;
+    use crate::rmd::view::render;
+
+    const DOC: &str = "---
+shell: bash
+---
+# Deploy
+
+Ships the **current** build, see [the guide](https://example.com/guide).
+
+* build first
+* then `deploy`
+
+```sh
+make build
+```
+
+```json
+{\"replicas\": 3}
+```
+
+```file path=app.conf
+port = 80
+```
+
+```python
+print('deployed')
+```
+";
+
+    // the rendering as it reads, whether or not colors are on
+    fn plain(text: &str) -> String {
+        let mut out = String::new();
+        let mut chars = text.chars();
+        while let Some(c) = chars.next() {
+            if c == '\u{1b}' {
+                chars.by_ref().find(|c| *c == 'm');
+            } else {
+                out.push(c);
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn should_number_executable_blocks() {
+        let commands = Rmd::new(String::from(DOC)).parse();
+        let view = plain(&render(DOC, "", &commands));
+
+        assert!(view.contains(" [1] │ make build\n"));
+        assert!(view.contains("     │ {\"replicas\": 3}\n"));
+        assert!(view.contains("     │ port = 80\n"));
+        assert!(view.contains(" [2] │ print('deployed')\n"));
+        assert!(!view.contains("[3]"));
+    }
+
+    #[test]
+    fn should_render_inline_markup() {
+        let view = plain(&render(DOC, "", &[]));
+
+        assert!(view.starts_with("Deploy\n\nShips the current build, see the guide (https://example.com/guide).\n\n"));
+        assert!(view.contains("• build first\n• then deploy\n\n"));
+        assert!(!view.contains("shell: bash"));
+    }
+}
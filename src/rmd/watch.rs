@@ -0,0 +1,247 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::Result;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use colored::*;
+use tempfile::TempDir;
+
+use crate::rmd::command::{Command, Fixture};
+use crate::rmd::document;
+use crate::rmd::executor::RunContext;
+use crate::rmd::log;
+use crate::rmd::parser::Rmd;
+use crate::rmd::runner;
+use crate::rmd::secrets;
+use crate::rmd::workdir;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+// walked for changes under a `cwd=` directory, but never inside these
+const IGNORED_DIRS: [&str; 3] = [".git", "node_modules", "target"];
+
+static STOP: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_interrupt(_: libc::c_int) {
+    STOP.store(true, Ordering::SeqCst);
+}
+
+// What the last run left behind, so the next one only re-runs what it has to.
+struct LastRun {
+    fingerprints: Vec<String>,
+    fixtures: Vec<(String, String)>,
+    // by block index: whether the block failed or was skipped, and so has to run again
+    unsettled: Vec<bool>,
+    ctx: RunContext,
+    workdir: Option<TempDir>,
+}
+
+// Polls the document, the runbooks it includes and every `cwd=` directory it names and re-runs the document when they change.
+// Only the blocks from the first one that changed, failed or was skipped onwards run again; the
+// ones before keep their outputs. The run's own writes are not seen as changes.
+pub fn watch(path: &str, keep_workdir: bool, interpreters: &HashMap<String, String>) -> Result<()> {
+    unsafe {
+        libc::signal(libc::SIGINT, on_interrupt as extern "C" fn(libc::c_int) as libc::sighandler_t);
+    }
+
+    let mut last: Option<LastRun> = None;
+    let mut watched: Vec<PathBuf> = vec![PathBuf::from(path)];
+    let mut snapshots: Option<Vec<Snapshot>> = None;
+    while !STOP.load(Ordering::SeqCst) {
+        let current: Vec<Snapshot> = watched.iter().map(|root| snapshot(root)).collect();
+        let changed: Vec<&PathBuf> = match &snapshots {
+            None => watched.iter().collect(),
+            Some(previous) => watched.iter().zip(previous.iter().zip(&current))
+                .filter(|(_, (before, now))| before != now)
+                .map(|(root, _)| root)
+                .collect(),
+        };
+        if changed.is_empty() {
+            thread::sleep(POLL_INTERVAL);
+            continue;
+        }
+
+        // clear the screen and move the cursor home
+        if !log::is_json() {
+            eprint!("\x1b[2J\x1b[H");
+        }
+        let started = Instant::now();
+        match document::read(path) {
+            Ok(text) => {
+                let changed_dirs: Vec<PathBuf> = changed.into_iter().filter(|root| root.as_path() != Path::new(path)).cloned().collect();
+                let (run, roots) = run_once(path, text, last.take(), &changed_dirs, interpreters);
+                last = Some(run);
+                watched = roots;
+            }
+            Err(err) => log::error("watch", &format!("cannot read {}: {}", path, err)),
+        }
+        let finished = format!("finished in {:.1?}, watching {} for changes (ctrl-c to stop)", started.elapsed(), path);
+        log::status("watch", &finished, finished.dimmed());
+
+        // taken after the run, so files the blocks wrote do not start another one
+        snapshots = Some(watched.iter().map(|root| snapshot(root)).collect());
+    }
+
+    if let Some(workdir) = last.and_then(|last| last.workdir) {
+        if keep_workdir {
+            let kept = workdir.into_path().display().to_string();
+            log::status("watch", &format!("workdir kept at {}", kept), format!("{} {}", "workdir kept at".green(), kept));
+        }
+    }
+    Ok(())
+}
+
+fn run_once(path: &str, text: String, last: Option<LastRun>, changed_dirs: &[PathBuf],
+            interpreters: &HashMap<String, String>) -> (LastRun, Vec<PathBuf>) {
+    let mut parser = Rmd::with_path(text, path.to_string());
+    let commands = parser.parse();
+    for err in parser.errors() {
+        log::error("parse", err);
+    }
+    let fingerprints: Vec<String> = commands.iter().map(fingerprint).collect();
+    let fixtures: Vec<(String, String)> = parser.fixtures().iter().map(|fixture| (fixture.path.clone(), fixture.content.clone())).collect();
+
+    let mut roots = vec![PathBuf::from(path)];
+    for cmd in &commands {
+        // an included runbook is watched like the document itself
+        let file = PathBuf::from(&cmd.script.file);
+        if !cmd.script.file.is_empty() && !roots.contains(&file) {
+            roots.push(file);
+        }
+        if let Some(cwd) = workdir::cwd_of(&cmd.script) {
+            if !roots.contains(&cwd) {
+                roots.push(cwd);
+            }
+        }
+    }
+
+    let first = match &last {
+        Some(last) if last.fixtures == fixtures => first_stale(&commands, &fingerprints, last, changed_dirs),
+        _ => 0,
+    };
+    // a secret is asked for once, not on every change
+    let known = last.as_ref().map(|last| last.ctx.secrets.clone()).unwrap_or_default();
+    let (mut ctx, workdir) = match last {
+        Some(last) if first > 0 => (last.ctx, last.workdir),
+        // a run from the top starts from fresh fixtures, whatever the last one did to them
+        _ => match fresh_workdir(parser.fixtures()) {
+            Ok(workdir) => {
+                let ctx = RunContext {
+                    workdir: workdir.as_ref().map(|dir| dir.path().to_path_buf()),
+                    interpreters: interpreters.clone(),
+                    ..RunContext::default()
+                };
+                (ctx, workdir)
+            }
+            Err(err) => {
+                log::error("run", &err.to_string());
+                (RunContext { interpreters: interpreters.clone(), ..RunContext::default() }, None)
+            }
+        },
+    };
+
+    match secrets::resolve(&commands, &known) {
+        Ok(secrets) => ctx.secrets = secrets,
+        Err(err) => log::error("run", &err.to_string()),
+    }
+
+    let mut unsettled = vec![true; commands.len()];
+    match runner::run_commands_from(commands, first, &mut ctx) {
+        Ok(report) => {
+            unsettled = vec![false; unsettled.len()];
+            for block in &report.blocks {
+                unsettled[block.index] = !block.outcome.is_settled();
+            }
+            if log::enabled(log::Level::Normal) {
+                report.print_compact();
+            }
+        }
+        Err(err) => log::error("run", &err.to_string()),
+    }
+
+    (LastRun { fingerprints, fixtures, unsettled, ctx, workdir }, roots)
+}
+
+fn fresh_workdir(fixtures: &[Fixture]) -> Result<Option<TempDir>> {
+    if fixtures.is_empty() {
+        Ok(None)
+    } else {
+        workdir::create(fixtures).map(Some)
+    }
+}
+
+// the first block that is new, edited, runs in a changed `cwd=` directory, or did not pass last time
+fn first_stale(commands: &[Command], fingerprints: &[String], last: &LastRun, changed_dirs: &[PathBuf]) -> usize {
+    (0..commands.len())
+        .find(|&index| {
+            last.fingerprints.get(index) != Some(&fingerprints[index])
+                || last.unsettled.get(index).copied().unwrap_or(true)
+                || workdir::cwd_of(&commands[index].script).is_some_and(|cwd| changed_dirs.contains(&cwd))
+        })
+        .unwrap_or(commands.len())
+}
+
+fn fingerprint(cmd: &Command) -> String {
+    let attrs: BTreeMap<&String, &String> = cmd.script.attrs.iter().collect();
+    format!("{}\0{:?}\0{}", cmd.script.executor, attrs, cmd.script.source)
+}
+
+type Snapshot = HashMap<PathBuf, SystemTime>;
+
+fn snapshot(root: &Path) -> Snapshot {
+    let mut snapshot = HashMap::new();
+    collect_mtimes(root, &mut snapshot);
+    snapshot
+}
+
+fn collect_mtimes(path: &Path, snapshot: &mut Snapshot) {
+    let metadata = match fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(_) => return,
+    };
+    if let Ok(modified) = metadata.modified() {
+        snapshot.insert(path.to_path_buf(), modified);
+    }
+    if !metadata.is_dir() {
+        return;
+    }
+
+    if let Ok(entries) = fs::read_dir(path) {
+        for entry in entries.flatten() {
+            let ignored = IGNORED_DIRS.iter().any(|name| entry.file_name() == *name);
+            if !ignored {
+                collect_mtimes(&entry.path(), snapshot);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use std::fs;
+
+    use tempfile::tempdir;
+
+    use crate::rmd::watch::run_once;
+
+    #[test]
+    fn should_only_rerun_blocks_from_the_first_change() {
+        let dir = tempdir().unwrap();
+        let doc = dir.path().join("doc.md");
+        let log = dir.path().join("log");
+        let path = doc.to_str().unwrap();
+        let first = format!("```sh id=one\necho one >> {}\n```\n", log.display());
+        let second = format!("```sh\necho ${{{{ blocks.one.stdout }}}}two >> {}\n```\n", log.display());
+
+        let (last, roots) = run_once(path, format!("{}{}", first, second), None, &[], &HashMap::new());
+        assert_eq!(vec![doc.clone()], roots);
+        assert_eq!("one\ntwo\n", fs::read_to_string(&log).unwrap());
+
+        let edited = second.replace("two", "three");
+        run_once(path, format!("{}{}", first, edited), Some(last), &[], &HashMap::new());
+        assert_eq!("one\ntwo\nthree\n", fs::read_to_string(&log).unwrap());
+    }
+}
@@ -0,0 +1,87 @@
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Component, Path, PathBuf};
+
+use tempfile::TempDir;
+
+use crate::rmd::command::{Fixture, Script};
+
+// Writes the document's ```file blocks into a fresh scratch directory, which then serves as the
+// cwd of every block in the run. Dropping the directory removes it; `TempDir::into_path` keeps it.
+pub fn create(fixtures: &[Fixture]) -> Result<TempDir> {
+    let dir = tempfile::Builder::new().prefix("rinput-workdir").tempdir()?;
+    for fixture in fixtures {
+        let path = dir.path().join(relative_path(fixture)?);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, &fixture.content)?;
+    }
+
+    Ok(dir)
+}
+
+// `cwd=dir` runs a block in a directory of its own instead, relative to the document
+pub fn cwd_of(script: &Script) -> Option<PathBuf> {
+    let cwd = script.attr("cwd")?;
+    let doc_dir = Path::new(&script.file).parent().unwrap_or_else(|| Path::new(""));
+    Some(doc_dir.join(cwd))
+}
+
+// a fixture may only name a file below the working directory
+pub fn relative_path(fixture: &Fixture) -> Result<PathBuf> {
+    let path = Path::new(&fixture.path);
+    let escapes = path.components()
+        .any(|component| !matches!(component, Component::Normal(_) | Component::CurDir));
+    if fixture.path.is_empty() || escapes {
+        let msg = format!("the file block at line {} needs a relative path= inside the working directory, got `{}`",
+                          fixture.line, fixture.path);
+        return Err(Error::new(ErrorKind::InvalidInput, msg));
+    }
+
+    Ok(path.to_path_buf())
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use crate::rmd::command::{Fixture, Script};
+    use crate::rmd::workdir::{create, cwd_of};
+
+    fn fixture(path: &str, content: &str) -> Fixture {
+        let mut fixture = Fixture::new(String::from(path), 1);
+        fixture.content = String::from(content);
+        fixture
+    }
+
+    #[test]
+    fn should_write_fixtures_into_workdir() {
+        let dir = create(&[fixture("config.toml", "debug = true\n"), fixture("data/input.csv", "a,b\n")]).unwrap();
+
+        assert_eq!("debug = true\n", fs::read_to_string(dir.path().join("config.toml")).unwrap());
+        assert_eq!("a,b\n", fs::read_to_string(dir.path().join("data/input.csv")).unwrap());
+
+        let path = dir.path().to_path_buf();
+        drop(dir);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn should_reject_paths_outside_workdir() {
+        assert!(create(&[fixture("../escape.txt", "")]).is_err());
+        assert!(create(&[fixture("/etc/passwd", "")]).is_err());
+        assert!(create(&[fixture("", "")]).is_err());
+    }
+
+    #[test]
+    fn should_resolve_cwd_against_document() {
+        let mut script = Script::new();
+        script.file = String::from("docs/tutorial.md");
+        assert_eq!(None, cwd_of(&script));
+
+        script.attrs.insert(String::from("cwd"), String::from("../examples"));
+        assert_eq!(Some(PathBuf::from("docs/../examples")), cwd_of(&script));
+    }
+}